@@ -0,0 +1,277 @@
+//! Load test of the day 19 chat rooms: serves the router in-process,
+//! connects many clients across many rooms, has them tweet at a fixed rate
+//! and reports delivery latency, dropped tweets and the view count.
+//!
+//! ```sh
+//! cargo bench --bench chat_load -- --users 2000 --rooms 100 --rate 1000 --duration 20
+//! ```
+//!
+//! Thousands of clients need as many file descriptors, twice: raise
+//! `ulimit -n` first.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use cch23_wolfboyyang::{
+    challenge::{db::MyState, ws::Sockets},
+    config::Config,
+    router,
+};
+use futures::{
+    stream::{self, SplitSink},
+    SinkExt, StreamExt,
+};
+use serde_json::{json, Value};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::Mutex as AsyncMutex,
+    time::{interval, timeout, Instant, MissedTickBehavior},
+};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+type Sink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
+
+/// Clients connecting at the same time.
+const CONNECTING: usize = 100;
+
+/// How long deliveries may trail the last tweet.
+const DRAIN: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+struct Options {
+    users: usize,
+    rooms: usize,
+    /// Tweets per second, over every room.
+    rate: u64,
+    duration: Duration,
+}
+
+impl Options {
+    fn parse() -> Result<Self, String> {
+        let mut options = Self {
+            users: 1000,
+            rooms: 50,
+            rate: 500,
+            duration: Duration::from_secs(10),
+        };
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            // passed by `cargo bench`
+            if arg == "--bench" {
+                continue;
+            }
+            let value = args.next().ok_or(format!("{arg} needs a value"))?;
+            let number = |value: &str| {
+                value
+                    .parse::<u64>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or(format!("{arg} must be a positive integer, not {value}"))
+            };
+            match arg.as_str() {
+                "--users" => options.users = number(&value)? as usize,
+                "--rooms" => options.rooms = number(&value)? as usize,
+                "--rate" => options.rate = number(&value)?,
+                "--duration" => options.duration = Duration::from_secs(number(&value)?),
+                _ => return Err(format!("unknown option {arg}")),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// What the clients received, shared by their readers.
+#[derive(Default)]
+struct Received {
+    tweets: AtomicU64,
+    /// Microseconds from sending a tweet to receiving it.
+    latencies: Mutex<Vec<u64>>,
+}
+
+struct Client {
+    room: usize,
+    sink: AsyncMutex<Sink>,
+}
+
+async fn serve() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut config = Config::default();
+    // the simulated users connect by path, without logging in
+    config.chat.require_token = false;
+    let app = router(MyState::memory(), Sockets::default(), &config);
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    addr
+}
+
+/// Connect user `i`, and read what they receive until the connection ends.
+async fn connect(
+    addr: SocketAddr,
+    i: usize,
+    rooms: usize,
+    start: Instant,
+    received: Arc<Received>,
+) -> Result<Client, String> {
+    let room = i % rooms;
+    let url = format!("ws://{addr}/19/ws/room/{room}/user/user{i}");
+    let (ws, _) = connect_async(url)
+        .await
+        .map_err(|e| format!("user{i} failed to connect: {e}"))?;
+    let (sink, mut stream) = ws.split();
+
+    // the join of the user is its first frame, once it is subscribed to the room
+    stream
+        .next()
+        .await
+        .ok_or(format!("user{i} was disconnected"))?
+        .map_err(|e| format!("user{i} was disconnected: {e}"))?;
+    tokio::spawn(async move {
+        while let Some(Ok(message)) = stream.next().await {
+            let Message::Text(text) = message else {
+                continue;
+            };
+            let Ok(frame) = serde_json::from_str::<Value>(&text) else {
+                continue;
+            };
+            if frame["type"] != "tweet" {
+                continue;
+            }
+            // tweets carry the microseconds since the start they were sent at
+            let Some(sent) = frame["message"]
+                .as_str()
+                .and_then(|m| m.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            let now = start.elapsed().as_micros() as u64;
+            received.tweets.fetch_add(1, Ordering::Relaxed);
+            received
+                .latencies
+                .lock()
+                .unwrap()
+                .push(now.saturating_sub(sent));
+        }
+    });
+
+    Ok(Client {
+        room,
+        sink: AsyncMutex::new(sink),
+    })
+}
+
+fn percentile(sorted: &[u64], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let index = ((sorted.len() - 1) as f64 * p).round() as usize;
+    Duration::from_micros(sorted[index])
+}
+
+#[tokio::main]
+async fn main() {
+    let options = match Options::parse() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{e}");
+            eprintln!("usage: chat_load [--users N] [--rooms N] [--rate TWEETS_PER_SEC] [--duration SECS]");
+            std::process::exit(2);
+        }
+    };
+    println!("{options:?}");
+
+    let addr = serve().await;
+    let start = Instant::now();
+    let received = Arc::new(Received::default());
+    let clients: Vec<Client> = stream::iter(0..options.users)
+        .map(|i| connect(addr, i, options.rooms, start, received.clone()))
+        .buffer_unordered(CONNECTING)
+        .filter_map(|client| async move { client.map_err(|e| eprintln!("{e}")).ok() })
+        .collect()
+        .await;
+    println!(
+        "connected {} users in {:.2?}",
+        clients.len(),
+        start.elapsed()
+    );
+    if clients.is_empty() {
+        return;
+    }
+
+    let mut members = HashMap::<usize, u64>::new();
+    for client in &clients {
+        *members.entry(client.room).or_default() += 1;
+    }
+
+    // tweet from the users in turn
+    let mut ticks = interval(Duration::from_secs(1) / options.rate as u32);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Burst);
+    let sending = Instant::now();
+    let (mut sent, mut expected, mut failed) = (0u64, 0u64, 0u64);
+    while sending.elapsed() < options.duration {
+        ticks.tick().await;
+        let client = &clients[sent as usize % clients.len()];
+        let at = start.elapsed().as_micros().to_string();
+        let frame = json!({"type": "tweet", "message": at}).to_string();
+        sent += 1;
+        if client
+            .sink
+            .lock()
+            .await
+            .send(Message::Text(frame))
+            .await
+            .is_err()
+        {
+            failed += 1;
+            continue;
+        }
+        expected += members[&client.room];
+    }
+    let elapsed = sending.elapsed();
+    tokio::time::sleep(DRAIN).await;
+
+    let views = timeout(
+        Duration::from_secs(10),
+        reqwest::get(format!("http://{addr}/19/views")),
+    )
+    .await
+    .ok()
+    .and_then(Result::ok);
+    let views = match views {
+        Some(response) => response.text().await.ok(),
+        None => None,
+    };
+    for client in &clients {
+        let _ = client.sink.lock().await.close().await;
+    }
+
+    let delivered = received.tweets.load(Ordering::Relaxed);
+    let mut latencies = std::mem::take(&mut *received.latencies.lock().unwrap());
+    latencies.sort_unstable();
+
+    println!(
+        "sent {sent} tweets in {elapsed:.2?} ({:.0}/s), {failed} failed",
+        sent as f64 / elapsed.as_secs_f64()
+    );
+    println!(
+        "delivered {delivered} of {expected} expected, {} dropped",
+        expected.saturating_sub(delivered)
+    );
+    println!(
+        "latency p50 {:.2?}, p90 {:.2?}, p99 {:.2?}, max {:.2?}",
+        percentile(&latencies, 0.5),
+        percentile(&latencies, 0.9),
+        percentile(&latencies, 0.99),
+        percentile(&latencies, 1.0)
+    );
+    match views {
+        Some(views) => println!("/19/views {views}, {expected} expected"),
+        None => println!("/19/views could not be read"),
+    }
+}
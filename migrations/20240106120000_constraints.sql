@@ -0,0 +1,36 @@
+-- Rows that cannot satisfy the new constraints are moved aside, to be fixed
+-- and inserted again by hand, rather than dropped.
+CREATE TABLE regions_quarantine (LIKE regions);
+CREATE TABLE orders_quarantine (LIKE orders);
+
+WITH moved AS (
+  DELETE FROM regions WHERE name IS NULL RETURNING *
+)
+INSERT INTO regions_quarantine SELECT * FROM moved;
+
+WITH moved AS (
+  DELETE FROM orders
+  WHERE region_id IS NULL
+    OR gift_name IS NULL
+    OR quantity IS NULL
+    OR quantity <= 0
+    OR region_id NOT IN (SELECT id FROM regions)
+  RETURNING *
+)
+INSERT INTO orders_quarantine SELECT * FROM moved;
+
+ALTER TABLE regions
+  ALTER COLUMN name SET NOT NULL;
+
+ALTER TABLE orders
+  ALTER COLUMN region_id SET NOT NULL,
+  ALTER COLUMN gift_name SET NOT NULL,
+  ALTER COLUMN quantity SET NOT NULL,
+  ADD CONSTRAINT orders_region_id_fkey FOREIGN KEY (region_id) REFERENCES regions (id),
+  ADD CONSTRAINT orders_quantity_check CHECK (quantity > 0);
+
+-- /13/orders/popular and /18/regions/top_list group orders by gift
+CREATE INDEX orders_gift_name_idx ON orders (gift_name) INCLUDE (quantity);
+-- /18/regions/total and /18/regions/top_list join orders to their region
+CREATE INDEX orders_region_id_idx ON orders (region_id, gift_name) INCLUDE (quantity);
+CREATE INDEX regions_name_idx ON regions (name);
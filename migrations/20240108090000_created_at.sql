@@ -0,0 +1,6 @@
+-- Existing orders get the time of the migration.
+ALTER TABLE orders
+  ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+-- /api/analytics filters and buckets orders by creation time
+CREATE INDEX orders_created_at_idx ON orders (created_at) INCLUDE (region_id, gift_name, quantity);
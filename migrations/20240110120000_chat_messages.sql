@@ -0,0 +1,10 @@
+CREATE TABLE chat_messages (
+  id BIGSERIAL PRIMARY KEY,
+  room BIGINT NOT NULL,
+  author TEXT NOT NULL,
+  message TEXT NOT NULL,
+  sent_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- /19/rooms/:room/messages and history replay page through a room by id
+CREATE INDEX chat_messages_room_id_idx ON chat_messages (room, id);
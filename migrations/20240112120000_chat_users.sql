@@ -0,0 +1,6 @@
+-- users log in to /19/login with the password of their first login
+CREATE TABLE chat_users (
+  name TEXT PRIMARY KEY,
+  password_hash TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
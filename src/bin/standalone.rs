@@ -0,0 +1,57 @@
+//! Run the service without the Shuttle runtime.
+//!
+//! ```sh
+//! cargo run --features standalone --bin standalone -- cch23.toml
+//! ```
+
+use std::path::PathBuf;
+
+use cch23_wolfboyyang::{
+    challenge::{db::MyState, ws::Sockets},
+    config::{Config, StoreBackend},
+    router, shutdown_signal,
+};
+use sqlx::postgres::PgPoolOptions;
+use tokio::net::TcpListener;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()),
+        )
+        .init();
+
+    let path = std::env::args_os()
+        .nth(1)
+        .or_else(|| std::env::var_os("CCH23_CONFIG"))
+        .map(PathBuf::from);
+    let config = Config::load(path.as_deref())?;
+
+    let state = match config.store {
+        StoreBackend::Postgres => {
+            let database_url = config
+                .database_url
+                .as_deref()
+                .ok_or("database_url is not configured")?;
+            let pool = PgPoolOptions::new().connect(database_url).await?;
+            sqlx::migrate!().run(&pool).await?;
+            MyState::postgres(pool)
+        }
+        StoreBackend::Memory => MyState::memory(),
+    };
+    let sockets = Sockets::new(&config.websocket);
+    let app = router(state, sockets.clone(), &config);
+
+    let listener = TcpListener::bind(config.listen).await?;
+    tracing::info!("listening on {}", listener.local_addr()?);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move {
+            shutdown_signal().await;
+            // upgraded connections are not waited for, so close them first
+            sockets.shutdown().await;
+        })
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,486 @@
+use axum::{extract::State, http::StatusCode, routing::get, Router};
+
+use super::{
+    db::{
+        analytics::{fill_gaps, AnalyticsQuery, Group, Series},
+        list::{OrderQuery, Page, RegionQuery, MAX_LIMIT},
+        MyState, OnConflict, Order, OrderPatch, Region, RegionPatch,
+    },
+    error::AppError,
+    exchange,
+    extract::{Json, Path, Query},
+    feed,
+    ws::Sockets,
+};
+
+/// REST resources for the orders and regions of days 13 and 18.
+pub fn task(state: MyState, sockets: Sockets) -> Router {
+    Router::new()
+        .route("/orders", get(list_orders))
+        .route(
+            "/orders/:id",
+            get(get_order)
+                .put(put_order)
+                .patch(patch_order)
+                .delete(delete_order),
+        )
+        .route("/regions", get(list_regions))
+        .route(
+            "/regions/:id",
+            get(get_region)
+                .put(put_region)
+                .patch(patch_region)
+                .delete(delete_region),
+        )
+        .route("/analytics/regions", get(region_analytics))
+        .route("/analytics/gifts", get(gift_analytics))
+        .with_state(state.clone())
+        .merge(exchange::task(state.clone()))
+        .merge(feed::task(state, sockets))
+}
+
+fn check_limit(limit: usize) -> Result<(), AppError> {
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(AppError::BadRequest(format!(
+            "limit must be between 1 and {MAX_LIMIT}"
+        )));
+    }
+    Ok(())
+}
+
+/// Refuse a cursor issued for another sort column, whose key the store
+/// can't compare.
+fn check_cursor(fits: Option<bool>) -> Result<(), AppError> {
+    if fits == Some(false) {
+        return Err(AppError::BadRequest(
+            "cursor was issued for another sort".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn check_id(path: i32, body: i32) -> Result<(), AppError> {
+    if path != body {
+        return Err(AppError::BadRequest(format!(
+            "id {body} of the body does not match the path"
+        )));
+    }
+    Ok(())
+}
+
+/// 201 when the row was created, 200 when it replaced an existing one.
+fn put_status(inserted: u64) -> StatusCode {
+    if inserted > 0 {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    }
+}
+
+async fn list_orders(
+    State(state): State<MyState>,
+    Query(mut query): Query<OrderQuery>,
+) -> Result<Json<Page<Order>>, AppError> {
+    let limit = query.limit;
+    check_limit(limit)?;
+    check_cursor(query.cursor.as_ref().map(|cursor| query.sort.fits(cursor)))?;
+
+    // one more row tells whether there is a next page
+    query.limit += 1;
+    let orders = state.store.list_orders(&query).await?;
+    Ok(Json(Page::new(orders, limit, |order| {
+        query.sort.cursor(order)
+    })))
+}
+
+async fn get_order(
+    State(state): State<MyState>,
+    Path(id): Path<i32>,
+) -> Result<Json<Order>, AppError> {
+    state
+        .store
+        .order(id)
+        .await?
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound(format!("order {id} does not exist")))
+}
+
+async fn put_order(
+    State(state): State<MyState>,
+    Path(id): Path<i32>,
+    Json(order): Json<Order>,
+) -> Result<(StatusCode, Json<Order>), AppError> {
+    check_id(id, order.id)?;
+    let summary = state
+        .store
+        .insert_orders(vec![order], OnConflict::Update)
+        .await?;
+    // the store keeps the creation time of a replaced order
+    let order = state
+        .store
+        .order(id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("order {id} does not exist")))?;
+
+    Ok((put_status(summary.inserted), Json(order)))
+}
+
+async fn patch_order(
+    State(state): State<MyState>,
+    Path(id): Path<i32>,
+    Json(patch): Json<OrderPatch>,
+) -> Result<Json<Order>, AppError> {
+    Ok(Json(state.store.update_order(id, patch).await?))
+}
+
+async fn delete_order(
+    State(state): State<MyState>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, AppError> {
+    state.store.delete_order(id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_regions(
+    State(state): State<MyState>,
+    Query(mut query): Query<RegionQuery>,
+) -> Result<Json<Page<Region>>, AppError> {
+    let limit = query.limit;
+    check_limit(limit)?;
+    check_cursor(query.cursor.as_ref().map(|cursor| query.sort.fits(cursor)))?;
+
+    // one more row tells whether there is a next page
+    query.limit += 1;
+    let regions = state.store.list_regions(&query).await?;
+    Ok(Json(Page::new(regions, limit, |region| {
+        query.sort.cursor(region)
+    })))
+}
+
+async fn get_region(
+    State(state): State<MyState>,
+    Path(id): Path<i32>,
+) -> Result<Json<Region>, AppError> {
+    state
+        .store
+        .region(id)
+        .await?
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound(format!("region {id} does not exist")))
+}
+
+async fn put_region(
+    State(state): State<MyState>,
+    Path(id): Path<i32>,
+    Json(region): Json<Region>,
+) -> Result<(StatusCode, Json<Region>), AppError> {
+    check_id(id, region.id)?;
+    let summary = state
+        .store
+        .insert_regions(vec![region.clone()], OnConflict::Update)
+        .await?;
+
+    Ok((put_status(summary.inserted), Json(region)))
+}
+
+async fn patch_region(
+    State(state): State<MyState>,
+    Path(id): Path<i32>,
+    Json(patch): Json<RegionPatch>,
+) -> Result<Json<Region>, AppError> {
+    Ok(Json(state.store.update_region(id, patch).await?))
+}
+
+async fn delete_region(
+    State(state): State<MyState>,
+    Path(id): Path<i32>,
+) -> Result<StatusCode, AppError> {
+    state.store.delete_region(id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn region_analytics(
+    State(state): State<MyState>,
+    Query(query): Query<AnalyticsQuery>,
+) -> Result<Json<Vec<Series>>, AppError> {
+    analytics(state, Group::Region, query).await
+}
+
+async fn gift_analytics(
+    State(state): State<MyState>,
+    Query(query): Query<AnalyticsQuery>,
+) -> Result<Json<Vec<Series>>, AppError> {
+    analytics(state, Group::Gift, query).await
+}
+
+async fn analytics(
+    state: MyState,
+    group: Group,
+    query: AnalyticsQuery,
+) -> Result<Json<Vec<Series>>, AppError> {
+    if query.from > query.to {
+        return Err(AppError::BadRequest("`from` is after `to`".to_string()));
+    }
+    let starts = query.bucket.range(query.from, query.to)?;
+    let totals = state.store.bucket_totals(group, &query).await?;
+
+    Ok(Json(fill_gaps(totals, &starts)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenge::error::assert_problem;
+    use axum_test::TestServer;
+    use serde_json::{json, Value};
+
+    async fn seed(server: &TestServer) {
+        for (id, name) in [(1, "North Pole"), (2, "Europe"), (3, "Asia")] {
+            let response = server
+                .put(&format!("/regions/{id}"))
+                .json(&json!({"id": id, "name": name}))
+                .await;
+            response.assert_status(StatusCode::CREATED);
+        }
+
+        let orders = [
+            (1, 2, "Toy Train", 5, "2024-01-01T10:00:00Z"),
+            (2, 2, "Doll", 8, "2024-01-02T12:00:00Z"),
+            (3, 3, "Action Figure", 12, "2024-01-02T23:59:59Z"),
+            (4, 1, "Board Game", 10, "2024-01-08T00:00:00Z"),
+            (5, 2, "Teddy Bear", 6, "2024-01-09T08:30:00Z"),
+            (6, 3, "Toy Train", 3, "2024-01-15T17:00:00Z"),
+        ];
+        for (id, region_id, gift_name, quantity, created_at) in orders {
+            let response = server
+                .put(&format!("/orders/{id}"))
+                .json(&json!({
+                    "id": id,
+                    "region_id": region_id,
+                    "gift_name": gift_name,
+                    "quantity": quantity,
+                    "created_at": created_at
+                }))
+                .await;
+            response.assert_status(StatusCode::CREATED);
+        }
+    }
+
+    fn ids(page: &Value) -> Vec<i64> {
+        page["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["id"].as_i64().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn crud() {
+        let app = task(MyState::memory(), Sockets::default());
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+        seed(&server).await;
+
+        // Send the request.
+        let response = server.get("/orders/2").await;
+        response.assert_status(StatusCode::OK);
+        response.assert_json(&json!({
+            "id": 2,
+            "region_id": 2,
+            "gift_name": "Doll",
+            "quantity": 8,
+            "created_at": "2024-01-02T12:00:00Z"
+        }));
+
+        let response = server
+            .put("/orders/2")
+            .json(&json!({
+                "id": 2,
+                "region_id": 3,
+                "gift_name": "Doll",
+                "quantity": 9,
+                "created_at": "2030-01-01T00:00:00Z"
+            }))
+            .await;
+        response.assert_status(StatusCode::OK);
+        assert_eq!(
+            response.json::<Value>()["created_at"],
+            "2024-01-02T12:00:00Z"
+        );
+
+        let response = server
+            .patch("/orders/2")
+            .json(&json!({"quantity": 1}))
+            .await;
+        response.assert_status(StatusCode::OK);
+        // replacing an order keeps its creation time
+        response.assert_json(&json!({
+            "id": 2,
+            "region_id": 3,
+            "gift_name": "Doll",
+            "quantity": 1,
+            "created_at": "2024-01-02T12:00:00Z"
+        }));
+
+        let response = server
+            .patch("/orders/2")
+            .json(&json!({"region_id": 9}))
+            .await;
+        assert_problem(&response, StatusCode::UNPROCESSABLE_ENTITY, "unprocessable");
+
+        let response = server
+            .put("/orders/3")
+            .json(&json!({"id":4,"region_id":3,"gift_name":"Doll","quantity":9}))
+            .await;
+        assert_problem(&response, StatusCode::BAD_REQUEST, "bad_request");
+
+        let response = server.delete("/orders/2").await;
+        response.assert_status(StatusCode::NO_CONTENT);
+        let response = server.get("/orders/2").await;
+        assert_problem(&response, StatusCode::NOT_FOUND, "not_found");
+        let response = server.delete("/orders/2").await;
+        assert_problem(&response, StatusCode::NOT_FOUND, "not_found");
+
+        let response = server
+            .patch("/regions/1")
+            .json(&json!({"name": "Santa's Village"}))
+            .await;
+        response.assert_json(&json!({"id":1,"name":"Santa's Village"}));
+
+        // region 1 still has an order
+        let response = server.delete("/regions/1").await;
+        assert_problem(&response, StatusCode::CONFLICT, "conflict");
+        let response = server.delete("/orders/4").await;
+        response.assert_status(StatusCode::NO_CONTENT);
+        let response = server.delete("/regions/1").await;
+        response.assert_status(StatusCode::NO_CONTENT);
+        let response = server.get("/regions/1").await;
+        assert_problem(&response, StatusCode::NOT_FOUND, "not_found");
+    }
+
+    #[tokio::test]
+    async fn list() {
+        let app = task(MyState::memory(), Sockets::default());
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+        seed(&server).await;
+
+        // Send the request.
+        let response = server
+            .get("/orders")
+            .add_query_param("region_id", 2)
+            .add_query_param("min_quantity", 6)
+            .await;
+        response.assert_status(StatusCode::OK);
+        let page = response.json::<Value>();
+        assert_eq!(ids(&page), vec![2, 5]);
+        assert_eq!(page["next_cursor"], Value::Null);
+
+        let response = server
+            .get("/orders")
+            .add_query_param("gift_name", "Toy Train")
+            .await;
+        assert_eq!(ids(&response.json::<Value>()), vec![1, 6]);
+
+        // walk all orders by descending quantity, two at a time
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let mut request = server
+                .get("/orders")
+                .add_query_param("sort", "quantity")
+                .add_query_param("order", "desc")
+                .add_query_param("limit", 2);
+            if let Some(cursor) = &cursor {
+                request = request.add_query_param("cursor", cursor);
+            }
+            let page = request.await.json::<Value>();
+            seen.extend(ids(&page));
+            match page["next_cursor"].as_str() {
+                Some(next) => cursor = Some(next.to_string()),
+                None => break,
+            }
+        }
+        assert_eq!(seen, vec![3, 4, 2, 5, 1, 6]);
+
+        let response = server.get("/regions").add_query_param("sort", "name").await;
+        assert_eq!(ids(&response.json::<Value>()), vec![3, 2, 1]);
+
+        let response = server.get("/orders").add_query_param("limit", 0).await;
+        assert_problem(&response, StatusCode::BAD_REQUEST, "bad_request");
+        let response = server
+            .get("/orders")
+            .add_query_param("cursor", "garbage")
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        // a cursor only continues the sort it was issued for
+        let page = server
+            .get("/orders")
+            .add_query_param("sort", "quantity")
+            .add_query_param("limit", 1)
+            .await
+            .json::<Value>();
+        let response = server
+            .get("/orders")
+            .add_query_param("sort", "gift_name")
+            .add_query_param("cursor", page["next_cursor"].as_str().unwrap())
+            .await;
+        assert_problem(&response, StatusCode::BAD_REQUEST, "bad_request");
+    }
+
+    #[tokio::test]
+    async fn bucketed_analytics() {
+        let app = task(MyState::memory(), Sockets::default());
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+        seed(&server).await;
+
+        // Send the request.
+        let response = server
+            .get("/analytics/regions")
+            .add_query_param("from", "2024-01-01")
+            .add_query_param("to", "2024-01-03")
+            .await;
+        response.assert_status(StatusCode::OK);
+        response.assert_json(&json!([
+            {"name": "Asia", "buckets": [
+                {"start": "2024-01-01", "total": 0},
+                {"start": "2024-01-02", "total": 12},
+                {"start": "2024-01-03", "total": 0}
+            ]},
+            {"name": "Europe", "buckets": [
+                {"start": "2024-01-01", "total": 5},
+                {"start": "2024-01-02", "total": 8},
+                {"start": "2024-01-03", "total": 0}
+            ]}
+        ]));
+
+        let response = server
+            .get("/analytics/gifts")
+            .add_query_param("from", "2024-01-03")
+            .add_query_param("to", "2024-01-31")
+            .add_query_param("bucket", "week")
+            .await;
+        let series = response.json::<Vec<Series>>();
+        let toy_train = series.iter().find(|s| s.name == "Toy Train").unwrap();
+        let totals = toy_train
+            .buckets
+            .iter()
+            .map(|point| point.total)
+            .collect::<Vec<_>>();
+        // the first week starts before `from` but only counts orders after it
+        assert_eq!(totals, vec![0, 0, 3, 0, 0]);
+
+        let response = server
+            .get("/analytics/gifts")
+            .add_query_param("from", "2024-02-01")
+            .add_query_param("to", "2024-01-01")
+            .await;
+        assert_problem(&response, StatusCode::BAD_REQUEST, "bad_request");
+    }
+}
@@ -1,21 +1,27 @@
-use axum::{extract::Path, http::StatusCode, response::IntoResponse, routing::get, Router};
+use axum::{http::StatusCode, response::IntoResponse, routing::get, Router};
+
+use super::{error::AppError, extract::Path};
 
 pub fn task() -> Router {
     Router::new().route("/*path", get(cube_the_bits))
 }
 
-async fn cube_the_bits(Path(path): Path<String>) -> impl IntoResponse {
+async fn cube_the_bits(Path(path): Path<String>) -> Result<impl IntoResponse, AppError> {
     let cube_bits = path
         .split('/')
-        .map(|s| s.parse::<i32>().unwrap())
-        .fold(0, |acc, x| acc ^ x);
+        .map(|s| {
+            s.parse::<i32>()
+                .map_err(|_| AppError::InvalidInteger(s.to_string()))
+        })
+        .try_fold(0, |acc, x| x.map(|x| acc ^ x))?;
     let sled_id = cube_bits.pow(3);
-    (StatusCode::OK, sled_id.to_string())
+    Ok((StatusCode::OK, sled_id.to_string()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::challenge::error::assert_problem;
     use axum::http::StatusCode;
     use axum_test::TestServer;
 
@@ -48,4 +54,17 @@ mod tests {
 
         response.assert_text(27.to_string());
     }
+
+    #[tokio::test]
+    async fn invalid_number() {
+        let app = task();
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server.get("/4/eight").await;
+
+        assert_problem(&response, StatusCode::BAD_REQUEST, "invalid_integer");
+    }
 }
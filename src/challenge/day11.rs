@@ -1,43 +1,55 @@
-use axum::{extract::Multipart, routing::post, Router};
+use std::path::Path;
+
+use axum::{routing::post, Router};
 use tower_http::services::ServeDir;
 
-pub fn task() -> Router {
+use super::{error::AppError, extract::Multipart};
+
+pub fn task(assets_dir: impl AsRef<Path>) -> Router {
     Router::new()
-        .nest_service("/assets", ServeDir::new("assets"))
+        .nest_service("/assets", ServeDir::new(assets_dir))
         .route("/red_pixels", post(activate_bull_mode))
 }
 
-async fn activate_bull_mode(mut multipart: Multipart) -> String {
-    while let Some(field) = multipart.next_field().await.unwrap() {
-        let name = field.name().unwrap().to_string();
-        if name != "image" {
+async fn activate_bull_mode(Multipart(mut multipart): Multipart) -> Result<String, AppError> {
+    while let Some(field) = multipart.next_field().await? {
+        if field.name() != Some("image") {
             continue;
         }
-        let data = field.bytes().await.unwrap();
+        let data = field.bytes().await?;
 
         return match lodepng::decode_memory(data, lodepng::ColorType::RGBA, 8) {
-            Ok(lodepng::Image::RGBA(image)) => image
+            Ok(lodepng::Image::RGBA(image)) => Ok(image
                 .buffer
                 .iter()
                 .filter(|pixel| pixel.r as u16 > pixel.g as u16 + pixel.b as u16)
                 .count()
-                .to_string(),
-            Ok(_) => "Decoded image, but it was not RGBA".into(),
-            Err(reason) => format!("Could not load, because: {reason}"),
+                .to_string()),
+            Ok(_) => Err(AppError::InvalidImage(
+                "decoded image, but it was not RGBA".to_string(),
+            )),
+            Err(reason) => Err(AppError::InvalidImage(reason.to_string())),
         };
     }
-    "No image found".into()
+    Err(AppError::BadRequest("no image found".to_string()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::challenge::error::assert_problem;
     use axum::http::StatusCode;
     use axum_test::TestServer;
 
+    fn multipart_body(name: &str, content: &str) -> String {
+        format!(
+            "--BOUNDARY\r\nContent-Disposition: form-data; name=\"{name}\"; filename=\"decoration.png\"\r\n\r\n{content}\r\n--BOUNDARY--\r\n"
+        )
+    }
+
     #[tokio::test]
     async fn task1() {
-        let app = task();
+        let app = task("assets");
 
         // Run the application for testing.
         let server = TestServer::new(app).unwrap();
@@ -61,4 +73,38 @@ mod tests {
 
         assert_eq!(bytes, EXPECTED);
     }
+
+    #[tokio::test]
+    async fn missing_image() {
+        let app = task("assets");
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server
+            .post("/red_pixels")
+            .bytes(multipart_body("name", "decoration").into())
+            .content_type("multipart/form-data; boundary=BOUNDARY")
+            .await;
+
+        assert_problem(&response, StatusCode::BAD_REQUEST, "bad_request");
+    }
+
+    #[tokio::test]
+    async fn invalid_image() {
+        let app = task("assets");
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server
+            .post("/red_pixels")
+            .bytes(multipart_body("image", "definitely not a png").into())
+            .content_type("multipart/form-data; boundary=BOUNDARY")
+            .await;
+
+        assert_problem(&response, StatusCode::UNPROCESSABLE_ENTITY, "invalid_image");
+    }
 }
@@ -1,7 +1,7 @@
 use axum::{
-    extract::{Path, State},
+    extract::State,
     routing::{get, post},
-    Json, Router,
+    Router,
 };
 use chrono::{DateTime, Datelike, Utc};
 use serde::{Deserialize, Serialize};
@@ -10,6 +10,11 @@ use std::{collections::HashMap, sync::Arc};
 use ulid::Ulid;
 use uuid::Uuid;
 
+use super::{
+    error::AppError,
+    extract::{Json, Path},
+};
+
 type SharedState = Arc<std::sync::RwLock<AppState>>;
 
 #[derive(Default)]
@@ -44,27 +49,42 @@ async fn save_data(Path(data): Path<String>, State(state): State<SharedState>) {
     time_capsule.insert(data, Instant::now());
 }
 
-async fn load_data(Path(data): Path<String>, State(state): State<SharedState>) -> String {
+async fn load_data(
+    Path(data): Path<String>,
+    State(state): State<SharedState>,
+) -> Result<String, AppError> {
     let time_capsule = &state.read().unwrap().time_capsule;
-    let time = time_capsule.get(&data).unwrap();
-    (*time).elapsed().as_secs().to_string()
+    let time = time_capsule
+        .get(&data)
+        .ok_or_else(|| AppError::NotFound(format!("packet `{data}` was never saved")))?;
+    Ok((*time).elapsed().as_secs().to_string())
+}
+
+fn parse_ulid(id: &str) -> Result<Ulid, AppError> {
+    Ulid::from_string(id).map_err(|_| AppError::InvalidUlid(id.to_string()))
 }
 
 // Convert all the ULIDs to UUIDs and return a new array but in reverse order.
-async fn convert_ulids(data: Json<Vec<String>>) -> Json<Vec<String>> {
-    let ids: Vec<String> = data
+async fn convert_ulids(data: Json<Vec<String>>) -> Result<Json<Vec<String>>, AppError> {
+    let ids = data
         .iter()
-        .map(|id| Uuid::from(Ulid::from_string(id).unwrap()).to_string())
+        .map(|id| parse_ulid(id).map(|ulid| Uuid::from(ulid).to_string()))
         .rev()
-        .collect();
-    Json(ids)
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Json(ids))
 }
 
-async fn analyze_ulids(Path(weekday): Path<u32>, data: Json<Vec<String>>) -> Json<Report> {
-    let mut lbs_count = 0;
-    let dates: Vec<DateTime<Utc>> = data
+async fn analyze_ulids(
+    Path(weekday): Path<u32>,
+    data: Json<Vec<String>>,
+) -> Result<Json<Report>, AppError> {
+    let ulids = data
         .iter()
-        .map(|id| Ulid::from_string(id).unwrap())
+        .map(|id| parse_ulid(id))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut lbs_count = 0;
+    let dates: Vec<DateTime<Utc>> = ulids
+        .into_iter()
         .inspect(|ulid| lbs_count += (ulid.0 & 1) as u32)
         .map(|ulid| DateTime::<Utc>::from(ulid.datetime()))
         .rev()
@@ -77,19 +97,18 @@ async fn analyze_ulids(Path(weekday): Path<u32>, data: Json<Vec<String>>) -> Jso
             christmas_eve_count += 1;
         }
         if date.weekday().num_days_from_monday() == weekday {
-            print!("{} ", date.weekday());
             weekday_count += 1;
         }
         if date > Utc::now() {
             future_day_count += 1;
         }
     }
-    Json(Report {
+    Ok(Json(Report {
         christmas_eve: christmas_eve_count,
         weekday: weekday_count,
         in_future: future_day_count,
         lbs: lbs_count,
-    })
+    }))
 }
 
 #[cfg(test)]
@@ -97,6 +116,7 @@ mod tests {
     use std::time::Duration;
 
     use super::*;
+    use crate::challenge::error::assert_problem;
     use axum::http::StatusCode;
     use axum_test::TestServer;
     use std::thread::sleep;
@@ -134,4 +154,43 @@ mod tests {
 
         response.assert_text("0");
     }
+
+    #[tokio::test]
+    async fn unknown_packet() {
+        let app = task();
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server.get("/load/packet20231224").await;
+
+        assert_problem(&response, StatusCode::NOT_FOUND, "not_found");
+    }
+
+    #[tokio::test]
+    async fn invalid_ulid() {
+        let app = task();
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server
+            .post("/ulids")
+            .json(&serde_json::json!([
+                "01GJMTFMYDM1K1WW6KZE6R7H3Z",
+                "not-a-ulid"
+            ]))
+            .await;
+
+        assert_problem(&response, StatusCode::BAD_REQUEST, "invalid_ulid");
+
+        let response = server
+            .post("/ulids/5")
+            .json(&serde_json::json!(["not-a-ulid"]))
+            .await;
+
+        assert_problem(&response, StatusCode::BAD_REQUEST, "invalid_ulid");
+    }
 }
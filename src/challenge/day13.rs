@@ -2,10 +2,14 @@ use axum::{
     extract::State,
     response::IntoResponse,
     routing::{get, post},
-    Json, Router,
+    Router,
 };
 
-use super::db::{insert_orders, reset, MyState};
+use super::{
+    db::{insert_orders, reset, MyState},
+    error::AppError,
+    extract::Json,
+};
 
 pub fn task(state: MyState) -> Router {
     Router::new()
@@ -17,39 +21,225 @@ pub fn task(state: MyState) -> Router {
         .with_state(state)
 }
 
-async fn simple_query(State(state): State<MyState>) -> String {
+async fn simple_query(State(state): State<MyState>) -> Result<String, AppError> {
+    let pool = state
+        .pool
+        .ok_or_else(|| AppError::Unavailable("no SQL database is configured".to_string()))?;
     let record = sqlx::query!(r#"select 20231213 as "id!""#)
-        .fetch_one(&state.pool)
-        .await
-        .unwrap();
+        .fetch_one(&pool)
+        .await?;
 
-    record.id.to_string()
+    Ok(record.id.to_string())
 }
 
-async fn orders_total_quantity(State(state): State<MyState>) -> impl IntoResponse {
-    if let Ok(record) = sqlx::query!(r#"SELECT SUM(quantity) as "total!" FROM orders"#)
-        .fetch_one(&state.pool)
-        .await
-    {
-        Json(serde_json::json!({"total": record.total}))
-    } else {
-        Json(serde_json::json!({"total": 0}))
-    }
+async fn orders_total_quantity(
+    State(state): State<MyState>,
+) -> Result<impl IntoResponse, AppError> {
+    let total = state.store.total_quantity().await?;
+
+    Ok(Json(serde_json::json!({ "total": total })))
+}
+
+async fn orders_popular_gift(State(state): State<MyState>) -> Result<impl IntoResponse, AppError> {
+    let popular = state.store.popular_gift().await?;
+
+    Ok(Json(serde_json::json!({ "popular": popular })))
 }
 
-async fn orders_popular_gift(State(state): State<MyState>) -> impl IntoResponse {
-    if let Ok(record) = sqlx::query!(
-        r#"SELECT gift_name as "popular!", SUM(quantity) AS gift_count
-            FROM orders
-            GROUP BY gift_name
-            ORDER BY gift_count DESC
-            LIMIT 1"#
-    )
-    .fetch_one(&state.pool)
-    .await
-    {
-        Json(serde_json::json!({"popular": record.popular}))
-    } else {
-        Json(serde_json::json!({"popular": null}))
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenge::{day18, error::assert_problem};
+    use axum::http::StatusCode;
+    use axum_test::TestServer;
+    use serde_json::json;
+
+    /// Orders need their regions, which only day 18 can create.
+    async fn seed_regions(state: &MyState) {
+        let regions: Vec<_> = (1..=4)
+            .map(|id| json!({"id": id, "name": format!("Region {id}")}))
+            .collect();
+        let server = TestServer::new(day18::task(state.clone())).unwrap();
+        server
+            .post("/regions")
+            .json(&regions)
+            .await
+            .assert_status(StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn task1() {
+        let app = task(MyState::memory());
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server.get("/sql").await;
+
+        assert_problem(&response, StatusCode::SERVICE_UNAVAILABLE, "unavailable");
+    }
+
+    #[tokio::test]
+    async fn task2() {
+        let state = MyState::memory();
+        let app = task(state.clone());
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server.post("/reset").await;
+        response.assert_status(StatusCode::OK);
+        seed_regions(&state).await;
+
+        let response = server
+            .post("/orders")
+            .json(&json!([
+                {"id":1,"region_id":2,"gift_name":"Toy Train","quantity":5},
+                {"id":2,"region_id":2,"gift_name":"Doll","quantity":8},
+                {"id":3,"region_id":3,"gift_name":"Action Figure","quantity":12},
+                {"id":4,"region_id":4,"gift_name":"Board Game","quantity":10},
+                {"id":5,"region_id":2,"gift_name":"Teddy Bear","quantity":6},
+                {"id":6,"region_id":3,"gift_name":"Toy Train","quantity":3}
+            ]))
+            .await;
+        response.assert_status(StatusCode::OK);
+
+        let response = server.get("/orders/total").await;
+        response.assert_status(StatusCode::OK);
+        response.assert_json(&json!({"total": 44}));
+    }
+
+    #[tokio::test]
+    async fn task3() {
+        let state = MyState::memory();
+        seed_regions(&state).await;
+        let app = task(state);
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server.get("/orders/popular").await;
+        response.assert_status(StatusCode::OK);
+        response.assert_json(&json!({"popular": null}));
+
+        let response = server
+            .post("/orders")
+            .json(&json!([
+                {"id":1,"region_id":2,"gift_name":"Toy Train","quantity":5},
+                {"id":2,"region_id":2,"gift_name":"Doll","quantity":8},
+                {"id":3,"region_id":3,"gift_name":"Toy Train","quantity":4}
+            ]))
+            .await;
+        response.assert_status(StatusCode::OK);
+
+        let response = server.get("/orders/popular").await;
+        response.assert_status(StatusCode::OK);
+        response.assert_json(&json!({"popular": "Toy Train"}));
+    }
+
+    #[tokio::test]
+    async fn orders_need_regions() {
+        let server = TestServer::new(task(MyState::memory())).unwrap();
+
+        let response = server
+            .post("/orders")
+            .json(&json!([{"id":1,"region_id":2,"gift_name":"Toy Train","quantity":5}]))
+            .await;
+        assert_problem(&response, StatusCode::UNPROCESSABLE_ENTITY, "unprocessable");
+    }
+
+    #[tokio::test]
+    async fn bulk_insert_on_conflict() {
+        let state = MyState::memory();
+        seed_regions(&state).await;
+        let app = task(state);
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server
+            .post("/orders")
+            .json(&json!([
+                {"id":1,"region_id":2,"gift_name":"Toy Train","quantity":5},
+                {"id":2,"region_id":2,"gift_name":"Doll","quantity":8}
+            ]))
+            .await;
+        response.assert_status(StatusCode::OK);
+        response.assert_json(&json!({"inserted": 2, "updated": 0, "rejected": 0}));
+
+        let batch = json!([
+            {"id":2,"region_id":2,"gift_name":"Doll","quantity":20},
+            {"id":3,"region_id":3,"gift_name":"Toy Train","quantity":4}
+        ]);
+
+        // the whole batch is rejected by default
+        let response = server.post("/orders").json(&batch).await;
+        assert_problem(&response, StatusCode::CONFLICT, "conflict");
+        let response = server.get("/orders/total").await;
+        response.assert_json(&json!({"total": 13}));
+
+        let response = server
+            .post("/orders")
+            .add_query_param("on_conflict", "skip")
+            .json(&batch)
+            .await;
+        response.assert_status(StatusCode::OK);
+        response.assert_json(&json!({"inserted": 1, "updated": 0, "rejected": 1}));
+        let response = server.get("/orders/total").await;
+        response.assert_json(&json!({"total": 17}));
+
+        let response = server
+            .post("/orders")
+            .add_query_param("on_conflict", "update")
+            .json(&batch)
+            .await;
+        response.assert_status(StatusCode::OK);
+        response.assert_json(&json!({"inserted": 0, "updated": 2, "rejected": 0}));
+        let response = server.get("/orders/total").await;
+        response.assert_json(&json!({"total": 29}));
+        let response = server.get("/orders/popular").await;
+        response.assert_json(&json!({"popular": "Doll"}));
+    }
+
+    #[tokio::test]
+    async fn bulk_insert_constraints() {
+        let state = MyState::memory();
+        seed_regions(&state).await;
+        let app = task(state);
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server
+            .post("/orders")
+            .json(&json!([{"id":1,"region_id":2,"gift_name":"Doll","quantity":0}]))
+            .await;
+        assert_problem(&response, StatusCode::UNPROCESSABLE_ENTITY, "unprocessable");
+
+        let batch = json!([
+            {"id":1,"region_id":2,"gift_name":"Doll","quantity":8},
+            {"id":2,"region_id":9,"gift_name":"Toy Train","quantity":5}
+        ]);
+
+        let response = server.post("/orders").json(&batch).await;
+        assert_problem(&response, StatusCode::UNPROCESSABLE_ENTITY, "unprocessable");
+
+        let response = server
+            .post("/orders")
+            .add_query_param("on_conflict", "skip")
+            .json(&batch)
+            .await;
+        response.assert_status(StatusCode::OK);
+        response.assert_json(&json!({"inserted": 1, "updated": 0, "rejected": 1}));
+
+        let response = server.post("/reset").await;
+        response.assert_status(StatusCode::OK);
+        let response = server.get("/orders/total").await;
+        response.assert_json(&json!({"total": 0}));
     }
 }
@@ -1,6 +1,8 @@
-use axum::{response::IntoResponse, routing::post, Json, Router};
+use axum::{response::IntoResponse, routing::post, Router};
 use serde::Deserialize;
 
+use super::extract::Json;
+
 #[derive(Deserialize)]
 struct HtmlContent {
     content: String,
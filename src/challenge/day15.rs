@@ -1,150 +1,158 @@
-use axum::{http::StatusCode, response::IntoResponse, routing::post, Json, Router};
+use axum::{http::StatusCode, response::IntoResponse, routing::post, Router};
 use fancy_regex::Regex;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use sha2::{Digest, Sha256};
 
+use super::{error::AppError, extract::Json};
+
 #[derive(Deserialize, Serialize)]
 struct Report {
     result: String,
     reason: String,
 }
 
+/// A pattern a text must match, or must not match, to be nice.
+pub struct RegexRule {
+    regex: Regex,
+    must_match: bool,
+    reason: String,
+}
+
+impl RegexRule {
+    pub fn require(pattern: &str, reason: &str) -> Result<Self, AppError> {
+        Ok(Self {
+            regex: Regex::new(pattern)?,
+            must_match: true,
+            reason: reason.to_string(),
+        })
+    }
+
+    pub fn forbid(pattern: &str, reason: &str) -> Result<Self, AppError> {
+        Ok(Self {
+            must_match: false,
+            ..Self::require(pattern, reason)?
+        })
+    }
+
+    pub fn passes(&self, text: &str) -> Result<bool, AppError> {
+        Ok(self.regex.is_match(text)? == self.must_match)
+    }
+
+    /// Why a text breaking the rule is naughty.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+fn naughty(status: StatusCode, reason: &str) -> (StatusCode, Json<Report>) {
+    (
+        status,
+        Json(Report {
+            result: "naughty".to_string(),
+            reason: reason.to_string(),
+        }),
+    )
+}
+
 pub fn task() -> Router {
     Router::new()
         .route("/nice", post(check_password))
         .route("/game", post(play_game))
 }
 
-async fn check_password(payload: String) -> impl IntoResponse {
-    if let Ok(payload) = serde_json::from_str::<serde_json::Value>(&payload) {
-        if let Some(input) = payload.get("input") {
-            let text = input.as_str().unwrap();
-            // Rule 1: must contain at least 3 vowels
-            let vowels = Regex::new(r"(.*[aeiouy]){3,}").unwrap();
-            // Rule 2: must contain at least one letter that appears twice in a row
-            let twice = Regex::new(r"([a-z])\1").unwrap();
-            // Rule 3: must not contain ab, cd, pq, or xy
-            let blacklist = Regex::new(r"ab|cd|pq|xy").unwrap();
-            return if vowels.is_match(text).unwrap()
-                && twice.is_match(text).unwrap()
-                && !blacklist.is_match(text).unwrap()
-            {
+async fn check_password(payload: String) -> Result<impl IntoResponse, AppError> {
+    if let Ok(payload) = serde_json::from_str::<Value>(&payload) {
+        if let Some(text) = payload.get("input").and_then(Value::as_str) {
+            let rules = [
+                // Rule 1: must contain at least 3 vowels
+                RegexRule::require(r"(.*[aeiouy]){3,}", "vowels")?,
+                // Rule 2: must contain at least one letter that appears twice in a row
+                RegexRule::require(r"([a-z])\1", "twice")?,
+                // Rule 3: must not contain ab, cd, pq, or xy
+                RegexRule::forbid(r"ab|cd|pq|xy", "blacklist")?,
+            ];
+            let mut nice = true;
+            for rule in &rules {
+                nice = nice && rule.passes(text)?;
+            }
+            return Ok(if nice {
                 (StatusCode::OK, Json(serde_json::json!({"result": "nice"})))
             } else {
                 (
                     StatusCode::BAD_REQUEST,
                     Json(serde_json::json!({"result": "naughty"})),
                 )
-            };
+            });
         }
     }
 
-    (
+    Ok((
         StatusCode::BAD_REQUEST,
         Json(serde_json::json!("response body does not matter")),
-    )
+    ))
 }
 
-async fn play_game(payload: String) -> impl IntoResponse {
-    if let Ok(payload) = serde_json::from_str::<serde_json::Value>(&payload) {
-        if let Some(input) = payload.get("input") {
-            let text = input.as_str().unwrap();
+async fn play_game(payload: String) -> Result<impl IntoResponse, AppError> {
+    if let Ok(payload) = serde_json::from_str::<Value>(&payload) {
+        if let Some(text) = payload.get("input").and_then(Value::as_str) {
             // Rule 1: must be at least 8 characters long
             if text.len() < 8 {
-                return (
-                    StatusCode::BAD_REQUEST,
-                    Json(Report {
-                        result: "naughty".to_string(),
-                        reason: "8 chars".to_string(),
-                    }),
-                );
+                return Ok(naughty(StatusCode::BAD_REQUEST, "8 chars"));
             }
             // Rule 2: must contain uppercase letters, lowercase letters, and digits
-            let rule_2 = Regex::new(r"(?=.*[A-Z])(?=.*[a-z])(?=.*\d).*").unwrap();
-            if !rule_2.is_match(text).unwrap() {
-                return (
-                    StatusCode::BAD_REQUEST,
-                    Json(Report {
-                        result: "naughty".to_string(),
-                        reason: "more types of chars".to_string(),
-                    }),
-                );
+            let rule_2 =
+                RegexRule::require(r"(?=.*[A-Z])(?=.*[a-z])(?=.*\d).*", "more types of chars")?;
+            if !rule_2.passes(text)? {
+                return Ok(naughty(StatusCode::BAD_REQUEST, rule_2.reason()));
             }
             // Rule 3: must contain at least 5 digits
-            let rule_3 = Regex::new(r"(.*\d.*){5,}").unwrap();
-            if !rule_3.is_match(text).unwrap() {
-                return (
-                    StatusCode::BAD_REQUEST,
-                    Json(Report {
-                        result: "naughty".to_string(),
-                        reason: "55555".to_string(),
-                    }),
-                );
+            let rule_3 = RegexRule::require(r"(.*\d.*){5,}", "55555")?;
+            if !rule_3.passes(text)? {
+                return Ok(naughty(StatusCode::BAD_REQUEST, rule_3.reason()));
             }
 
             // Rule 4: all integers must add up to 2023
-            let rule_4 = Regex::new(r"\d+").unwrap();
-            if rule_4
-                .find_iter(text)
-                .map(|m| m.unwrap().as_str().parse::<i32>().unwrap())
-                .sum::<i32>()
-                != 2023
-            {
-                return (
-                    StatusCode::BAD_REQUEST,
-                    Json(Report {
-                        result: "naughty".to_string(),
-                        reason: "math is hard".to_string(),
-                    }),
-                );
+            let rule_4 = Regex::new(r"\d+")?;
+            let mut sum = 0;
+            for m in rule_4.find_iter(text) {
+                let number = m?.as_str();
+                sum += number
+                    .parse::<i32>()
+                    .map_err(|_| AppError::InvalidInteger(number.to_string()))?;
+            }
+            if sum != 2023 {
+                return Ok(naughty(StatusCode::BAD_REQUEST, "math is hard"));
             }
 
             // Rule 5: must contain the letters j, o, and y in that order
-            let rule_5 = Regex::new(r"^([^joy]*)j([^joy]*)o([^joy]*)y([^joy]*)$").unwrap();
-            if !rule_5.is_match(text).unwrap() {
-                return (
-                    StatusCode::NOT_ACCEPTABLE,
-                    Json(Report {
-                        result: "naughty".to_string(),
-                        reason: "not joyful enough".to_string(),
-                    }),
-                );
+            let rule_5 = RegexRule::require(
+                r"^([^joy]*)j([^joy]*)o([^joy]*)y([^joy]*)$",
+                "not joyful enough",
+            )?;
+            if !rule_5.passes(text)? {
+                return Ok(naughty(StatusCode::NOT_ACCEPTABLE, rule_5.reason()));
             }
 
             // Rule 6: must contain a letter that repeats with exactly one other letter between them
-            let rule_6 = Regex::new(r"([a-zA-Z])\w\1").unwrap();
-            if !rule_6.is_match(text).unwrap() {
-                return (
+            let rule_6 = RegexRule::require(r"([a-zA-Z])\w\1", "illegal: no sandwich")?;
+            if !rule_6.passes(text)? {
+                return Ok(naughty(
                     StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
-                    Json(Report {
-                        result: "naughty".to_string(),
-                        reason: "illegal: no sandwich".to_string(),
-                    }),
-                );
+                    rule_6.reason(),
+                ));
             }
 
             // Rule 7: must contain at least one unicode character in the range [U+2980, U+2BFF]
-            let rule_7 = Regex::new(r"[\u{2980}-\u{2BFF}]").unwrap();
-            if !rule_7.is_match(text).unwrap() {
-                return (
-                    StatusCode::RANGE_NOT_SATISFIABLE,
-                    Json(Report {
-                        result: "naughty".to_string(),
-                        reason: "outranged".to_string(),
-                    }),
-                );
+            let rule_7 = RegexRule::require(r"[\u{2980}-\u{2BFF}]", "outranged")?;
+            if !rule_7.passes(text)? {
+                return Ok(naughty(StatusCode::RANGE_NOT_SATISFIABLE, rule_7.reason()));
             }
 
             // Rule 8: must contain at least one emoji
 
             if emojito::find_emoji(text).is_empty() {
-                return (
-                    StatusCode::UPGRADE_REQUIRED,
-                    Json(Report {
-                        result: "naughty".to_string(),
-                        reason: "😳".to_string(),
-                    }),
-                );
+                return Ok(naughty(StatusCode::UPGRADE_REQUIRED, "😳"));
             }
 
             // Rule 9: the hexadecimal representation of the sha256 hash must end with an a
@@ -157,30 +165,21 @@ async fn play_game(payload: String) -> impl IntoResponse {
             // read hash digest and consume hasher
             let result = hasher.finalize();
             if !hex::encode(result).ends_with('a') {
-                return (
-                    StatusCode::IM_A_TEAPOT,
-                    Json(Report {
-                        result: "naughty".to_string(),
-                        reason: "not a coffee brewer".to_string(),
-                    }),
-                );
+                return Ok(naughty(StatusCode::IM_A_TEAPOT, "not a coffee brewer"));
             }
 
-            return (
+            return Ok((
                 StatusCode::OK,
                 Json(Report {
                     result: "nice".to_string(),
                     reason: "that's a nice password".to_string(),
                 }),
-            );
+            ));
         }
     }
 
-    (
+    Ok(naughty(
         StatusCode::BAD_REQUEST,
-        Json(Report {
-            result: "naughty".to_string(),
-            reason: "response body does not matter".to_string(),
-        }),
-    )
+        "response body does not matter",
+    ))
 }
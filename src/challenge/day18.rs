@@ -1,21 +1,15 @@
-use std::collections::BTreeMap;
-
 use axum::{
-    extract::{Path, State},
+    extract::State,
     response::IntoResponse,
     routing::{get, post},
-    Json, Router,
+    Router,
 };
-use serde::{Deserialize, Serialize};
-
-use super::db::{MyState, reset, insert_orders, insert_regions};
 
-#[derive(Deserialize, Serialize)]
-struct RegionGift {
-    region_name: String,
-    gift_name: String,
-    gift_count: Option<i64>,
-}
+use super::{
+    db::{insert_orders, insert_regions, reset, MyState, TopListQuery},
+    error::AppError,
+    extract::{Json, Path, Query},
+};
 
 pub fn task(state: MyState) -> Router {
     Router::new()
@@ -27,66 +21,179 @@ pub fn task(state: MyState) -> Router {
         .with_state(state)
 }
 
-async fn regions_total_quantity(State(state): State<MyState>) -> impl IntoResponse {
-    if let Ok(records) = sqlx::query!(
-        r#"SELECT regions.name as "region_name!", SUM(orders.quantity) as "total!" FROM orders
-    INNER JOIN regions ON orders.region_id=regions.id GROUP BY regions.name ORDER BY regions.name"#
-    )
-    .fetch_all(&state.pool)
-    .await
-    {
-        Json(serde_json::json!(records
-            .iter()
-            .map(|r| {
-                serde_json::json!({
-                    "region": r.region_name,
-                    "total": r.total})
-            })
-            .collect::<Vec<_>>()))
-    } else {
-        Json(serde_json::json!({"region": null, "total": 0}))
-    }
+async fn regions_total_quantity(
+    State(state): State<MyState>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(state.store.region_totals().await?))
 }
 
 async fn regions_toplist(
     Path(num): Path<usize>,
+    Query(query): Query<TopListQuery>,
     State(state): State<MyState>,
-) -> impl IntoResponse {
-    let mut toplist = BTreeMap::<String, Vec<RegionGift>>::new();
-    if let Ok(records) = sqlx::query!(r#"SELECT DISTINCT name as "region_name!" from regions"#)
-        .fetch_all(&state.pool)
-        .await
-    {
-        for record in records {
-            toplist.insert(record.region_name, Vec::new());
-        }
+) -> Result<impl IntoResponse, AppError> {
+    Ok(Json(state.store.region_toplist(num, &query).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenge::db::RegionTopList;
+    use axum::http::StatusCode;
+    use axum_test::TestServer;
+    use serde_json::json;
+
+    async fn seed(server: &TestServer) {
+        let response = server.post("/reset").await;
+        response.assert_status(StatusCode::OK);
+
+        let response = server
+            .post("/regions")
+            .json(&json!([
+                {"id":1,"name":"North Pole"},
+                {"id":2,"name":"Europe"},
+                {"id":3,"name":"North America"},
+                {"id":4,"name":"South America"},
+                {"id":5,"name":"Africa"},
+                {"id":6,"name":"Asia"},
+                {"id":7,"name":"Oceania"}
+            ]))
+            .await;
+        response.assert_status(StatusCode::OK);
+
+        let response = server
+            .post("/orders")
+            .json(&json!([
+                {"id":1,"region_id":2,"gift_name":"Board Game","quantity":5},
+                {"id":2,"region_id":2,"gift_name":"Origami Set","quantity":8},
+                {"id":3,"region_id":3,"gift_name":"Action Figure","quantity":12},
+                {"id":4,"region_id":4,"gift_name":"Teddy Bear","quantity":10},
+                {"id":5,"region_id":2,"gift_name":"Yarn Ball","quantity":6},
+                {"id":6,"region_id":3,"gift_name":"Art Set","quantity":3},
+                {"id":7,"region_id":5,"gift_name":"Robot Lego Kit","quantity":5},
+                {"id":8,"region_id":6,"gift_name":"Drone","quantity":9}
+            ]))
+            .await;
+        response.assert_status(StatusCode::OK);
     }
 
-    if let Ok(records) = sqlx::query_as!(RegionGift,
-        r#"SELECT regions.name as "region_name!", orders.gift_name as "gift_name!", SUM(orders.quantity) as gift_count FROM regions
-    INNER JOIN orders ON orders.region_id=regions.id GROUP BY regions.name, orders.gift_name ORDER BY regions.name, gift_count DESC, gift_name"#
-    )
-    .fetch_all(&state.pool)
-    .await
-    {
-        for record in records {
-            if let Some(list) = toplist.get_mut(&record.region_name) {
-                if list.len() < num {
-                    list.push(record);
-                }
-            }
-        }
-        
-        Json(serde_json::json!(
-            toplist
+    #[tokio::test]
+    async fn task1() {
+        let app = task(MyState::memory());
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        seed(&server).await;
+
+        let response = server.get("/regions/total").await;
+        response.assert_status(StatusCode::OK);
+        response.assert_json(&json!([
+            {"region":"Africa","total":5},
+            {"region":"Asia","total":9},
+            {"region":"Europe","total":19},
+            {"region":"North America","total":15},
+            {"region":"South America","total":10}
+        ]));
+    }
+
+    #[tokio::test]
+    async fn task2() {
+        let app = task(MyState::memory());
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        seed(&server).await;
+
+        let response = server.get("/regions/top_list/2").await;
+        response.assert_status(StatusCode::OK);
+        let toplist = response.json::<Vec<RegionTopList>>();
+        let top_gifts = toplist
             .iter()
-            .map(|(key, list)| {
-                serde_json::json!({
-                    "region": key,
-                    "top_gifts": list.iter().map(|r| r.gift_name.clone()).collect::<Vec<_>>()})
-            })
-            .collect::<Vec<_>>()))
-    } else {
-        Json(serde_json::json!({"region": null, "top_gifts": null}))
+            .map(|region| (region.region.as_str(), region.top_gifts.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            top_gifts,
+            vec![
+                ("Africa", vec!["Robot Lego Kit".to_string()]),
+                ("Asia", vec!["Drone".to_string()]),
+                (
+                    "Europe",
+                    vec!["Origami Set".to_string(), "Yarn Ball".to_string()]
+                ),
+                (
+                    "North America",
+                    vec!["Action Figure".to_string(), "Art Set".to_string()]
+                ),
+                ("North Pole", vec![]),
+                ("Oceania", vec![]),
+                ("South America", vec!["Teddy Bear".to_string()]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn toplist_ranking() {
+        let app = task(MyState::memory());
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        seed(&server).await;
+        let response = server
+            .post("/orders")
+            .json(&json!([
+                {"id":9,"region_id":2,"gift_name":"Board Game","quantity":1},
+                {"id":10,"region_id":3,"gift_name":"Board Game","quantity":1}
+            ]))
+            .await;
+        response.assert_status(StatusCode::OK);
+
+        let response = server
+            .get("/regions/top_list/3")
+            .add_query_param("regions", "Europe")
+            .await;
+        response.assert_status(StatusCode::OK);
+        response.assert_json(&json!([{
+            "region": "Europe",
+            "top_gifts": ["Origami Set", "Board Game", "Yarn Ball"],
+            "gifts": [
+                {"gift": "Origami Set", "count": 8, "rank": 1},
+                {"gift": "Board Game", "count": 6, "rank": 2},
+                {"gift": "Yarn Ball", "count": 6, "rank": 2}
+            ]
+        }]));
+
+        let response = server
+            .get("/regions/top_list/1")
+            .add_query_param("rank_by", "orders")
+            .add_query_param("regions", "Europe,Oceania")
+            .await;
+        response.assert_json(&json!([
+            {
+                "region": "Europe",
+                "top_gifts": ["Board Game"],
+                "gifts": [{"gift": "Board Game", "count": 2, "rank": 1}]
+            },
+            {"region": "Oceania", "top_gifts": [], "gifts": []}
+        ]));
+
+        let response = server
+            .get("/regions/top_list/2")
+            .add_query_param("rank_by", "reach")
+            .add_query_param("regions", "North America")
+            .await;
+        response.assert_json(&json!([{
+            "region": "North America",
+            "top_gifts": ["Board Game", "Action Figure"],
+            "gifts": [
+                {"gift": "Board Game", "count": 2, "rank": 1},
+                {"gift": "Action Figure", "count": 1, "rank": 2}
+            ]
+        }]));
     }
 }
@@ -0,0 +1,311 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use argon2::{
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
+    Argon2,
+};
+use async_trait::async_trait;
+use axum::http::{header, HeaderMap};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sqlx::PgPool;
+
+use crate::{challenge::error::AppError, config::ChatConfig};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Offered by a client passing its token as the WebSocket protocol that
+/// follows it, e.g. `Sec-WebSocket-Protocol: bearer, <token>`.
+pub const BEARER_PROTOCOL: &str = "bearer";
+
+fn mac(key: &[u8]) -> HmacSha256 {
+    HmacSha256::new_from_slice(key).expect("HMAC takes keys of any size")
+}
+
+/// What a token says about its holder.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Claims {
+    pub user: String,
+    /// Expiry, in seconds since the Unix epoch.
+    pub exp: i64,
+}
+
+/// Issues and verifies tokens of the form `<claims>.<signature>`, both
+/// base64url encoded, signed with HMAC-SHA256.
+pub struct Tokens {
+    key: Vec<u8>,
+    ttl: Duration,
+}
+
+impl Tokens {
+    pub fn new(key: impl Into<Vec<u8>>, ttl: Duration) -> Self {
+        Self {
+            key: key.into(),
+            ttl,
+        }
+    }
+
+    /// Without a configured secret, tokens are signed with a random key and
+    /// only accepted by this instance until it restarts.
+    pub fn from_config(config: &ChatConfig) -> Self {
+        let key = match &config.secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => {
+                let mut key = vec![0; 32];
+                OsRng.fill_bytes(&mut key);
+                key
+            }
+        };
+        Self::new(key, Duration::seconds(config.token_ttl_secs as i64))
+    }
+
+    /// A token for `user` and when it expires.
+    pub fn issue(&self, user: &str, now: DateTime<Utc>) -> (String, DateTime<Utc>) {
+        let expires_at = now + self.ttl;
+        let claims = Claims {
+            user: user.to_string(),
+            exp: expires_at.timestamp(),
+        };
+        let claims = serde_json::to_vec(&claims).expect("claims serialize to JSON");
+        let claims = URL_SAFE_NO_PAD.encode(claims);
+
+        let mut mac = mac(&self.key);
+        mac.update(claims.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        (format!("{claims}.{signature}"), expires_at)
+    }
+
+    pub fn verify(&self, token: &str, now: DateTime<Utc>) -> Result<Claims, AppError> {
+        let invalid = || AppError::Unauthorized("invalid token".to_string());
+        let (claims, signature) = token.split_once('.').ok_or_else(invalid)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+        let mut mac = mac(&self.key);
+        mac.update(claims.as_bytes());
+        mac.verify_slice(&signature).map_err(|_| invalid())?;
+
+        let claims = URL_SAFE_NO_PAD.decode(claims).map_err(|_| invalid())?;
+        let claims: Claims = serde_json::from_slice(&claims).map_err(|_| invalid())?;
+        if claims.exp <= now.timestamp() {
+            return Err(AppError::Unauthorized("token expired".to_string()));
+        }
+        Ok(claims)
+    }
+}
+
+/// The token a client offered in `Sec-WebSocket-Protocol`, after
+/// [`BEARER_PROTOCOL`].
+pub fn protocol_token(headers: &HeaderMap) -> Option<&str> {
+    let mut protocols = headers
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim);
+    protocols.find(|protocol| *protocol == BEARER_PROTOCOL)?;
+    protocols.next()
+}
+
+/// The password of a user, hashed with Argon2id into a PHC string, which
+/// holds its salt.
+#[derive(Clone, Debug)]
+pub struct Credentials {
+    pub password_hash: String,
+}
+
+impl Credentials {
+    fn new(password: &str) -> Self {
+        let salt = SaltString::generate(&mut OsRng);
+        let password_hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .expect("the default Argon2 parameters are valid")
+            .to_string();
+        Self { password_hash }
+    }
+
+    fn check(&self, password: &str) -> bool {
+        PasswordHash::new(&self.password_hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    }
+}
+
+/// Run a password hash off the async workers, it takes a while on purpose.
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, AppError> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))
+}
+
+/// Chat users, who claim their name with the password of their first login.
+#[async_trait]
+pub trait Accounts: Send + Sync {
+    /// Whether `password` is the one of `user`, registering the user with
+    /// it if they never logged in.
+    async fn login(&self, user: &str, password: &str) -> Result<bool, AppError>;
+}
+
+#[derive(Default)]
+pub struct MemoryAccounts {
+    users: Mutex<HashMap<String, Credentials>>,
+}
+
+#[async_trait]
+impl Accounts for MemoryAccounts {
+    async fn login(&self, user: &str, password: &str) -> Result<bool, AppError> {
+        let password = password.to_string();
+        let existing = self.users.lock().unwrap().get(user).cloned();
+        if let Some(credentials) = existing {
+            return blocking(move || credentials.check(&password)).await;
+        }
+
+        let new = blocking({
+            let password = password.clone();
+            move || Credentials::new(&password)
+        })
+        .await?;
+        // another login may have registered the user first
+        let credentials = self
+            .users
+            .lock()
+            .unwrap()
+            .entry(user.to_string())
+            .or_insert_with(|| new.clone())
+            .clone();
+        if credentials.password_hash == new.password_hash {
+            return Ok(true);
+        }
+        blocking(move || credentials.check(&password)).await
+    }
+}
+
+pub struct PgAccounts {
+    pool: PgPool,
+}
+
+impl PgAccounts {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    async fn credentials(&self, user: &str) -> Result<Option<Credentials>, AppError> {
+        let credentials = sqlx::query_as!(
+            Credentials,
+            "SELECT password_hash FROM chat_users WHERE name = $1",
+            user,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(credentials)
+    }
+}
+
+#[async_trait]
+impl Accounts for PgAccounts {
+    async fn login(&self, user: &str, password: &str) -> Result<bool, AppError> {
+        let password = password.to_string();
+        let credentials = match self.credentials(user).await? {
+            Some(credentials) => credentials,
+            None => {
+                let new = blocking({
+                    let password = password.clone();
+                    move || Credentials::new(&password)
+                })
+                .await?;
+                let registered = sqlx::query!(
+                    "INSERT INTO chat_users (name, password_hash) VALUES ($1, $2)
+                    ON CONFLICT (name) DO NOTHING",
+                    user,
+                    new.password_hash,
+                )
+                .execute(&self.pool)
+                .await?
+                .rows_affected()
+                    == 1;
+                if registered {
+                    return Ok(true);
+                }
+                // another login registered the user first
+                self.credentials(user)
+                    .await?
+                    .ok_or_else(|| AppError::Internal(format!("{user} vanished")))?
+            }
+        };
+
+        blocking(move || credentials.check(&password)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        let tokens = Tokens::new("secret", Duration::hours(1));
+        let now = Utc::now();
+        let (token, expires_at) = tokens.issue("elf", now);
+
+        let claims = tokens.verify(&token, now).unwrap();
+        assert_eq!(claims.user, "elf");
+        assert_eq!(claims.exp, expires_at.timestamp());
+
+        assert!(tokens.verify(&token, now + Duration::hours(2)).is_err());
+        let other = Tokens::new("other secret", Duration::hours(1));
+        assert!(other.verify(&token, now).is_err());
+        // claims of another user with the signature of elf's
+        let (santa, _) = tokens.issue("santa", now);
+        let forged = format!(
+            "{}.{}",
+            santa.split_once('.').unwrap().0,
+            token.split_once('.').unwrap().1
+        );
+        assert!(tokens.verify(&forged, now).is_err());
+        assert!(tokens.verify("not a token", now).is_err());
+    }
+
+    #[test]
+    fn protocol() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(protocol_token(&headers), None);
+        headers.insert(
+            header::SEC_WEBSOCKET_PROTOCOL,
+            "chat, bearer, abc.def".parse().unwrap(),
+        );
+        assert_eq!(protocol_token(&headers), Some("abc.def"));
+    }
+
+    async fn first_login_claims_the_name(accounts: &dyn Accounts) {
+        assert!(accounts.login("elf", "cookies").await.unwrap());
+        assert!(accounts.login("elf", "cookies").await.unwrap());
+        assert!(!accounts.login("elf", "milk").await.unwrap());
+        assert!(accounts.login("santa", "milk").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn memory_accounts() {
+        first_login_claims_the_name(&MemoryAccounts::default()).await;
+    }
+
+    #[sqlx::test]
+    async fn pg_accounts(pool: PgPool) {
+        first_login_claims_the_name(&PgAccounts::new(pool)).await;
+    }
+
+    #[test]
+    fn password_hashes() {
+        let credentials = Credentials::new("cookies");
+        assert!(credentials.password_hash.starts_with("$argon2id$"));
+        assert!(credentials.check("cookies"));
+        assert!(!credentials.check("milk"));
+    }
+}
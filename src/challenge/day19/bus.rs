@@ -0,0 +1,179 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgListener, PgPool};
+use tokio::sync::broadcast;
+
+use super::room::RoomEvent;
+use crate::challenge::error::AppError;
+
+/// Events an instance may fall behind by before it misses some.
+const BUS_CAPACITY: usize = 1024;
+
+/// Channel the events of every room are notified on.
+const CHANNEL: &str = "chat_rooms";
+
+/// An event published to a room.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Published {
+    pub room: u32,
+    /// Id of the instance that published the event, which already applied
+    /// it to its own users.
+    pub origin: String,
+    pub event: RoomEvent,
+}
+
+/// Carries the events of the rooms to every server instance with users in
+/// them.
+#[async_trait]
+pub trait RoomBus: Send + Sync {
+    /// Send the event to the users of its room on every instance, this one
+    /// included.
+    async fn publish(&self, published: Published) -> Result<(), AppError>;
+
+    /// Events published to any room from now on, in the order they were
+    /// published.
+    fn subscribe(&self) -> broadcast::Receiver<Published>;
+}
+
+/// Rooms shared by the connections to this instance only.
+pub struct LocalBus {
+    tx: broadcast::Sender<Published>,
+}
+
+impl Default for LocalBus {
+    fn default() -> Self {
+        let (tx, _) = broadcast::channel(BUS_CAPACITY);
+        Self { tx }
+    }
+}
+
+#[async_trait]
+impl RoomBus for LocalBus {
+    async fn publish(&self, published: Published) -> Result<(), AppError> {
+        // nobody may be listening, which is fine
+        let _ = self.tx.send(published);
+        Ok(())
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Published> {
+        self.tx.subscribe()
+    }
+}
+
+/// Rooms shared through Postgres notifications by every instance using the
+/// same database.
+pub struct PgBus {
+    pool: PgPool,
+    tx: broadcast::Sender<Published>,
+}
+
+impl PgBus {
+    /// Must be called within a Tokio runtime, which runs the listener of
+    /// room notifications.
+    pub fn new(pool: PgPool) -> Self {
+        let (tx, _) = broadcast::channel(BUS_CAPACITY);
+        tokio::spawn(listen(pool.clone(), tx.clone()));
+        Self { pool, tx }
+    }
+}
+
+async fn listen(pool: PgPool, tx: broadcast::Sender<Published>) {
+    let mut listener = match PgListener::connect_with(&pool).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("failed to listen to chat rooms: {e}");
+            return;
+        }
+    };
+    if let Err(e) = listener.listen(CHANNEL).await {
+        tracing::error!("failed to listen to chat rooms: {e}");
+        return;
+    }
+
+    loop {
+        match listener.recv().await {
+            Ok(notification) => match serde_json::from_str(notification.payload()) {
+                Ok(published) => {
+                    let _ = tx.send(published);
+                }
+                Err(e) => tracing::warn!("ignored invalid chat room notification: {e}"),
+            },
+            Err(e) => {
+                // the listener reconnects on the next call
+                tracing::warn!("lost chat room notifications: {e}");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl RoomBus for PgBus {
+    async fn publish(&self, published: Published) -> Result<(), AppError> {
+        // Postgres refuses payloads over 8000 bytes, which fails the publish
+        let payload = serde_json::to_string(&published)?;
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(CHANNEL)
+            .bind(payload)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<Published> {
+        self.tx.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typing(user: &str) -> Published {
+        Published {
+            room: 1,
+            origin: "north".to_string(),
+            event: RoomEvent::Typing {
+                user: user.to_string(),
+                typing: true,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn local_bus() {
+        let bus = LocalBus::default();
+        let mut rx = bus.subscribe();
+        let published = typing("elf");
+
+        bus.publish(published.clone()).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap(), published);
+    }
+
+    #[sqlx::test]
+    async fn pg_bus(pool: PgPool) {
+        let north = PgBus::new(pool.clone());
+        let south = PgBus::new(pool);
+        let mut rx = south.subscribe();
+        let published = typing("elf");
+
+        // the other instance listens once its listener has connected
+        let received = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                north.publish(published.clone()).await.unwrap();
+                if let Ok(received) =
+                    tokio::time::timeout(Duration::from_millis(100), rx.recv()).await
+                {
+                    break received.unwrap();
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(received, published);
+
+        assert!(north.publish(typing(&"elf".repeat(3000))).await.is_err());
+    }
+}
@@ -0,0 +1,248 @@
+use std::{collections::HashMap, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+/// Points a player needs to win a two-player game.
+pub const POINTS_TO_WIN: u32 = 11;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum Phase {
+    /// Waiting for a second player.
+    Waiting,
+    /// Waiting for player `server` to serve.
+    Serving { server: usize },
+    /// The ball is with player `ball`, who has to hit it back.
+    Rally { ball: usize, hits: u32 },
+    /// `winner` is the player left when the other one disconnected, or the
+    /// first to reach [`POINTS_TO_WIN`].
+    Over { winner: usize },
+}
+
+/// What a frame of a player did to the game.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Play {
+    Served,
+    Hit,
+    Missed,
+    /// The frame came out of turn or before the game started.
+    Ignored,
+}
+
+/// A ping-pong game of one or two players, who are numbered from 0.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Game {
+    pub id: u64,
+    pub players: usize,
+    /// Players connected to the game.
+    pub joined: usize,
+    #[serde(flatten)]
+    pub phase: Phase,
+    /// Points per player; a player playing alone can't score.
+    pub score: Vec<u32>,
+}
+
+impl Game {
+    fn new(id: u64, players: usize) -> Self {
+        Self {
+            id,
+            players,
+            joined: 1,
+            phase: if players == 1 {
+                Phase::Serving { server: 0 }
+            } else {
+                Phase::Waiting
+            },
+            score: vec![0; players],
+        }
+    }
+
+    fn opponent(&self, player: usize) -> usize {
+        (player + 1) % self.players
+    }
+
+    /// Apply a text frame of `player`: `serve` starts a rally, `ping` hits
+    /// the ball back and anything else while the ball is theirs misses it.
+    pub fn play(&mut self, player: usize, text: &str) -> Play {
+        match (self.phase, text) {
+            (Phase::Serving { server }, "serve") if server == player => {
+                self.phase = Phase::Rally {
+                    ball: self.opponent(player),
+                    hits: 0,
+                };
+                Play::Served
+            }
+            (Phase::Rally { ball, hits }, "ping") if ball == player => {
+                self.phase = Phase::Rally {
+                    ball: self.opponent(player),
+                    hits: hits + 1,
+                };
+                Play::Hit
+            }
+            (Phase::Rally { ball, .. }, _) if ball == player && text != "serve" => {
+                let opponent = self.opponent(player);
+                // the one who missed serves next
+                self.phase = Phase::Serving { server: player };
+                if opponent != player {
+                    self.score[opponent] += 1;
+                    if self.score[opponent] >= POINTS_TO_WIN {
+                        self.phase = Phase::Over { winner: opponent };
+                    }
+                }
+                Play::Missed
+            }
+            _ => Play::Ignored,
+        }
+    }
+
+    /// A started two-player game is won by the player who stays.
+    fn leave(&mut self, player: usize) {
+        self.joined -= 1;
+        if !matches!(self.phase, Phase::Waiting | Phase::Over { .. }) && self.players > 1 {
+            self.phase = Phase::Over {
+                winner: self.opponent(player),
+            };
+        }
+    }
+}
+
+/// Open games, each in a watch channel its players follow.
+#[derive(Default)]
+pub struct Games {
+    next_id: u64,
+    games: HashMap<u64, Arc<watch::Sender<Game>>>,
+    /// A two-player game waiting for its second player.
+    waiting: Option<u64>,
+}
+
+impl Games {
+    /// Start a game for one player, or pair two players: the first waits
+    /// in a new game the second one joins. Returns the game and the number
+    /// of the player in it.
+    pub fn join(&mut self, players: usize) -> (Arc<watch::Sender<Game>>, usize) {
+        if players > 1 {
+            if let Some(id) = self.waiting.take() {
+                let game = self.games[&id].clone();
+                game.send_modify(|game| {
+                    game.joined += 1;
+                    game.phase = Phase::Serving { server: 0 };
+                });
+                return (game, 1);
+            }
+        }
+
+        self.next_id += 1;
+        let id = self.next_id;
+        let (game, _) = watch::channel(Game::new(id, players));
+        let game = Arc::new(game);
+        self.games.insert(id, game.clone());
+        if players > 1 {
+            self.waiting = Some(id);
+        }
+        (game, 0)
+    }
+
+    /// Remove `player` from game `id`, dropping the game when nobody is left.
+    pub fn leave(&mut self, id: u64, player: usize) {
+        let Some(game) = self.games.get(&id) else {
+            return;
+        };
+        game.send_modify(|game| game.leave(player));
+        if game.borrow().joined == 0 {
+            self.games.remove(&id);
+            if self.waiting == Some(id) {
+                self.waiting = None;
+            }
+        }
+    }
+
+    pub fn get(&self, id: u64) -> Option<Game> {
+        self.games.get(&id).map(|game| game.borrow().clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn solo() {
+        let mut games = Games::default();
+        let (game, player) = games.join(1);
+        let play = |text| {
+            let mut play = Play::Ignored;
+            game.send_modify(|game| play = game.play(player, text));
+            play
+        };
+
+        assert_eq!(play("ping"), Play::Ignored);
+        assert_eq!(play("serve"), Play::Served);
+        assert_eq!(play("ping"), Play::Hit);
+        assert_eq!(play("ping"), Play::Hit);
+        assert_eq!(play("oops"), Play::Missed);
+        assert_eq!(play("ping"), Play::Ignored);
+        assert_eq!(game.borrow().score, vec![0]);
+
+        // another player gets a game of their own
+        let (other, _) = games.join(1);
+        assert_ne!(other.borrow().id, game.borrow().id);
+
+        let id = game.borrow().id;
+        games.leave(id, player);
+        assert!(games.get(id).is_none());
+    }
+
+    #[test]
+    fn two_players() {
+        let mut games = Games::default();
+        let (game, first) = games.join(2);
+        assert_eq!(game.borrow().phase, Phase::Waiting);
+        let (other, second) = games.join(2);
+        let id = game.borrow().id;
+        assert_eq!(other.borrow().id, id);
+        assert_eq!((first, second), (0, 1));
+
+        let mut game = games.get(id).unwrap();
+        assert_eq!(game.play(1, "serve"), Play::Ignored);
+        assert_eq!(game.play(0, "serve"), Play::Served);
+        assert_eq!(game.play(0, "ping"), Play::Ignored);
+        assert_eq!(game.play(1, "ping"), Play::Hit);
+        assert_eq!(game.play(0, "ping"), Play::Hit);
+        assert_eq!(game.phase, Phase::Rally { ball: 1, hits: 2 });
+        assert_eq!(game.play(1, "miss"), Play::Missed);
+        assert_eq!(game.score, vec![1, 0]);
+        assert_eq!(game.phase, Phase::Serving { server: 1 });
+
+        for _ in 1..POINTS_TO_WIN {
+            game.play(1, "serve");
+            game.play(0, "ping");
+            game.play(1, "miss");
+        }
+        assert_eq!(game.phase, Phase::Over { winner: 0 });
+
+        // a third player waits for a new game
+        let (third, _) = games.join(2);
+        assert_ne!(third.borrow().id, id);
+    }
+
+    #[test]
+    fn forfeit() {
+        let mut games = Games::default();
+        let (game, _) = games.join(2);
+        games.join(2);
+        let id = game.borrow().id;
+
+        games.leave(id, 1);
+        assert_eq!(games.get(id).unwrap().phase, Phase::Over { winner: 0 });
+        games.leave(id, 0);
+        assert!(games.get(id).is_none());
+
+        // a player who gives up waiting takes the game along
+        let (game, _) = games.join(2);
+        let id = game.borrow().id;
+        games.leave(id, 0);
+        let (game, player) = games.join(2);
+        assert_eq!((game.borrow().phase, player), (Phase::Waiting, 0));
+    }
+}
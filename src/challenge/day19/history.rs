@@ -0,0 +1,357 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::RwLock,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::challenge::error::AppError;
+
+/// A tweet as stored in the log and broadcast to a room.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChatMessage {
+    /// Increases with every message of every room.
+    pub id: i64,
+    pub user: String,
+    pub message: String,
+    pub sent_at: DateTime<Utc>,
+}
+
+/// Messages sent within one minute.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MinuteCount {
+    pub minute: DateTime<Utc>,
+    pub messages: i64,
+}
+
+/// Start of the minute containing `time`.
+pub fn minute(time: DateTime<Utc>) -> DateTime<Utc> {
+    time.duration_trunc(Duration::minutes(1)).unwrap()
+}
+
+pub const DEFAULT_LIMIT: usize = 20;
+/// Most messages returned by one page, replayed on join or read from the
+/// log at once.
+pub const MAX_LIMIT: usize = 100;
+
+fn default_limit() -> usize {
+    DEFAULT_LIMIT
+}
+
+/// Query string of `GET /rooms/:room/messages`.
+#[derive(Deserialize, Clone, Debug)]
+pub struct HistoryQuery {
+    /// Only messages older than this id.
+    pub before: Option<i64>,
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+/// Messages of a room, oldest first, going back in time page by page.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct HistoryPage {
+    pub items: Vec<ChatMessage>,
+    /// Pass as `before` to fetch the older messages.
+    pub next_before: Option<i64>,
+}
+
+impl HistoryPage {
+    /// Build a page from up to `limit + 1` messages; the extra, oldest one
+    /// only tells that there are older messages.
+    pub fn new(mut items: Vec<ChatMessage>, limit: usize) -> Self {
+        let next_before = if items.len() > limit {
+            items.drain(..items.len() - limit);
+            items.first().map(|message| message.id)
+        } else {
+            None
+        };
+        Self { items, next_before }
+    }
+}
+
+/// Every message sent to a room, kept for users joining later and for
+/// receivers that fell behind.
+#[async_trait]
+pub trait MessageLog: Send + Sync {
+    async fn append(&self, room: u32, user: &str, message: &str) -> Result<ChatMessage, AppError>;
+
+    /// Up to `limit` messages newer than `after`, oldest first.
+    async fn after(
+        &self,
+        room: u32,
+        after: i64,
+        limit: usize,
+    ) -> Result<Vec<ChatMessage>, AppError>;
+
+    /// The `limit` newest messages older than `before` (or the newest ones
+    /// without it), oldest first.
+    async fn before(
+        &self,
+        room: u32,
+        before: Option<i64>,
+        limit: usize,
+    ) -> Result<Vec<ChatMessage>, AppError>;
+
+    /// Number of messages sent to `room` (or to any room without it) per
+    /// minute since `since`, skipping minutes without messages.
+    async fn per_minute(
+        &self,
+        room: Option<u32>,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<MinuteCount>, AppError>;
+}
+
+/// Log kept in process memory, lost on restart.
+#[derive(Default)]
+pub struct MemoryLog {
+    rooms: RwLock<HashMap<u32, Vec<ChatMessage>>>,
+    last_id: RwLock<i64>,
+    /// Messages kept per room, every message when unset.
+    keep: Option<usize>,
+}
+
+impl MemoryLog {
+    /// A log keeping the `keep` newest messages of each room.
+    pub fn new(keep: usize) -> Self {
+        Self {
+            keep: Some(keep),
+            ..Self::default()
+        }
+    }
+}
+
+#[async_trait]
+impl MessageLog for MemoryLog {
+    async fn append(&self, room: u32, user: &str, message: &str) -> Result<ChatMessage, AppError> {
+        let mut rooms = self.rooms.write().unwrap();
+        let mut last_id = self.last_id.write().unwrap();
+        *last_id += 1;
+        let message = ChatMessage {
+            id: *last_id,
+            user: user.to_string(),
+            message: message.to_string(),
+            sent_at: Utc::now(),
+        };
+        let messages = rooms.entry(room).or_default();
+        messages.push(message.clone());
+        if let Some(keep) = self.keep {
+            let pruned = messages.len().saturating_sub(keep);
+            messages.drain(..pruned);
+        }
+
+        Ok(message)
+    }
+
+    async fn after(
+        &self,
+        room: u32,
+        after: i64,
+        limit: usize,
+    ) -> Result<Vec<ChatMessage>, AppError> {
+        let rooms = self.rooms.read().unwrap();
+        let messages = rooms.get(&room).map(Vec::as_slice).unwrap_or_default();
+        // messages are sorted by id
+        let start = messages.partition_point(|message| message.id <= after);
+        Ok(messages[start..].iter().take(limit).cloned().collect())
+    }
+
+    async fn before(
+        &self,
+        room: u32,
+        before: Option<i64>,
+        limit: usize,
+    ) -> Result<Vec<ChatMessage>, AppError> {
+        let rooms = self.rooms.read().unwrap();
+        let messages = rooms.get(&room).map(Vec::as_slice).unwrap_or_default();
+        let end = match before {
+            Some(before) => messages.partition_point(|message| message.id < before),
+            None => messages.len(),
+        };
+        Ok(messages[end.saturating_sub(limit)..end].to_vec())
+    }
+
+    async fn per_minute(
+        &self,
+        room: Option<u32>,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<MinuteCount>, AppError> {
+        let rooms = self.rooms.read().unwrap();
+        let mut counts = BTreeMap::<DateTime<Utc>, i64>::new();
+        let messages = rooms
+            .iter()
+            .filter(|(id, _)| room.is_none_or(|room| **id == room))
+            .flat_map(|(_, messages)| messages);
+        for message in messages.filter(|message| message.sent_at >= since) {
+            *counts.entry(minute(message.sent_at)).or_default() += 1;
+        }
+
+        Ok(counts
+            .into_iter()
+            .map(|(minute, messages)| MinuteCount { minute, messages })
+            .collect())
+    }
+}
+
+/// Log stored in the `chat_messages` table, so it survives restarts.
+pub struct PgLog {
+    pool: PgPool,
+    /// Messages kept per room.
+    keep: usize,
+}
+
+impl PgLog {
+    pub fn new(pool: PgPool, keep: usize) -> Self {
+        Self { pool, keep }
+    }
+}
+
+#[async_trait]
+impl MessageLog for PgLog {
+    async fn append(&self, room: u32, user: &str, message: &str) -> Result<ChatMessage, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let message = sqlx::query_as!(
+            ChatMessage,
+            r#"INSERT INTO chat_messages (room, author, message) VALUES ($1, $2, $3)
+            RETURNING id, author as "user", message, sent_at"#,
+            room as i64,
+            user,
+            message,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        // drop what fell out of the window of the room
+        sqlx::query!(
+            "DELETE FROM chat_messages WHERE room = $1 AND id <= (
+                SELECT id FROM chat_messages WHERE room = $1
+                ORDER BY id DESC OFFSET $2 LIMIT 1
+            )",
+            room as i64,
+            self.keep as i64,
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(message)
+    }
+
+    async fn after(
+        &self,
+        room: u32,
+        after: i64,
+        limit: usize,
+    ) -> Result<Vec<ChatMessage>, AppError> {
+        let messages = sqlx::query_as!(
+            ChatMessage,
+            r#"SELECT id, author as "user", message, sent_at FROM chat_messages
+            WHERE room = $1 AND id > $2 ORDER BY id LIMIT $3"#,
+            room as i64,
+            after,
+            limit as i64,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(messages)
+    }
+
+    async fn before(
+        &self,
+        room: u32,
+        before: Option<i64>,
+        limit: usize,
+    ) -> Result<Vec<ChatMessage>, AppError> {
+        let mut messages = sqlx::query_as!(
+            ChatMessage,
+            r#"SELECT id, author as "user", message, sent_at FROM chat_messages
+            WHERE room = $1 AND ($2::BIGINT IS NULL OR id < $2)
+            ORDER BY id DESC LIMIT $3"#,
+            room as i64,
+            before,
+            limit as i64,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        messages.reverse();
+
+        Ok(messages)
+    }
+
+    async fn per_minute(
+        &self,
+        room: Option<u32>,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<MinuteCount>, AppError> {
+        let counts = sqlx::query_as!(
+            MinuteCount,
+            r#"SELECT date_trunc('minute', sent_at) as "minute!", COUNT(*) as "messages!"
+            FROM chat_messages
+            WHERE ($1::BIGINT IS NULL OR room = $1) AND sent_at >= $2
+            GROUP BY 1 ORDER BY 1"#,
+            room.map(i64::from),
+            since,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(messages: &[ChatMessage]) -> Vec<i64> {
+        messages.iter().map(|message| message.id).collect()
+    }
+
+    #[tokio::test]
+    async fn memory_log() {
+        let log = MemoryLog::default();
+        for i in 0..5 {
+            let room = if i == 2 { 2 } else { 1 };
+            log.append(room, "elf", &format!("tweet {i}"))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(ids(&log.after(1, 1, 2).await.unwrap()), vec![2, 4]);
+        assert_eq!(ids(&log.before(1, None, 2).await.unwrap()), vec![4, 5]);
+        assert_eq!(ids(&log.before(1, Some(4), 10).await.unwrap()), vec![1, 2]);
+        assert!(log.after(3, 0, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn memory_log_retention() {
+        let log = MemoryLog::new(2);
+        for i in 0..5 {
+            let room = if i == 2 { 2 } else { 1 };
+            log.append(room, "elf", &format!("tweet {i}"))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(ids(&log.before(1, None, 10).await.unwrap()), vec![4, 5]);
+        assert_eq!(ids(&log.after(1, 0, 10).await.unwrap()), vec![4, 5]);
+        assert_eq!(ids(&log.before(2, None, 10).await.unwrap()), vec![3]);
+    }
+
+    #[sqlx::test]
+    async fn pg_log_retention(pool: PgPool) {
+        let log = PgLog::new(pool, 2);
+        for i in 0..5 {
+            let room = if i == 2 { 2 } else { 1 };
+            log.append(room, "elf", &format!("tweet {i}"))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(ids(&log.before(1, None, 10).await.unwrap()), vec![4, 5]);
+        assert_eq!(ids(&log.after(1, 0, 10).await.unwrap()), vec![4, 5]);
+        assert_eq!(ids(&log.before(2, None, 10).await.unwrap()), vec![3]);
+    }
+}
@@ -0,0 +1,1804 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::ControlFlow,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use axum::{
+    extract::{
+        ws::{CloseFrame, Message, WebSocket},
+        State,
+    },
+    http::HeaderMap,
+    response::IntoResponse,
+    routing::{get, post},
+    Router,
+};
+use chrono::{DateTime, Duration, Utc};
+use futures::{stream::SplitSink, SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc, watch, RwLock,
+};
+
+use super::{
+    db::MyState,
+    error::AppError,
+    extract::{Json, Path, Query, WebSocketUpgrade},
+    ws::{Heartbeat, Sockets},
+};
+use crate::config::ChatConfig;
+
+mod auth;
+mod bus;
+mod game;
+mod history;
+mod moderation;
+mod protocol;
+mod room;
+mod views;
+
+use auth::{protocol_token, Accounts, MemoryAccounts, PgAccounts, Tokens, BEARER_PROTOCOL};
+use bus::{LocalBus, PgBus, Published, RoomBus};
+use game::{Game, Games, Phase, Play};
+use history::{
+    minute, ChatMessage, HistoryPage, HistoryQuery, MemoryLog, MessageLog, MinuteCount, PgLog,
+    DEFAULT_LIMIT, MAX_LIMIT,
+};
+use moderation::{Action, ContentFilter, Moderation, RateLimit, RuleFilter};
+use protocol::{ClientFrame, Envelope, Parsed, ProtocolError, Reply, ServerFrame};
+use room::{Presence, Room, RoomEvent, RoomSummary};
+use views::{ViewCount, ViewQuery, Views};
+
+/// Most minutes `GET /activity` looks back.
+const MAX_MINUTES: usize = 24 * 60;
+
+/// Where the chat keeps what outlives a connection.
+#[derive(Clone)]
+struct Backend {
+    log: Arc<dyn MessageLog>,
+    bus: Arc<dyn RoomBus>,
+    accounts: Arc<dyn Accounts>,
+}
+
+impl Backend {
+    /// Must be called within a Tokio runtime, which runs the listener of
+    /// the bus.
+    fn postgres(pool: PgPool, config: &ChatConfig) -> Self {
+        Self {
+            log: Arc::new(PgLog::new(pool.clone(), config.history_limit)),
+            bus: Arc::new(PgBus::new(pool.clone())),
+            accounts: Arc::new(PgAccounts::new(pool)),
+        }
+    }
+
+    fn memory(config: &ChatConfig) -> Self {
+        Self {
+            log: Arc::new(MemoryLog::new(config.history_limit)),
+            bus: Arc::new(LocalBus::default()),
+            accounts: Arc::new(MemoryAccounts::default()),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct GameState {
+    games: Arc<Mutex<Games>>,
+    rooms: Arc<RwLock<HashMap<u32, Arc<Room>>>>,
+    log: Arc<dyn MessageLog>,
+    bus: Arc<dyn RoomBus>,
+    accounts: Arc<dyn Accounts>,
+    tokens: Arc<Tokens>,
+    require_token: bool,
+    views: Arc<Views>,
+    /// Users of the rooms on every instance.
+    presence: Arc<Mutex<Presence>>,
+    moderation: Arc<Mutex<HashMap<u32, Moderation>>>,
+    filter: Arc<dyn ContentFilter>,
+    rate_limit: Option<RateLimit>,
+    sockets: Sockets,
+    /// Tells the events this instance published from those of the others.
+    instance: String,
+}
+
+impl GameState {
+    /// Must be called within a Tokio runtime, which runs the task handing
+    /// the events of the bus to the rooms.
+    fn new(backend: Backend, sockets: Sockets, config: &ChatConfig) -> Self {
+        let filter =
+            RuleFilter::from_config(config).expect("chat patterns are checked with the config");
+        let events = backend.bus.subscribe();
+        let state = Self {
+            games: Arc::new(Mutex::new(Games::default())),
+            rooms: Arc::new(RwLock::new(HashMap::new())),
+            log: backend.log,
+            bus: backend.bus,
+            accounts: backend.accounts,
+            tokens: Arc::new(Tokens::from_config(config)),
+            require_token: config.require_token,
+            views: Arc::new(Views::default()),
+            presence: Arc::new(Mutex::new(Presence::default())),
+            moderation: Arc::new(Mutex::new(HashMap::new())),
+            filter: Arc::new(filter),
+            rate_limit: RateLimit::from_config(config),
+            sockets,
+            instance: ulid::Ulid::new().to_string(),
+        };
+        tokio::spawn(dispatch(events, state.clone()));
+        state
+    }
+
+    /// The user a chat connection acts as: the holder of `token` if given,
+    /// otherwise the user of the path unless tokens are required.
+    fn identify(&self, path_user: Option<String>, token: Option<&str>) -> Result<String, AppError> {
+        let Some(token) = token else {
+            return match path_user {
+                Some(user) if !self.require_token => Ok(user),
+                _ => Err(AppError::Unauthorized("a token is required".to_string())),
+            };
+        };
+        let claims = self.tokens.verify(token, Utc::now())?;
+        match path_user {
+            Some(user) if user != claims.user => Err(AppError::Forbidden(format!(
+                "the token is for {}, not {user}",
+                claims.user
+            ))),
+            _ => Ok(claims.user),
+        }
+    }
+
+    /// Add a connection of `user` to the room, creating it if needed. The
+    /// receiver is subscribed before the join is announced.
+    async fn join(&self, id: u32, user: &str) -> (Arc<Room>, broadcast::Receiver<RoomEvent>) {
+        // joins and leaves hold the write lock, so an emptied room can't be
+        // torn down while someone joins it
+        let mut rooms = self.rooms.write().await;
+        let room = rooms
+            .entry(id)
+            .or_insert_with(|| {
+                tracing::info!("create channel for room {id}");
+                Arc::new(Room::new())
+            })
+            .clone();
+        let rx = room.tx.subscribe();
+        let first = room.join(user);
+        drop(rooms);
+
+        if first {
+            // the other instances learn it from the bus
+            let joined = self.presence.lock().unwrap().join(id, user);
+            self.moderation
+                .lock()
+                .unwrap()
+                .entry(id)
+                .or_insert_with(|| Moderation::new(user));
+            let event = RoomEvent::Join {
+                user: user.to_string(),
+            };
+            if joined {
+                // nobody may be listening, which is fine
+                let _ = room.tx.send(event.clone());
+            }
+            self.announce(id, event).await;
+        }
+        (room, rx)
+    }
+
+    /// Remove a connection of `user`, tearing the room down when nobody is
+    /// left in it on this instance.
+    async fn leave(&self, id: u32, room: &Room, user: &str) {
+        let mut rooms = self.rooms.write().await;
+        let last = room.leave(user);
+        if room.is_empty() {
+            tracing::info!("close empty room {id}");
+            rooms.remove(&id);
+        }
+        drop(rooms);
+
+        if last {
+            let event = RoomEvent::Leave {
+                user: user.to_string(),
+            };
+            let left = self.presence.lock().unwrap().leave(id, user);
+            self.prune(id);
+            if left {
+                let _ = room.tx.send(event.clone());
+            }
+            self.announce(id, event).await;
+        }
+    }
+
+    /// Forget the moderation of the room once nobody is left in it on any
+    /// instance.
+    fn prune(&self, id: u32) {
+        if self.presence.lock().unwrap().is_empty(id) {
+            self.moderation.lock().unwrap().remove(&id);
+        }
+    }
+
+    async fn publish(&self, room: u32, event: RoomEvent) -> Result<(), AppError> {
+        self.bus
+            .publish(Published {
+                room,
+                origin: self.instance.clone(),
+                event,
+            })
+            .await
+    }
+
+    /// Publish a join or leave, which the users of this instance were told.
+    async fn announce(&self, id: u32, event: RoomEvent) {
+        if let Err(e) = self.publish(id, event).await {
+            tracing::error!("failed to announce presence in room {id}: {e}");
+        }
+    }
+
+    /// Apply an event published to a room to what this instance knows of
+    /// it. Returns whether the users of this instance are pushed the event.
+    fn receive(
+        &self,
+        Published {
+            room,
+            origin,
+            event,
+        }: &Published,
+    ) -> bool {
+        let local = *origin == self.instance;
+        match event {
+            RoomEvent::Join { user } => {
+                self.moderation
+                    .lock()
+                    .unwrap()
+                    .entry(*room)
+                    .or_insert_with(|| Moderation::new(user))
+                    .joined(user);
+                !local && self.presence.lock().unwrap().join(*room, user)
+            }
+            RoomEvent::Leave { user } => {
+                // this instance counted its own users out when they left
+                let left = !local && self.presence.lock().unwrap().leave(*room, user);
+                self.prune(*room);
+                left
+            }
+            RoomEvent::Moderation { user, action } => {
+                // also applied again here by the instance of the owner, which keeps every
+                // instance on the order of the bus
+                if let Some(moderation) = self.moderation.lock().unwrap().get_mut(room) {
+                    moderation.enforce(*action, user);
+                }
+                true
+            }
+            RoomEvent::Message(ChatMessage { user, .. }) | RoomEvent::Direct { from: user, .. }
+                if !local =>
+            {
+                self.count_sent(*room, user);
+                true
+            }
+            _ => true,
+        }
+    }
+
+    /// Count a message sent through another instance against the rate limit.
+    fn count_sent(&self, id: u32, user: &str) {
+        let Some(limit) = &self.rate_limit else {
+            return;
+        };
+        if let Some(moderation) = self.moderation.lock().unwrap().get_mut(&id) {
+            moderation.count(user, limit, Instant::now());
+        }
+    }
+
+    fn is_banned(&self, id: u32, user: &str) -> bool {
+        self.moderation
+            .lock()
+            .unwrap()
+            .get(&id)
+            .is_some_and(|moderation| moderation.banned.contains(user))
+    }
+
+    fn moderate(&self, id: u32, by: &str, action: Action, user: &str) -> Result<(), String> {
+        match self.moderation.lock().unwrap().get_mut(&id) {
+            Some(moderation) => moderation.apply(by, action, user),
+            None => Err(format!("room {id} has no owner")),
+        }
+    }
+
+    /// `Err` with the reason when `user` may not send `message` to the room
+    /// now. Messages that pass count against the rate limit.
+    fn check_message(&self, id: u32, user: &str, message: &str) -> Result<(), String> {
+        let mut moderation = self.moderation.lock().unwrap();
+        let Some(moderation) = moderation.get_mut(&id) else {
+            return self.filter.check(message);
+        };
+        if moderation.muted.contains(user) {
+            return Err("you are muted in this room".to_string());
+        }
+        self.filter.check(message)?;
+        if let Some(limit) = &self.rate_limit {
+            if !moderation.allow(user, limit, Instant::now()) {
+                return Err("too many messages, slow down".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Hand the events published to the rooms to the users of this instance.
+async fn dispatch(mut events: broadcast::Receiver<Published>, state: GameState) {
+    loop {
+        match events.recv().await {
+            Ok(published) => {
+                if !state.receive(&published) {
+                    continue;
+                }
+                if let Some(room) = state.rooms.read().await.get(&published.room) {
+                    // nobody may be listening, which is fine
+                    let _ = room.tx.send(published.event);
+                }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!("dropped {skipped} room events");
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+pub fn task(state: MyState, sockets: Sockets, config: &ChatConfig) -> Router {
+    // tweets and users are kept in Postgres when the gift store is, so they survive restarts,
+    // and rooms are shared with the other instances using the database
+    let backend = match state.pool {
+        Some(pool) => Backend::postgres(pool, config),
+        None => Backend::memory(config),
+    };
+    router(GameState::new(backend, sockets, config))
+}
+
+fn router(state: GameState) -> Router {
+    Router::new()
+        .route("/ws/ping", get(game_handler))
+        .route("/games/:id", get(get_game))
+        .route("/reset", post(reset))
+        .route("/views", get(get_views))
+        .route("/views/breakdown", get(views_breakdown))
+        .route("/activity", get(activity))
+        .route("/login", post(login))
+        .route("/ws/room/:room", get(chat_handler))
+        .route("/ws/room/:room/user/:user", get(chat_handler))
+        .route("/rooms", get(list_rooms))
+        .route("/rooms/:room/users", get(room_users))
+        .route("/rooms/:room/moderation", get(room_moderation))
+        .route("/rooms/:room/messages", get(room_messages))
+        .with_state(state)
+}
+
+/// Query string of `/ws/ping`.
+#[derive(Deserialize, Debug)]
+struct GameQuery {
+    /// 1 to play alone, 2 to be paired with the next player asking for a
+    /// two-player game.
+    players: Option<usize>,
+}
+
+async fn game_handler(
+    WebSocketUpgrade(ws): WebSocketUpgrade,
+    Query(query): Query<GameQuery>,
+    State(state): State<GameState>,
+) -> Result<impl IntoResponse, AppError> {
+    let players = query.players.unwrap_or(1);
+    if !(1..=2).contains(&players) {
+        return Err(AppError::BadRequest(format!(
+            "a game has 1 or 2 players, not {players}"
+        )));
+    }
+    tracing::info!("new client connected");
+    Ok(state
+        .sockets
+        .limit(ws)
+        .on_upgrade(move |socket| handle_game_socket(socket, players, state)))
+}
+
+async fn get_game(
+    Path(id): Path<u64>,
+    State(state): State<GameState>,
+) -> Result<Json<Game>, AppError> {
+    state
+        .games
+        .lock()
+        .unwrap()
+        .get(id)
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound(format!("no game {id} is being played")))
+}
+
+async fn reset(State(state): State<GameState>) {
+    state.views.reset();
+}
+
+async fn get_views(
+    Query(query): Query<ViewQuery>,
+    State(state): State<GameState>,
+) -> impl IntoResponse {
+    state.views.total(&query).to_string()
+}
+
+async fn views_breakdown(
+    Query(query): Query<ViewQuery>,
+    State(state): State<GameState>,
+) -> Json<Vec<ViewCount>> {
+    Json(state.views.breakdown(&query))
+}
+
+/// Query string of `GET /activity`.
+#[derive(Deserialize, Debug)]
+struct ActivityQuery {
+    room: Option<u32>,
+    #[serde(default = "default_minutes")]
+    minutes: usize,
+}
+
+fn default_minutes() -> usize {
+    60
+}
+
+/// Messages sent per minute over the last `minutes` minutes, including the
+/// current one, with zero for quiet minutes.
+async fn activity(
+    Query(query): Query<ActivityQuery>,
+    State(state): State<GameState>,
+) -> Result<Json<Vec<MinuteCount>>, AppError> {
+    if !(1..=MAX_MINUTES).contains(&query.minutes) {
+        return Err(AppError::BadRequest(format!(
+            "minutes must be between 1 and {MAX_MINUTES}"
+        )));
+    }
+
+    let now = minute(Utc::now());
+    let since = now - Duration::minutes(query.minutes as i64 - 1);
+    let counts: HashMap<_, _> = state
+        .log
+        .per_minute(query.room, since)
+        .await?
+        .into_iter()
+        .map(|count| (count.minute, count.messages))
+        .collect();
+    let series = (0..query.minutes as i64)
+        .map(|i| {
+            let minute = since + Duration::minutes(i);
+            MinuteCount {
+                minute,
+                messages: counts.get(&minute).copied().unwrap_or_default(),
+            }
+        })
+        .collect();
+    Ok(Json(series))
+}
+
+#[derive(Deserialize, Debug)]
+struct LoginRequest {
+    user: String,
+    password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct LoginResponse {
+    user: String,
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// A token for the user, who claims their name with their first login.
+async fn login(
+    State(state): State<GameState>,
+    Json(login): Json<LoginRequest>,
+) -> Result<Json<LoginResponse>, AppError> {
+    if login.user.is_empty() || login.password.is_empty() {
+        return Err(AppError::BadRequest(
+            "user and password must not be empty".to_string(),
+        ));
+    }
+    if !state.accounts.login(&login.user, &login.password).await? {
+        return Err(AppError::Unauthorized(format!(
+            "wrong password for {}",
+            login.user
+        )));
+    }
+
+    let (token, expires_at) = state.tokens.issue(&login.user, Utc::now());
+    tracing::info!("{} logged in", login.user);
+    Ok(Json(LoginResponse {
+        user: login.user,
+        token,
+        expires_at,
+    }))
+}
+
+#[derive(Deserialize, Debug)]
+struct ChatPath {
+    room: u32,
+    /// Trusted only without a token, and only if tokens aren't required.
+    user: Option<String>,
+}
+
+/// Messages sent to a user before the live ones: the `last` N messages of
+/// the room, or every message after the id `since`.
+#[derive(Deserialize, Debug, Default)]
+struct JoinQuery {
+    last: Option<usize>,
+    since: Option<i64>,
+    /// Also accepted after `bearer` in `Sec-WebSocket-Protocol`.
+    token: Option<String>,
+}
+
+async fn chat_handler(
+    Path(ChatPath { room, user }): Path<ChatPath>,
+    Query(join): Query<JoinQuery>,
+    headers: HeaderMap,
+    WebSocketUpgrade(ws): WebSocketUpgrade,
+    State(state): State<GameState>,
+) -> Result<impl IntoResponse, AppError> {
+    let token = join.token.as_deref().or_else(|| protocol_token(&headers));
+    let user = state.identify(user, token)?;
+    if join.last.is_some() && join.since.is_some() {
+        return Err(AppError::BadRequest(
+            "only one of last and since may be given".to_string(),
+        ));
+    }
+    if join.last.is_some_and(|last| last > MAX_LIMIT) {
+        return Err(AppError::BadRequest(format!(
+            "last must be at most {MAX_LIMIT}"
+        )));
+    }
+    if state.is_banned(room, &user) {
+        return Err(AppError::Forbidden(format!(
+            "{user} is banned from room {room}"
+        )));
+    }
+    tracing::info!("{user} connected to room {room}");
+
+    Ok(state
+        .sockets
+        .limit(ws)
+        .protocols([BEARER_PROTOCOL])
+        .on_upgrade(move |socket| handle_chat_socket(socket, room, user, join, state)))
+}
+
+async fn list_rooms(State(state): State<GameState>) -> Json<Vec<RoomSummary>> {
+    Json(state.presence.lock().unwrap().rooms())
+}
+
+async fn room_users(
+    Path(room): Path<u32>,
+    State(state): State<GameState>,
+) -> Result<Json<Vec<String>>, AppError> {
+    state
+        .presence
+        .lock()
+        .unwrap()
+        .users(room)
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound(format!("nobody is in room {room}")))
+}
+
+async fn room_moderation(
+    Path(room): Path<u32>,
+    State(state): State<GameState>,
+) -> Result<Json<Moderation>, AppError> {
+    state
+        .moderation
+        .lock()
+        .unwrap()
+        .get(&room)
+        .cloned()
+        .map(Json)
+        .ok_or_else(|| AppError::NotFound(format!("nobody ever joined room {room}")))
+}
+
+async fn room_messages(
+    Path(room): Path<u32>,
+    Query(query): Query<HistoryQuery>,
+    State(state): State<GameState>,
+) -> Result<Json<HistoryPage>, AppError> {
+    let limit = query.limit;
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(AppError::BadRequest(format!(
+            "limit must be between 1 and {MAX_LIMIT}"
+        )));
+    }
+
+    // one more message tells whether there are older ones
+    let messages = state.log.before(room, query.before, limit + 1).await?;
+    Ok(Json(HistoryPage::new(messages, limit)))
+}
+
+async fn handle_game_socket(socket: WebSocket, players: usize, state: GameState) {
+    // By splitting socket we can send and receive at the same time. In this example we will send
+    // unsolicited messages to client based on some sort of server's internal event (i.e .timer).
+    let (sender, mut receiver) = socket.split();
+    let sender = Arc::new(RwLock::new(sender));
+    let heartbeat = state.sockets.open();
+    let seen = heartbeat.seen();
+    let (game, player) = state.games.lock().unwrap().join(players);
+    let id = game.borrow().id;
+    tracing::info!("player {player} joined game {id}");
+
+    // the players of a two-player game are pushed its state on every change,
+    // a player alone only hears `pong` when they hit the ball
+    let push_task =
+        (players > 1).then(|| tokio::spawn(push_game(game.subscribe(), sender.clone())));
+    let mut heartbeat_task = tokio::spawn(heartbeat.keep_alive(sender.clone()));
+
+    let receive = async {
+        while let Some(msg) = receiver.next().await {
+            seen.touch();
+            if let Ok(msg) = msg {
+                if process_game_message(msg, player, &game, &sender)
+                    .await
+                    .is_break()
+                {
+                    break;
+                }
+            } else {
+                tracing::info!("client abruptly disconnected");
+                break;
+            }
+        }
+    };
+    tokio::select! {
+        _ = receive => heartbeat_task.abort(),
+        // the client went quiet or the server stops
+        _ = &mut heartbeat_task => {}
+    }
+    if let Some(push_task) = push_task {
+        push_task.abort();
+    }
+    state.games.lock().unwrap().leave(id, player);
+    // returning from the handler closes the websocket connection
+    tracing::info!("player {player} left game {id}");
+}
+
+/// Send the state of a game to a player until it is over.
+async fn push_game(
+    mut game: watch::Receiver<Game>,
+    sender: Arc<RwLock<SplitSink<WebSocket, Message>>>,
+) {
+    loop {
+        let state = game.borrow_and_update().clone();
+        let text = serde_json::to_string(&state).expect("a game serializes to JSON");
+        if sender
+            .write()
+            .await
+            .send(Message::Text(text))
+            .await
+            .is_err()
+        {
+            return;
+        }
+        if matches!(state.phase, Phase::Over { .. }) {
+            break;
+        }
+        if game.changed().await.is_err() {
+            return;
+        }
+    }
+    let _ = sender
+        .write()
+        .await
+        .send(Message::Close(Some(CloseFrame {
+            code: 1000,
+            reason: "game over".into(),
+        })))
+        .await;
+}
+
+async fn handle_chat_socket(
+    socket: WebSocket,
+    id: u32,
+    user: String,
+    join: JoinQuery,
+    state: GameState,
+) {
+    // By splitting socket we can send and receive at the same time. In this example we will send
+    // unsolicited messages to client based on some sort of server's internal event (i.e .timer).
+    let (sender, mut receiver) = socket.split();
+    let heartbeat = state.sockets.open();
+    let seen = heartbeat.seen();
+
+    // subscribe before reading the log so no message falls in between
+    let (room, mut rx) = state.join(id, &user).await;
+    tracing::info!("{} joined room {}", user, id);
+    let (replies_tx, mut replies) = mpsc::channel(16);
+
+    let log = state.log.clone();
+    let send_user = user.clone();
+    let mut client = Client {
+        sender,
+        room: id,
+        views: state.views.clone(),
+        logged: HashSet::new(),
+        heartbeat,
+    };
+    // This task will replay the requested history, then forward the broadcast events and the
+    // replies to this client's frames to this connected client.
+    let mut send_task = tokio::spawn(async move {
+        let joined = match join.since {
+            Some(since) => catch_up(log.as_ref(), since, &mut client).await,
+            None => replay_last(log.as_ref(), join.last.unwrap_or(0), &mut client).await,
+        };
+        let Some(mut last_id) = joined else {
+            return;
+        };
+
+        loop {
+            tokio::select! {
+                message = client.heartbeat.beat() => {
+                    let close = matches!(message, Message::Close(_));
+                    if client.write(message).await.is_err() || close {
+                        break;
+                    }
+                }
+                event = rx.recv() => match event {
+                    Ok(RoomEvent::Message(message)) => {
+                        // already sent from the log
+                        if client.logged.remove(&message.id) {
+                            continue;
+                        }
+                        // tweets of other instances may come out of order
+                        last_id = last_id.max(message.id);
+                        if client.send_event(RoomEvent::Message(message)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(event) => {
+                        if !event.is_for(&send_user) {
+                            continue;
+                        }
+                        // a banned user is disconnected once told
+                        let banned = matches!(
+                            &event,
+                            RoomEvent::Moderation { user, action: Action::Ban } if *user == send_user
+                        );
+                        if client.send_event(event).await.is_err() || banned {
+                            break;
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!("{send_user} missed {skipped} messages of room {id}");
+                        match catch_up(log.as_ref(), last_id, &mut client).await {
+                            Some(caught_up) => last_id = caught_up,
+                            None => break,
+                        }
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                outgoing = replies.recv() => match outgoing {
+                    Some(Outgoing::Reply { reference, reply }) => {
+                        if client.send(reference, ServerFrame::Reply(reply)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Outgoing::Close(error)) => {
+                        client.close(error).await;
+                        break;
+                    }
+                    // the client is gone
+                    None => break,
+                },
+            }
+        }
+    });
+
+    let session = Session {
+        id,
+        room: room.clone(),
+        user: user.clone(),
+        state: state.clone(),
+        replies: replies_tx,
+    };
+    // This task will receive messages from this client.
+    let mut recv_task = tokio::spawn(async move {
+        while let Some(Ok(msg)) = receiver.next().await {
+            seen.touch();
+            if process_chat_message(msg, &session).await.is_break() {
+                break;
+            }
+        }
+        tracing::info!("{} left room {}", session.user, session.id);
+    });
+
+    //If any one of the tasks exit, abort the other.
+    tokio::select! {
+        _ = (&mut send_task) => recv_task.abort(),
+        // the send task pushes what is left, like the error of a protocol violation, and stops
+        // once the replies channel is closed
+        _ = (&mut recv_task) => {
+            let _ = (&mut send_task).await;
+        }
+    };
+    state.leave(id, &room, &user).await;
+    // returning from the handler closes the websocket connection
+}
+
+/// What the receiving half of a chat connection has the sending half push.
+enum Outgoing {
+    Reply {
+        reference: Option<String>,
+        reply: Reply,
+    },
+    /// Report the error, then close the connection.
+    Close(ProtocolError),
+}
+
+/// The sending half of a chat connection.
+struct Client {
+    sender: SplitSink<WebSocket, Message>,
+    room: u32,
+    views: Arc<Views>,
+    /// Ids of the tweets sent from the log, which the room may push again.
+    logged: HashSet<i64>,
+    /// Also bounds how long a frame may take to be written.
+    heartbeat: Heartbeat,
+}
+
+impl Client {
+    /// Push `frame` to the client; a tweet counts as viewed once it was
+    /// written to the socket.
+    async fn send(
+        &mut self,
+        reference: Option<String>,
+        frame: ServerFrame,
+    ) -> Result<(), axum::Error> {
+        let envelope = Envelope::new(reference, frame);
+        let text = serde_json::to_string(&envelope).unwrap();
+        self.write(Message::Text(text)).await?;
+        if let ServerFrame::Event(RoomEvent::Message(message)) = envelope.body {
+            self.views.record(self.room, &message.user);
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, message: Message) -> Result<(), axum::Error> {
+        self.heartbeat.send(&mut self.sender, message).await
+    }
+
+    async fn send_event(&mut self, event: RoomEvent) -> Result<(), axum::Error> {
+        self.send(None, ServerFrame::Event(event)).await
+    }
+
+    async fn close(&mut self, error: ProtocolError) {
+        let reply = Reply::error(error.code(), error.to_string());
+        let frame = CloseFrame {
+            code: error.close_code(),
+            reason: error.to_string().into(),
+        };
+        if self.send(None, ServerFrame::Reply(reply)).await.is_ok() {
+            let _ = self.write(Message::Close(Some(frame))).await;
+        }
+    }
+}
+
+/// Send the `last` newest messages of the client's room. Returns the id of
+/// the newest message of the room, or `None` when the client is gone or the
+/// log failed.
+async fn replay_last(log: &dyn MessageLog, last: usize, client: &mut Client) -> Option<i64> {
+    let room = client.room;
+    // the newest message is read even if none is sent, to know where live messages start
+    let messages = match log.before(room, None, last.max(1)).await {
+        Ok(messages) => messages,
+        Err(e) => {
+            tracing::error!("failed to read the history of room {room}: {e}");
+            return None;
+        }
+    };
+    let last_id = messages.last().map_or(0, |message| message.id);
+    let skipped = messages.len() - last.min(messages.len());
+    for message in messages.into_iter().skip(skipped) {
+        client.logged.insert(message.id);
+        client.send_event(RoomEvent::Message(message)).await.ok()?;
+    }
+
+    Some(last_id)
+}
+
+/// Send every message of the client's room after `last_id`, read from the
+/// log in pages. Returns the id of the last message sent, or `None` when the
+/// client is gone or the log failed.
+async fn catch_up(log: &dyn MessageLog, mut last_id: i64, client: &mut Client) -> Option<i64> {
+    let room = client.room;
+    loop {
+        let messages = match log.after(room, last_id, MAX_LIMIT).await {
+            Ok(messages) => messages,
+            Err(e) => {
+                tracing::error!("failed to read the history of room {room}: {e}");
+                return None;
+            }
+        };
+        let done = messages.len() < MAX_LIMIT;
+        for message in messages {
+            last_id = message.id;
+            client.logged.insert(message.id);
+            client.send_event(RoomEvent::Message(message)).await.ok()?;
+        }
+        if done {
+            return Some(last_id);
+        }
+    }
+}
+
+/// The receiving half of a chat connection.
+struct Session {
+    id: u32,
+    room: Arc<Room>,
+    user: String,
+    state: GameState,
+    replies: mpsc::Sender<Outgoing>,
+}
+
+impl Session {
+    async fn reply(&self, outgoing: Outgoing) {
+        // fails only once the sending half is gone
+        let _ = self.replies.send(outgoing).await;
+    }
+
+    /// Carry out a frame of the client. `Err` is the error reply.
+    async fn handle(&self, frame: ClientFrame) -> Result<Reply, Reply> {
+        let (id, user) = (self.id, &self.user);
+        match frame {
+            ClientFrame::Tweet { message } => {
+                self.check(&message)?;
+                let _sending = self.room.sending.lock().await;
+                let message = self
+                    .state
+                    .log
+                    .append(id, user, &message)
+                    .await
+                    .map_err(|e| {
+                        tracing::error!("failed to store tweet of {user} in room {id}: {e}");
+                        Reply::error("internal_error", "the tweet could not be stored")
+                    })?;
+                let tweet_id = message.id;
+                // views are counted by the receivers once the tweet reached their client
+                self.publish(RoomEvent::Message(message)).await?;
+                Ok(Reply::Ack { id: Some(tweet_id) })
+            }
+            ClientFrame::Dm { to, message } => {
+                self.check(&message)?;
+                if !self.state.presence.lock().unwrap().contains(id, &to) {
+                    return Err(Reply::error(
+                        "not_found",
+                        format!("{to} is not in the room"),
+                    ));
+                }
+                // direct messages are not logged, they would show up in the room's history
+                self.publish(RoomEvent::Direct {
+                    from: user.clone(),
+                    to,
+                    message,
+                    sent_at: Utc::now(),
+                })
+                .await?;
+                Ok(Reply::Ack { id: None })
+            }
+            ClientFrame::History { before, limit } => {
+                let limit = limit.unwrap_or(DEFAULT_LIMIT);
+                if !(1..=MAX_LIMIT).contains(&limit) {
+                    return Err(Reply::error(
+                        "bad_request",
+                        format!("limit must be between 1 and {MAX_LIMIT}"),
+                    ));
+                }
+                // one more message tells whether there are older ones
+                let messages = self
+                    .state
+                    .log
+                    .before(id, before, limit + 1)
+                    .await
+                    .map_err(|e| {
+                        tracing::error!("failed to read the history of room {id}: {e}");
+                        Reply::error("internal_error", "the history could not be read")
+                    })?;
+                Ok(Reply::History(HistoryPage::new(messages, limit)))
+            }
+            ClientFrame::Typing { typing } => {
+                // typing notifications are not kept
+                self.publish(RoomEvent::Typing {
+                    user: user.clone(),
+                    typing,
+                })
+                .await?;
+                Ok(Reply::Ack { id: None })
+            }
+            ClientFrame::Moderate {
+                action,
+                user: target,
+            } => {
+                self.state
+                    .moderate(id, user, action, &target)
+                    .map_err(|reason| Reply::error("forbidden", reason))?;
+                self.publish(RoomEvent::Moderation {
+                    user: target,
+                    action,
+                })
+                .await?;
+                Ok(Reply::Ack { id: None })
+            }
+        }
+    }
+
+    async fn publish(&self, event: RoomEvent) -> Result<(), Reply> {
+        self.state.publish(self.id, event).await.map_err(|e| {
+            tracing::error!("failed to publish to room {}: {e}", self.id);
+            Reply::error("internal_error", "the room could not be reached")
+        })
+    }
+
+    fn check(&self, message: &str) -> Result<(), Reply> {
+        self.state
+            .check_message(self.id, &self.user, message)
+            .map_err(|reason| {
+                tracing::info!(
+                    "rejected message of {} in room {}: {reason}",
+                    self.user,
+                    self.id
+                );
+                Reply::error("rejected", reason)
+            })
+    }
+}
+
+/// helper to print contents of messages to stdout. Has special treatment for Close.
+async fn process_game_message(
+    msg: Message,
+    player: usize,
+    game: &watch::Sender<Game>,
+    sender: &RwLock<SplitSink<WebSocket, Message>>,
+) -> ControlFlow<(), ()> {
+    match msg {
+        Message::Text(t) => {
+            let mut play = Play::Ignored;
+            // ignored frames don't wake the players up
+            game.send_if_modified(|game| {
+                play = game.play(player, &t);
+                play != Play::Ignored
+            });
+            let solo = game.borrow().players == 1;
+            match play {
+                Play::Served => tracing::info!("player {player} served"),
+                Play::Hit if solo => {
+                    tracing::info!("pong");
+                    if sender
+                        .write()
+                        .await
+                        .send(Message::Text(String::from("pong")))
+                        .await
+                        .is_err()
+                    {
+                        tracing::info!("client abruptly disconnected");
+                        return ControlFlow::Break(());
+                    }
+                }
+                Play::Hit => tracing::info!("player {player} hit the ball"),
+                Play::Missed => tracing::info!("player {player} missed"),
+                Play::Ignored => tracing::info!(">>> player {player} sent {t:?} out of turn"),
+            }
+        }
+        Message::Binary(d) => {
+            tracing::info!(">>> client sent {} bytes: {:?}", d.len(), d);
+        }
+        Message::Close(c) => {
+            if let Some(cf) = c {
+                tracing::info!(
+                    ">>> client sent close with code {} and reason `{}`",
+                    cf.code,
+                    cf.reason
+                );
+            } else {
+                tracing::info!(">>> client somehow sent close message without CloseFrame");
+            }
+            return ControlFlow::Break(());
+        }
+
+        Message::Pong(v) => {
+            tracing::info!(">>> client sent pong with {v:?}");
+        }
+        // You should never need to manually handle Message::Ping, as axum's websocket library
+        // will do so for you automagically by replying with Pong and copying the v according to
+        // spec. But if you need the contents of the pings you can see them here.
+        Message::Ping(v) => {
+            tracing::info!(">>> client sent ping with {v:?}");
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+/// helper to print contents of messages to stdout. Has special treatment for Close.
+async fn process_chat_message(msg: Message, session: &Session) -> ControlFlow<(), ()> {
+    match msg {
+        Message::Text(text) => {
+            let (reference, frame, typed) = match protocol::parse(&text) {
+                Ok(Parsed::Typed(envelope)) => (envelope.reference, envelope.body, true),
+                Ok(Parsed::Legacy(frame)) => (None, frame, false),
+                Ok(Parsed::Ignored) => return ControlFlow::Continue(()),
+                Err(error) => {
+                    tracing::info!(">>> {} sent {:?}: {error}", session.user, text);
+                    session.reply(Outgoing::Close(error)).await;
+                    return ControlFlow::Break(());
+                }
+            };
+            let reply = session.handle(frame).await.unwrap_or_else(|error| error);
+            // the first clients only expect to hear about failures
+            if typed || matches!(reply, Reply::Error { .. }) {
+                session.reply(Outgoing::Reply { reference, reply }).await;
+            }
+        }
+        Message::Binary(_) => {
+            session.reply(Outgoing::Close(ProtocolError::Binary)).await;
+            return ControlFlow::Break(());
+        }
+        Message::Close(c) => {
+            if let Some(cf) = c {
+                tracing::info!(
+                    ">>> client sent close with code {} and reason `{}`",
+                    cf.code,
+                    cf.reason
+                );
+            } else {
+                tracing::info!(
+                    ">>> {} somehow sent close message without CloseFrame",
+                    session.user
+                );
+            }
+            return ControlFlow::Break(());
+        }
+        _ => {
+            tracing::info!(">>> client sent something else: {:?}", msg);
+        }
+    }
+    ControlFlow::Continue(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use axum_test::TestServer;
+    use serde_json::{json, Value};
+    use tokio::{net::TcpListener, time::timeout};
+    use tokio_tungstenite::{
+        connect_async,
+        tungstenite::{client::IntoClientRequest, Message as WsMessage},
+        MaybeTlsStream, WebSocketStream,
+    };
+
+    use crate::challenge::error::assert_problem;
+
+    type Ws = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+    /// Chat settings trusting the user of the path, which most tests connect
+    /// as.
+    fn trusting() -> ChatConfig {
+        ChatConfig {
+            require_token: false,
+            ..ChatConfig::default()
+        }
+    }
+
+    /// Serve day 19 on a free port, for tests that need a real WebSocket.
+    async fn serve() -> std::net::SocketAddr {
+        serve_app(task(MyState::memory(), Sockets::default(), &trusting())).await
+    }
+
+    async fn serve_app(app: Router) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        addr
+    }
+
+    async fn connect(addr: std::net::SocketAddr, room: u32, user: &str) -> Ws {
+        let url = format!("ws://{addr}/ws/room/{room}/user/{user}");
+        let (mut ws, _) = connect_async(url).await.unwrap();
+        assert_eq!(next_frame(&mut ws).await["type"], "join");
+        ws
+    }
+
+    async fn send(ws: &mut Ws, frame: Value) {
+        ws.send(WsMessage::Text(frame.to_string())).await.unwrap();
+    }
+
+    /// The status of a refused upgrade.
+    async fn refused(url: String) -> StatusCode {
+        let error = connect_async(url).await.unwrap_err();
+        let tokio_tungstenite::tungstenite::Error::Http(response) = error else {
+            panic!("expected an HTTP error, got {error:?}");
+        };
+        response.status()
+    }
+
+    /// The next text frame, skipping pings and pongs.
+    async fn next_frame(ws: &mut Ws) -> Value {
+        loop {
+            let message = timeout(std::time::Duration::from_secs(5), ws.next())
+                .await
+                .expect("no frame within 5s")
+                .unwrap()
+                .unwrap();
+            match message {
+                WsMessage::Text(text) => return serde_json::from_str(&text).unwrap(),
+                WsMessage::Ping(_) | WsMessage::Pong(_) => {}
+                other => panic!("unexpected frame {other:?}"),
+            }
+        }
+    }
+
+    /// The next `n` text frames, sorted by type: room events and replies
+    /// travel separately, so their order is not fixed.
+    async fn next_frames(ws: &mut Ws, n: usize) -> Vec<Value> {
+        let mut frames = Vec::new();
+        for _ in 0..n {
+            frames.push(next_frame(ws).await);
+        }
+        frames.sort_by_key(|frame| frame["type"].as_str().unwrap().to_string());
+        frames
+    }
+
+    async fn expect_close(ws: &mut Ws, code: u16) {
+        let message = timeout(std::time::Duration::from_secs(5), ws.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let WsMessage::Close(Some(frame)) = message else {
+            panic!("expected a close frame, got {message:?}");
+        };
+        assert_eq!(u16::from(frame.code), code);
+    }
+
+    #[tokio::test]
+    async fn ws_typed_frames() {
+        let addr = serve().await;
+        let mut elf = connect(addr, 1, "elf").await;
+
+        send(
+            &mut elf,
+            json!({"v": 1, "type": "tweet", "ref": "t1", "message": "ho ho ho"}),
+        )
+        .await;
+        let frames = next_frames(&mut elf, 2).await;
+        assert_eq!(frames[0]["type"], "ack");
+        assert_eq!(frames[0]["ref"], "t1");
+        assert_eq!(frames[1]["type"], "tweet");
+        assert_eq!(frames[1]["user"], "elf");
+        assert_eq!(frames[1]["message"], "ho ho ho");
+        assert_eq!(frames[0]["id"], frames[1]["id"]);
+
+        send(
+            &mut elf,
+            json!({"type": "history", "ref": "h", "limit": 10}),
+        )
+        .await;
+        let frame = next_frame(&mut elf).await;
+        assert_eq!(frame["type"], "history");
+        assert_eq!(frame["ref"], "h");
+        assert_eq!(frame["items"][0]["message"], "ho ho ho");
+
+        send(
+            &mut elf,
+            json!({"type": "dm", "ref": "d", "to": "santa", "message": "hi"}),
+        )
+        .await;
+        let frame = next_frame(&mut elf).await;
+        assert_eq!(frame["type"], "error");
+        assert_eq!(frame["code"], "not_found");
+        assert_eq!(frame["ref"], "d");
+    }
+
+    #[tokio::test]
+    async fn ws_legacy_frames() {
+        let addr = serve().await;
+        let mut elf = connect(addr, 1, "elf").await;
+
+        // the first clients get the tweet but no ack
+        send(&mut elf, json!({"message": "ho ho ho"})).await;
+        send(
+            &mut elf,
+            json!({"type": "typing", "ref": "t", "typing": true}),
+        )
+        .await;
+        let frames = next_frames(&mut elf, 3).await;
+        let types: Vec<_> = frames.iter().map(|frame| &frame["type"]).collect();
+        assert_eq!(types, vec!["ack", "tweet", "typing"]);
+        assert_eq!(frames[0]["ref"], "t");
+        assert_eq!(frames[1]["message"], "ho ho ho");
+
+        send(&mut elf, json!({"message": "ho".repeat(100)})).await;
+        let frame = next_frame(&mut elf).await;
+        assert_eq!(frame["type"], "error");
+        assert_eq!(frame["code"], "rejected");
+    }
+
+    #[tokio::test]
+    async fn ws_instances_share_rooms() {
+        // two instances with the same database
+        let backend = Backend::memory(&ChatConfig::default());
+        let instance = || {
+            router(GameState::new(
+                backend.clone(),
+                Sockets::default(),
+                &trusting(),
+            ))
+        };
+        let (first, second) = (serve_app(instance()).await, serve_app(instance()).await);
+
+        let mut elf = connect(first, 1, "elf").await;
+        let mut santa = connect(second, 1, "santa").await;
+        send(&mut elf, json!({"message": "ho ho ho"})).await;
+        let frame = next_frame(&mut santa).await;
+        assert_eq!(frame["type"], "tweet");
+        assert_eq!(frame["user"], "elf");
+
+        // a late joiner gets the tweet from the log only once
+        let url = format!("ws://{first}/ws/room/1/user/grinch?last=5");
+        let (mut grinch, _) = connect_async(url).await.unwrap();
+        send(&mut elf, json!({"message": "again"})).await;
+        let mut tweets = Vec::new();
+        while tweets.len() < 2 {
+            let frame = next_frame(&mut grinch).await;
+            if frame["type"] == "tweet" {
+                tweets.push(frame["message"].clone());
+            }
+        }
+        assert_eq!(tweets, vec!["ho ho ho", "again"]);
+    }
+
+    #[tokio::test]
+    async fn ws_shutdown() {
+        let sockets = Sockets::default();
+        let addr = serve_app(task(MyState::memory(), sockets.clone(), &trusting())).await;
+        let mut elf = connect(addr, 1, "elf").await;
+        let (mut game, _) = connect_async(format!("ws://{addr}/ws/ping")).await.unwrap();
+
+        timeout(std::time::Duration::from_secs(5), sockets.shutdown())
+            .await
+            .unwrap();
+        expect_close(&mut elf, 1001).await;
+        expect_close(&mut game, 1001).await;
+    }
+
+    #[tokio::test]
+    async fn ws_invalid_frames_close() {
+        let addr = serve().await;
+
+        let mut elf = connect(addr, 1, "elf").await;
+        elf.send(WsMessage::Text("not json".to_string()))
+            .await
+            .unwrap();
+        let frame = next_frame(&mut elf).await;
+        assert_eq!(frame["type"], "error");
+        assert_eq!(frame["code"], "invalid_frame");
+        expect_close(&mut elf, 1007).await;
+
+        let mut elf = connect(addr, 1, "elf").await;
+        send(&mut elf, json!({"v": 2, "type": "tweet", "message": "ho"})).await;
+        let frame = next_frame(&mut elf).await;
+        assert_eq!(frame["code"], "unsupported_version");
+        expect_close(&mut elf, 1002).await;
+    }
+
+    #[tokio::test]
+    async fn history_pages() {
+        let log = Arc::new(MemoryLog::default());
+        for i in 0..5 {
+            log.append(1, "elf", &format!("tweet {i}")).await.unwrap();
+        }
+        let state = GameState::new(
+            Backend {
+                log,
+                ..Backend::memory(&ChatConfig::default())
+            },
+            Sockets::default(),
+            &ChatConfig::default(),
+        );
+        let app = Router::new()
+            .route("/rooms/:room/messages", get(room_messages))
+            .with_state(state);
+        let server = TestServer::new(app).unwrap();
+
+        let response = server
+            .get("/rooms/1/messages")
+            .add_query_param("limit", 2)
+            .await;
+        response.assert_status_ok();
+        let page = response.json::<HistoryPage>();
+        let messages: Vec<_> = page.items.iter().map(|m| m.message.as_str()).collect();
+        assert_eq!(messages, vec!["tweet 3", "tweet 4"]);
+        assert_eq!(page.next_before, Some(4));
+
+        let response = server
+            .get("/rooms/1/messages")
+            .add_query_param("limit", 3)
+            .add_query_param("before", 4)
+            .await;
+        let page = response.json::<HistoryPage>();
+        let messages: Vec<_> = page.items.iter().map(|m| m.message.as_str()).collect();
+        assert_eq!(messages, vec!["tweet 0", "tweet 1", "tweet 2"]);
+        assert_eq!(page.next_before, None);
+
+        let response = server.get("/rooms/2/messages").await;
+        assert!(response.json::<HistoryPage>().items.is_empty());
+
+        let response = server
+            .get("/rooms/1/messages")
+            .add_query_param("limit", 0)
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn rooms_and_users() {
+        let state = GameState::new(
+            Backend::memory(&ChatConfig::default()),
+            Sockets::default(),
+            &ChatConfig::default(),
+        );
+        let (room, _rx) = state.join(2, "santa").await;
+        state.join(1, "elf").await;
+        state.join(2, "elf").await;
+        let app = Router::new()
+            .route("/rooms", get(list_rooms))
+            .route("/rooms/:room/users", get(room_users))
+            .with_state(state.clone());
+        let server = TestServer::new(app).unwrap();
+
+        let response = server.get("/rooms").await;
+        response.assert_status_ok();
+        assert_eq!(
+            response.json::<Vec<RoomSummary>>(),
+            vec![
+                RoomSummary { room: 1, users: 1 },
+                RoomSummary { room: 2, users: 2 },
+            ]
+        );
+
+        let response = server.get("/rooms/2/users").await;
+        response.assert_status_ok();
+        assert_eq!(response.json::<Vec<String>>(), vec!["elf", "santa"]);
+
+        // the room is torn down with its last user
+        state.leave(2, &room, "santa").await;
+        state.leave(2, &room, "elf").await;
+        let response = server.get("/rooms/2/users").await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn activity_per_minute() {
+        let log = Arc::new(MemoryLog::default());
+        for room in [1, 1, 2] {
+            log.append(room, "elf", "tweet").await.unwrap();
+        }
+        let app = Router::new()
+            .route("/activity", get(activity))
+            .with_state(GameState::new(
+                Backend {
+                    log,
+                    ..Backend::memory(&ChatConfig::default())
+                },
+                Sockets::default(),
+                &ChatConfig::default(),
+            ));
+        let server = TestServer::new(app).unwrap();
+
+        let response = server.get("/activity").add_query_param("minutes", 5).await;
+        response.assert_status_ok();
+        let series = response.json::<Vec<MinuteCount>>();
+        assert_eq!(series.len(), 5);
+        assert_eq!(series.iter().map(|point| point.messages).sum::<i64>(), 3);
+
+        let response = server
+            .get("/activity")
+            .add_query_param("room", 1)
+            .add_query_param("minutes", 5)
+            .await;
+        let series = response.json::<Vec<MinuteCount>>();
+        assert_eq!(series.iter().map(|point| point.messages).sum::<i64>(), 2);
+
+        let response = server.get("/activity").add_query_param("minutes", 0).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn moderation() {
+        let config = ChatConfig {
+            rate_limit: Some(1),
+            ..ChatConfig::default()
+        };
+        let state = GameState::new(
+            Backend::memory(&ChatConfig::default()),
+            Sockets::default(),
+            &config,
+        );
+        state.join(1, "santa").await;
+        state.join(1, "elf").await;
+
+        assert!(state.moderate(1, "elf", Action::Ban, "grinch").is_err());
+        state.moderate(1, "santa", Action::Ban, "grinch").unwrap();
+        state.moderate(1, "santa", Action::Mute, "elf").unwrap();
+        assert!(state.is_banned(1, "grinch"));
+        assert!(!state.is_banned(2, "grinch"));
+
+        assert!(state.check_message(1, "elf", "ho ho ho").is_err());
+        assert!(state.check_message(1, "santa", &"ho".repeat(100)).is_err());
+        state.check_message(1, "santa", "ho ho ho").unwrap();
+        // over the rate limit
+        assert!(state.check_message(1, "santa", "ho ho ho").is_err());
+
+        let app = Router::new()
+            .route("/rooms/:room/moderation", get(room_moderation))
+            .with_state(state);
+        let server = TestServer::new(app).unwrap();
+
+        let response = server.get("/rooms/1/moderation").await;
+        response.assert_status_ok();
+        let moderation = response.json::<Value>();
+        assert_eq!(moderation["owner"], "santa");
+        assert_eq!(moderation["banned"], serde_json::json!(["grinch"]));
+        assert_eq!(moderation["muted"], serde_json::json!(["elf"]));
+
+        let response = server.get("/rooms/2/moderation").await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+
+    /// Wait for the bus to deliver the events that make `done` true.
+    async fn until(done: impl Fn() -> bool) {
+        let wait = async {
+            while !done() {
+                tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            }
+        };
+        timeout(std::time::Duration::from_secs(5), wait)
+            .await
+            .expect("the bus did not deliver within 5s");
+    }
+
+    #[tokio::test]
+    async fn instances_share_moderation() {
+        // two instances with the same database
+        let config = ChatConfig {
+            rate_limit: Some(1),
+            ..ChatConfig::default()
+        };
+        let backend = Backend::memory(&config);
+        let first = GameState::new(backend.clone(), Sockets::default(), &config);
+        let second = GameState::new(backend, Sockets::default(), &config);
+
+        let (santa_room, _santa) = first.join(1, "santa").await;
+        let (elf_room, _elf) = second.join(1, "elf").await;
+        until(|| second.presence.lock().unwrap().contains(1, "santa")).await;
+        assert_eq!(
+            first.presence.lock().unwrap().users(1).unwrap(),
+            vec!["elf", "santa"]
+        );
+        // santa joined first on the bus, whatever each instance saw first
+        until(|| second.moderate(1, "santa", Action::Mute, "elf").is_ok()).await;
+        assert!(second.moderate(1, "elf", Action::Ban, "grinch").is_err());
+
+        first.check_message(1, "santa", "ho ho ho").unwrap();
+        let tweet = ChatMessage {
+            id: 1,
+            user: "santa".to_string(),
+            message: "ho ho ho".to_string(),
+            sent_at: Utc::now(),
+        };
+        first.publish(1, RoomEvent::Message(tweet)).await.unwrap();
+        first.moderate(1, "santa", Action::Ban, "grinch").unwrap();
+        let ban = RoomEvent::Moderation {
+            user: "grinch".to_string(),
+            action: Action::Ban,
+        };
+        first.publish(1, ban).await.unwrap();
+        // the bus keeps the order of the events
+        until(|| second.is_banned(1, "grinch")).await;
+        assert!(second.check_message(1, "santa", "ho ho ho").is_err());
+
+        // the moderation of a room goes with its last user
+        first.leave(1, &santa_room, "santa").await;
+        second.leave(1, &elf_room, "elf").await;
+        until(|| {
+            first.moderation.lock().unwrap().is_empty()
+                && second.moderation.lock().unwrap().is_empty()
+        })
+        .await;
+        assert!(first.presence.lock().unwrap().is_empty(1));
+        assert!(!second.is_banned(1, "grinch"));
+    }
+
+    async fn next_text(ws: &mut Ws) -> String {
+        let message = timeout(std::time::Duration::from_secs(5), ws.next())
+            .await
+            .expect("no frame within 5s")
+            .unwrap()
+            .unwrap();
+        message.into_text().unwrap()
+    }
+
+    /// Read game states until the game is in `state`.
+    async fn until_state(ws: &mut Ws, state: &str) -> Value {
+        loop {
+            let frame = next_frame(ws).await;
+            if frame["state"] == state {
+                return frame;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn ws_solo_game() {
+        let addr = serve().await;
+        let (mut ws, _) = connect_async(format!("ws://{addr}/ws/ping")).await.unwrap();
+        let (mut other, _) = connect_async(format!("ws://{addr}/ws/ping")).await.unwrap();
+
+        // nothing before the serve
+        ws.send(WsMessage::Text("ping".to_string())).await.unwrap();
+        ws.send(WsMessage::Text("serve".to_string())).await.unwrap();
+        ws.send(WsMessage::Text("ping".to_string())).await.unwrap();
+        assert_eq!(next_text(&mut ws).await, "pong");
+
+        // the other game hasn't started
+        other
+            .send(WsMessage::Text("ping".to_string()))
+            .await
+            .unwrap();
+        other
+            .send(WsMessage::Text("serve".to_string()))
+            .await
+            .unwrap();
+        other
+            .send(WsMessage::Text("ping".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(next_text(&mut other).await, "pong");
+
+        // a miss ends the rally until the next serve
+        ws.send(WsMessage::Text("oops".to_string())).await.unwrap();
+        ws.send(WsMessage::Text("ping".to_string())).await.unwrap();
+        ws.send(WsMessage::Text("serve".to_string())).await.unwrap();
+        ws.send(WsMessage::Text("ping".to_string())).await.unwrap();
+        assert_eq!(next_text(&mut ws).await, "pong");
+    }
+
+    #[tokio::test]
+    async fn ws_two_player_game() {
+        let addr = serve().await;
+        let url = format!("ws://{addr}/ws/ping?players=2");
+        let (mut first, _) = connect_async(url.as_str()).await.unwrap();
+        assert_eq!(next_frame(&mut first).await["state"], "waiting");
+        let (mut second, _) = connect_async(url.as_str()).await.unwrap();
+
+        let frame = until_state(&mut second, "serving").await;
+        assert_eq!(frame["server"], 0);
+        until_state(&mut first, "serving").await;
+
+        first
+            .send(WsMessage::Text("serve".to_string()))
+            .await
+            .unwrap();
+        let frame = until_state(&mut second, "rally").await;
+        assert_eq!((&frame["ball"], &frame["hits"]), (&json!(1), &json!(0)));
+        second
+            .send(WsMessage::Text("ping".to_string()))
+            .await
+            .unwrap();
+        second
+            .send(WsMessage::Text("ping".to_string()))
+            .await
+            .unwrap();
+        let frame = until_state(&mut first, "rally").await;
+        if frame["ball"] == 1 {
+            until_state(&mut first, "rally").await;
+        }
+        first
+            .send(WsMessage::Text("miss".to_string()))
+            .await
+            .unwrap();
+        let frame = until_state(&mut second, "serving").await;
+        assert_eq!(frame["score"], json!([0, 1]));
+        assert_eq!(frame["server"], 0);
+
+        let status = refused(format!("ws://{addr}/ws/ping?players=3")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // the player who stays wins
+        second.close(None).await.unwrap();
+        let frame = until_state(&mut first, "over").await;
+        assert_eq!(frame["winner"], 0);
+        let message = timeout(std::time::Duration::from_secs(5), first.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(message.is_close());
+    }
+
+    #[tokio::test]
+    async fn games() {
+        let server = TestServer::new(task(
+            MyState::memory(),
+            Sockets::default(),
+            &ChatConfig::default(),
+        ))
+        .unwrap();
+
+        let response = server.get("/games/1").await;
+        response.assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn first_login_claims_the_name() {
+        let server = TestServer::new(task(
+            MyState::memory(),
+            Sockets::default(),
+            &ChatConfig::default(),
+        ))
+        .unwrap();
+        let login = |password: &str| {
+            server
+                .post("/login")
+                .json(&json!({"user": "elf", "password": password}))
+        };
+
+        let response = login("cookies").await;
+        response.assert_status_ok();
+        let token = response.json::<LoginResponse>();
+        assert_eq!(token.user, "elf");
+        assert!(token.expires_at > Utc::now());
+
+        let response = login("milk").await;
+        assert_problem(&response, StatusCode::UNAUTHORIZED, "unauthorized");
+        let response = login("").await;
+        assert_problem(&response, StatusCode::BAD_REQUEST, "bad_request");
+    }
+
+    #[tokio::test]
+    async fn ws_tokens() {
+        let config = ChatConfig {
+            secret: Some("secret".to_string()),
+            ..ChatConfig::default()
+        };
+        let addr = serve_app(task(MyState::memory(), Sockets::default(), &config)).await;
+        let tokens = Tokens::from_config(&config);
+        let (elf, _) = tokens.issue("elf", Utc::now());
+
+        // the user comes from the token
+        let url = format!("ws://{addr}/ws/room/1?token={elf}");
+        let (mut ws, _) = connect_async(url).await.unwrap();
+        let frame = next_frame(&mut ws).await;
+        assert_eq!(
+            (&frame["type"], &frame["user"]),
+            (&json!("join"), &json!("elf"))
+        );
+
+        let url = format!("ws://{addr}/ws/room/1/user/santa");
+        assert_eq!(refused(url).await, StatusCode::UNAUTHORIZED);
+        let url = format!("ws://{addr}/ws/room/1/user/santa?token={elf}");
+        assert_eq!(refused(url).await, StatusCode::FORBIDDEN);
+        let url = format!("ws://{addr}/ws/room/1?token=not.valid");
+        assert_eq!(refused(url).await, StatusCode::UNAUTHORIZED);
+
+        // browsers can only pass the token as a protocol
+        let (santa, _) = tokens.issue("santa", Utc::now());
+        let mut request = format!("ws://{addr}/ws/room/1/user/santa")
+            .into_client_request()
+            .unwrap();
+        request.headers_mut().insert(
+            "sec-websocket-protocol",
+            format!("bearer, {santa}").parse().unwrap(),
+        );
+        let (mut ws, response) = connect_async(request).await.unwrap();
+        assert_eq!(response.headers()["sec-websocket-protocol"], "bearer");
+        assert_eq!(next_frame(&mut ws).await["user"], "santa");
+    }
+}
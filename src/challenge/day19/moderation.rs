@@ -0,0 +1,231 @@
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    challenge::{day15::RegexRule, error::AppError},
+    config::ChatConfig,
+};
+
+/// Decides whether a message may be sent to a room.
+pub trait ContentFilter: Send + Sync {
+    /// `Err` with the reason the message is rejected.
+    fn check(&self, message: &str) -> Result<(), String>;
+}
+
+/// Rejects messages that are too long or break one of the rules.
+pub struct RuleFilter {
+    max_len: usize,
+    rules: Vec<RegexRule>,
+}
+
+impl RuleFilter {
+    pub fn new(max_len: usize, rules: Vec<RegexRule>) -> Self {
+        Self { max_len, rules }
+    }
+
+    pub fn from_config(config: &ChatConfig) -> Result<Self, AppError> {
+        let rules = config
+            .blocked_patterns
+            .iter()
+            .map(|pattern| RegexRule::forbid(pattern, &format!("matches `{pattern}`")))
+            .collect::<Result<_, _>>()?;
+        Ok(Self::new(config.max_message_len, rules))
+    }
+}
+
+impl ContentFilter for RuleFilter {
+    fn check(&self, message: &str) -> Result<(), String> {
+        if message.len() > self.max_len {
+            return Err(format!("longer than {} bytes", self.max_len));
+        }
+        for rule in &self.rules {
+            match rule.passes(message) {
+                Ok(true) => {}
+                Ok(false) => return Err(rule.reason().to_string()),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// At most `max` messages per user within any `window`.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub max: usize,
+    pub window: Duration,
+}
+
+impl RateLimit {
+    pub fn from_config(config: &ChatConfig) -> Option<Self> {
+        config.rate_limit.map(|max| Self {
+            max,
+            window: Duration::from_secs(config.rate_window_secs),
+        })
+    }
+}
+
+/// What a room owner can do to another user.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Mute,
+    Unmute,
+    Ban,
+    Unban,
+}
+
+/// Owner, muted and banned users of a room. Dropped once nobody is left in
+/// the room on any instance.
+#[derive(Serialize, Clone, Debug)]
+pub struct Moderation {
+    /// The first user who joined the room.
+    pub owner: String,
+    pub muted: BTreeSet<String>,
+    pub banned: BTreeSet<String>,
+    /// Whether the owner is the first join published to the bus, rather than
+    /// the first one seen by this instance.
+    #[serde(skip)]
+    confirmed: bool,
+    /// When each user's recent messages were sent, oldest first.
+    #[serde(skip)]
+    sent: HashMap<String, VecDeque<Instant>>,
+}
+
+impl Moderation {
+    pub fn new(owner: &str) -> Self {
+        Self {
+            owner: owner.to_string(),
+            muted: BTreeSet::new(),
+            banned: BTreeSet::new(),
+            confirmed: false,
+            sent: HashMap::new(),
+        }
+    }
+
+    /// Record a join published to the bus: instances may each have seen a
+    /// different user first, the first join on the bus owns the room.
+    pub fn joined(&mut self, user: &str) {
+        if !self.confirmed {
+            self.owner = user.to_string();
+            self.confirmed = true;
+        }
+    }
+
+    /// `Err` with the reason when `by` may not apply `action` to `user`.
+    pub fn apply(&mut self, by: &str, action: Action, user: &str) -> Result<(), String> {
+        if by != self.owner {
+            return Err(format!("only {} can moderate the room", self.owner));
+        }
+        if user == self.owner {
+            return Err("the owner can't be moderated".to_string());
+        }
+        self.enforce(action, user);
+        Ok(())
+    }
+
+    /// Apply an action the owner took, possibly through another instance.
+    pub fn enforce(&mut self, action: Action, user: &str) {
+        let user = user.to_string();
+        match action {
+            Action::Mute => self.muted.insert(user),
+            Action::Unmute => self.muted.remove(&user),
+            Action::Ban => self.banned.insert(user),
+            Action::Unban => self.banned.remove(&user),
+        };
+    }
+
+    /// Record a message of `user` sent at `now` unless it exceeds `limit`.
+    pub fn allow(&mut self, user: &str, limit: &RateLimit, now: Instant) -> bool {
+        let sent = self.recent(user, limit, now);
+        if sent.len() >= limit.max {
+            return false;
+        }
+        sent.push_back(now);
+        true
+    }
+
+    /// Record a message `user` sent through another instance, which already
+    /// allowed it.
+    pub fn count(&mut self, user: &str, limit: &RateLimit, now: Instant) {
+        self.recent(user, limit, now).push_back(now);
+    }
+
+    /// The messages of `user` within the window ending at `now`.
+    fn recent(&mut self, user: &str, limit: &RateLimit, now: Instant) -> &mut VecDeque<Instant> {
+        let sent = self.sent.entry(user.to_string()).or_default();
+        while sent
+            .front()
+            .is_some_and(|time| now.duration_since(*time) >= limit.window)
+        {
+            sent.pop_front();
+        }
+        sent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn owner_moderates() {
+        let mut moderation = Moderation::new("santa");
+
+        assert!(moderation.apply("elf", Action::Ban, "grinch").is_err());
+        assert!(moderation.apply("santa", Action::Mute, "santa").is_err());
+
+        moderation.apply("santa", Action::Ban, "grinch").unwrap();
+        moderation.apply("santa", Action::Mute, "elf").unwrap();
+        moderation.apply("santa", Action::Unmute, "elf").unwrap();
+        assert!(moderation.banned.contains("grinch"));
+        assert!(moderation.muted.is_empty());
+    }
+
+    #[test]
+    fn rate_limit() {
+        let mut moderation = Moderation::new("santa");
+        let limit = RateLimit {
+            max: 2,
+            window: Duration::from_secs(10),
+        };
+        let start = Instant::now();
+
+        assert!(moderation.allow("elf", &limit, start));
+        assert!(moderation.allow("elf", &limit, start + Duration::from_secs(1)));
+        assert!(!moderation.allow("elf", &limit, start + Duration::from_secs(2)));
+        assert!(moderation.allow("santa", &limit, start + Duration::from_secs(2)));
+        assert!(moderation.allow("elf", &limit, start + Duration::from_secs(10)));
+
+        // messages sent through other instances count too
+        moderation.count("santa", &limit, start + Duration::from_secs(3));
+        assert!(!moderation.allow("santa", &limit, start + Duration::from_secs(4)));
+    }
+
+    #[test]
+    fn first_join_on_the_bus_owns() {
+        let mut moderation = Moderation::new("elf");
+
+        moderation.joined("santa");
+        moderation.joined("elf");
+        assert_eq!(moderation.owner, "santa");
+    }
+
+    #[test]
+    fn rule_filter() {
+        let config = ChatConfig {
+            max_message_len: 10,
+            blocked_patterns: vec![r"(?i)grinch".to_string()],
+            ..ChatConfig::default()
+        };
+        let filter = RuleFilter::from_config(&config).unwrap();
+
+        assert!(filter.check("ho ho ho").is_ok());
+        assert!(filter.check("the Grinch").is_err());
+        assert!(filter.check("ho ho ho ho ho").is_err());
+    }
+}
@@ -0,0 +1,287 @@
+//! Frames of the chat WebSocket. Typed frames are JSON objects tagged by
+//! `type`, with the protocol version in `v` and an optional `ref` the server
+//! echoes in its `ack` or `error` reply:
+//!
+//! ```json
+//! {"v": 1, "type": "tweet", "ref": "1", "message": "ho ho ho"}
+//! ```
+//!
+//! Frames without `type` are read the way the first clients sent them, e.g.
+//! `{"message": "ho ho ho"}`, and are only answered when they fail. Frames
+//! that can't be read are answered with an `error` and the connection is
+//! closed.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{history::HistoryPage, moderation::Action, room::RoomEvent};
+
+pub const VERSION: u32 = 1;
+
+fn version() -> u32 {
+    VERSION
+}
+
+/// A frame with the protocol version and the client's reference.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Envelope<T> {
+    #[serde(default = "version")]
+    pub v: u32,
+    #[serde(rename = "ref", default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    #[serde(flatten)]
+    pub body: T,
+}
+
+impl<T> Envelope<T> {
+    pub fn new(reference: Option<String>, body: T) -> Self {
+        Self {
+            v: VERSION,
+            reference,
+            body,
+        }
+    }
+}
+
+/// What a client can send.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientFrame {
+    Tweet {
+        message: String,
+    },
+    Dm {
+        to: String,
+        message: String,
+    },
+    /// The `limit` newest messages, or the newest ones before the id `before`.
+    History {
+        before: Option<i64>,
+        limit: Option<usize>,
+    },
+    Typing {
+        typing: bool,
+    },
+    Moderate {
+        action: Action,
+        user: String,
+    },
+}
+
+/// Answers to one client, as opposed to the events of its room.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Reply {
+    History(HistoryPage),
+    /// The frame was handled; `id` is the one of a stored tweet.
+    Ack {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<i64>,
+    },
+    Error {
+        code: String,
+        reason: String,
+    },
+}
+
+impl Reply {
+    pub fn error(code: &str, reason: impl Into<String>) -> Self {
+        Reply::Error {
+            code: code.to_string(),
+            reason: reason.into(),
+        }
+    }
+}
+
+/// Everything the server sends.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum ServerFrame {
+    Event(RoomEvent),
+    Reply(Reply),
+}
+
+/// A frame that breaks the protocol; the connection is closed after it is
+/// reported.
+#[derive(Debug, thiserror::Error)]
+pub enum ProtocolError {
+    #[error("invalid frame: {0}")]
+    InvalidFrame(String),
+    #[error("unsupported protocol version {0}, expected {VERSION}")]
+    UnsupportedVersion(u32),
+    #[error("binary frames are not supported")]
+    Binary,
+}
+
+impl ProtocolError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProtocolError::InvalidFrame(_) => "invalid_frame",
+            ProtocolError::UnsupportedVersion(_) => "unsupported_version",
+            ProtocolError::Binary => "unsupported_data",
+        }
+    }
+
+    /// WebSocket close code, see RFC 6455 section 7.4.1.
+    pub fn close_code(&self) -> u16 {
+        match self {
+            ProtocolError::InvalidFrame(_) => 1007,
+            ProtocolError::UnsupportedVersion(_) => 1002,
+            ProtocolError::Binary => 1003,
+        }
+    }
+}
+
+/// A text frame as read from a client.
+#[derive(Debug, PartialEq)]
+pub enum Parsed {
+    Typed(Envelope<ClientFrame>),
+    /// A frame without `type`, which is answered only when it fails.
+    Legacy(ClientFrame),
+    /// A frame without effect, like a tweet a client echoes back with its
+    /// `user`.
+    Ignored,
+}
+
+pub fn parse(text: &str) -> Result<Parsed, ProtocolError> {
+    let value = serde_json::from_str::<Value>(text)
+        .map_err(|e| ProtocolError::InvalidFrame(e.to_string()))?;
+    if value.get("type").is_none() {
+        return legacy(&value);
+    }
+
+    let envelope = serde_json::from_value::<Envelope<ClientFrame>>(value)
+        .map_err(|e| ProtocolError::InvalidFrame(e.to_string()))?;
+    if envelope.v != VERSION {
+        return Err(ProtocolError::UnsupportedVersion(envelope.v));
+    }
+    Ok(Parsed::Typed(envelope))
+}
+
+/// Read a frame of the first clients, which is told apart by its fields.
+fn legacy(value: &Value) -> Result<Parsed, ProtocolError> {
+    if let Some(typing) = value.get("typing").and_then(Value::as_bool) {
+        return Ok(Parsed::Legacy(ClientFrame::Typing { typing }));
+    }
+    let moderation = [
+        ("mute", Action::Mute),
+        ("unmute", Action::Unmute),
+        ("ban", Action::Ban),
+        ("unban", Action::Unban),
+    ]
+    .into_iter()
+    .find_map(|(key, action)| Some((action, value.get(key)?.as_str()?)));
+    if let Some((action, user)) = moderation {
+        return Ok(Parsed::Legacy(ClientFrame::Moderate {
+            action,
+            user: user.to_string(),
+        }));
+    }
+
+    let Some(message) = value.get("message").and_then(Value::as_str) else {
+        return Err(ProtocolError::InvalidFrame(
+            "expected a `type` or a `message`".to_string(),
+        ));
+    };
+    let message = message.to_string();
+    if let Some(to) = value.get("to").and_then(Value::as_str) {
+        return Ok(Parsed::Legacy(ClientFrame::Dm {
+            to: to.to_string(),
+            message,
+        }));
+    }
+    // only tweets are sent without the user
+    if value.get("user").is_some() {
+        return Ok(Parsed::Ignored);
+    }
+    Ok(Parsed::Legacy(ClientFrame::Tweet { message }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_frames() {
+        assert_eq!(
+            parse(r#"{"v":1,"type":"tweet","ref":"a","message":"ho"}"#).unwrap(),
+            Parsed::Typed(Envelope::new(
+                Some("a".to_string()),
+                ClientFrame::Tweet {
+                    message: "ho".to_string()
+                }
+            ))
+        );
+        // the version defaults to the current one
+        assert_eq!(
+            parse(r#"{"type":"history","limit":5}"#).unwrap(),
+            Parsed::Typed(Envelope::new(
+                None,
+                ClientFrame::History {
+                    before: None,
+                    limit: Some(5)
+                }
+            ))
+        );
+
+        assert!(matches!(
+            parse(r#"{"v":2,"type":"tweet","message":"ho"}"#),
+            Err(ProtocolError::UnsupportedVersion(2))
+        ));
+        assert!(matches!(
+            parse(r#"{"type":"shout","message":"ho"}"#),
+            Err(ProtocolError::InvalidFrame(_))
+        ));
+        assert!(matches!(
+            parse("not json"),
+            Err(ProtocolError::InvalidFrame(_))
+        ));
+    }
+
+    #[test]
+    fn legacy_frames() {
+        let frame = |text| parse(text).unwrap();
+
+        assert_eq!(
+            frame(r#"{"message":"ho"}"#),
+            Parsed::Legacy(ClientFrame::Tweet {
+                message: "ho".to_string()
+            })
+        );
+        assert_eq!(frame(r#"{"user":"elf","message":"ho"}"#), Parsed::Ignored);
+        assert_eq!(
+            frame(r#"{"to":"elf","message":"ho"}"#),
+            Parsed::Legacy(ClientFrame::Dm {
+                to: "elf".to_string(),
+                message: "ho".to_string()
+            })
+        );
+        assert_eq!(
+            frame(r#"{"mute":"elf"}"#),
+            Parsed::Legacy(ClientFrame::Moderate {
+                action: Action::Mute,
+                user: "elf".to_string()
+            })
+        );
+        assert!(parse(r#"{"messages":"ho"}"#).is_err());
+    }
+
+    #[test]
+    fn server_frames() {
+        let frame = ServerFrame::Reply(Reply::Ack { id: Some(3) });
+        let json = serde_json::to_value(Envelope::new(Some("a".to_string()), frame)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"v": 1, "ref": "a", "type": "ack", "id": 3})
+        );
+
+        let frame = ServerFrame::Event(RoomEvent::Join {
+            user: "elf".to_string(),
+        });
+        let json = serde_json::to_value(Envelope::new(None, frame)).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"v": 1, "type": "join", "user": "elf"})
+        );
+    }
+}
@@ -0,0 +1,230 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, Mutex as AsyncMutex};
+
+use super::{history::ChatMessage, moderation::Action};
+
+/// Events a room buffers for its slowest receiver; receivers further
+/// behind catch up on the tweets from the log.
+const ROOM_CAPACITY: usize = 64;
+
+/// What is pushed to the users of a room.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RoomEvent {
+    #[serde(rename = "tweet")]
+    Message(ChatMessage),
+    Join {
+        user: String,
+    },
+    Leave {
+        user: String,
+    },
+    Typing {
+        user: String,
+        typing: bool,
+    },
+    /// A private message, only pushed to its sender and recipient.
+    #[serde(rename = "dm")]
+    Direct {
+        from: String,
+        to: String,
+        message: String,
+        sent_at: DateTime<Utc>,
+    },
+    Moderation {
+        user: String,
+        action: Action,
+    },
+}
+
+impl RoomEvent {
+    /// Whether the event is pushed to the connections of `user`.
+    pub fn is_for(&self, user: &str) -> bool {
+        match self {
+            RoomEvent::Direct { from, to, .. } => from == user || to == user,
+            _ => true,
+        }
+    }
+}
+
+/// A room and the users connected to it.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RoomSummary {
+    pub room: u32,
+    pub users: usize,
+}
+
+pub struct Room {
+    /// Events of the bus and the presence of the users of this instance.
+    pub tx: broadcast::Sender<RoomEvent>,
+    /// Held from appending a tweet to the log until it is published, so
+    /// receivers get the tweets sent through this instance in id order.
+    pub sending: AsyncMutex<()>,
+    /// Open connections to this instance per user, a user may join from
+    /// several clients.
+    connections: Mutex<HashMap<String, usize>>,
+}
+
+impl Room {
+    pub fn new() -> Self {
+        let (tx, _rx) = broadcast::channel(ROOM_CAPACITY);
+        Self {
+            tx,
+            sending: AsyncMutex::new(()),
+            connections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count a new connection of `user`. Returns whether it is their first
+    /// one to this instance.
+    pub fn join(&self, user: &str) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+        let count = connections.entry(user.to_string()).or_default();
+        *count += 1;
+        *count == 1
+    }
+
+    /// Drop a connection of `user`. Returns whether it was their last one to
+    /// this instance.
+    pub fn leave(&self, user: &str) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+        let Some(count) = connections.get_mut(user) else {
+            return false;
+        };
+        *count -= 1;
+        if *count > 0 {
+            return false;
+        }
+        connections.remove(user);
+        true
+    }
+
+    /// Whether nobody is connected to the room through this instance.
+    pub fn is_empty(&self) -> bool {
+        self.connections.lock().unwrap().is_empty()
+    }
+}
+
+/// Users of the rooms on every instance, as announced on the bus: each user
+/// is counted once per instance they are connected to.
+#[derive(Default)]
+pub struct Presence {
+    rooms: HashMap<u32, HashMap<String, usize>>,
+}
+
+impl Presence {
+    /// Count `user` on one more instance. Returns whether they just joined
+    /// the room.
+    pub fn join(&mut self, room: u32, user: &str) -> bool {
+        let count = self
+            .rooms
+            .entry(room)
+            .or_default()
+            .entry(user.to_string())
+            .or_default();
+        *count += 1;
+        *count == 1
+    }
+
+    /// Count `user` on one less instance. Returns whether they just left the
+    /// room; a room nobody is in is forgotten.
+    pub fn leave(&mut self, room: u32, user: &str) -> bool {
+        let Some(users) = self.rooms.get_mut(&room) else {
+            return false;
+        };
+        let Some(count) = users.get_mut(user) else {
+            return false;
+        };
+        *count -= 1;
+        if *count > 0 {
+            return false;
+        }
+        users.remove(user);
+        if users.is_empty() {
+            self.rooms.remove(&room);
+        }
+        true
+    }
+
+    pub fn contains(&self, room: u32, user: &str) -> bool {
+        self.rooms
+            .get(&room)
+            .is_some_and(|users| users.contains_key(user))
+    }
+
+    pub fn is_empty(&self, room: u32) -> bool {
+        !self.rooms.contains_key(&room)
+    }
+
+    /// Names of the users of `room`, sorted, `None` when nobody is in it.
+    pub fn users(&self, room: u32) -> Option<Vec<String>> {
+        let mut users: Vec<_> = self.rooms.get(&room)?.keys().cloned().collect();
+        users.sort();
+        Some(users)
+    }
+
+    /// Every room somebody is in, sorted.
+    pub fn rooms(&self) -> Vec<RoomSummary> {
+        let mut rooms: Vec<_> = self
+            .rooms
+            .iter()
+            .map(|(&room, users)| RoomSummary {
+                room,
+                users: users.len(),
+            })
+            .collect();
+        rooms.sort_by_key(|summary| summary.room);
+        rooms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connections() {
+        let room = Room::new();
+
+        assert!(room.join("elf"));
+        assert!(room.join("santa"));
+        assert!(!room.join("elf"));
+
+        assert!(!room.leave("elf"));
+        assert!(room.leave("santa"));
+        assert!(!room.is_empty());
+        assert!(room.leave("elf"));
+        assert!(room.is_empty());
+        assert!(!room.leave("elf"));
+    }
+
+    #[test]
+    fn presence() {
+        let mut presence = Presence::default();
+
+        // elf is connected to two instances
+        assert!(presence.join(1, "elf"));
+        assert!(!presence.join(1, "elf"));
+        assert!(presence.join(1, "santa"));
+        assert!(presence.join(2, "elf"));
+        assert_eq!(presence.users(1).unwrap(), vec!["elf", "santa"]);
+        assert_eq!(
+            presence.rooms(),
+            vec![
+                RoomSummary { room: 1, users: 2 },
+                RoomSummary { room: 2, users: 1 },
+            ]
+        );
+
+        assert!(!presence.leave(1, "elf"));
+        assert!(presence.contains(1, "elf"));
+        assert!(presence.leave(1, "elf"));
+        assert!(presence.leave(1, "santa"));
+        assert!(presence.is_empty(1));
+        assert_eq!(presence.users(1), None);
+        assert!(!presence.leave(1, "santa"));
+    }
+}
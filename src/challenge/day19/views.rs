@@ -0,0 +1,103 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use serde::{Deserialize, Serialize};
+
+/// Query string of the view endpoints; without filters every view counts.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct ViewQuery {
+    pub room: Option<u32>,
+    /// Author of the viewed tweets.
+    pub user: Option<String>,
+}
+
+/// Views of the tweets of one author in one room.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ViewCount {
+    pub room: u32,
+    pub user: String,
+    pub views: usize,
+}
+
+/// Tweets delivered to a client, counted per room and author.
+#[derive(Default)]
+pub struct Views {
+    counts: Mutex<HashMap<(u32, String), usize>>,
+}
+
+impl Views {
+    pub fn record(&self, room: u32, author: &str) {
+        let mut counts = self.counts.lock().unwrap();
+        *counts.entry((room, author.to_string())).or_default() += 1;
+    }
+
+    pub fn reset(&self) {
+        self.counts.lock().unwrap().clear();
+    }
+
+    pub fn total(&self, query: &ViewQuery) -> usize {
+        self.breakdown(query).iter().map(|count| count.views).sum()
+    }
+
+    /// Views per room and author matching `query`, sorted by room then
+    /// author.
+    pub fn breakdown(&self, query: &ViewQuery) -> Vec<ViewCount> {
+        let counts = self.counts.lock().unwrap();
+        let mut breakdown: Vec<_> = counts
+            .iter()
+            .filter(|((room, user), _)| {
+                query.room.is_none_or(|r| *room == r)
+                    && query.user.as_ref().is_none_or(|u| user == u)
+            })
+            .map(|((room, user), views)| ViewCount {
+                room: *room,
+                user: user.clone(),
+                views: *views,
+            })
+            .collect();
+        breakdown.sort_by(|a, b| (a.room, &a.user).cmp(&(b.room, &b.user)));
+        breakdown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters() {
+        let views = Views::default();
+        views.record(1, "elf");
+        views.record(1, "elf");
+        views.record(1, "santa");
+        views.record(2, "elf");
+
+        assert_eq!(views.total(&ViewQuery::default()), 4);
+        let query = ViewQuery {
+            room: Some(1),
+            user: None,
+        };
+        assert_eq!(views.total(&query), 3);
+        let query = ViewQuery {
+            room: None,
+            user: Some("elf".to_string()),
+        };
+        assert_eq!(
+            views.breakdown(&query),
+            vec![
+                ViewCount {
+                    room: 1,
+                    user: "elf".to_string(),
+                    views: 2,
+                },
+                ViewCount {
+                    room: 2,
+                    user: "elf".to_string(),
+                    views: 1,
+                },
+            ]
+        );
+
+        views.reset();
+        assert_eq!(views.total(&ViewQuery::default()), 0);
+    }
+}
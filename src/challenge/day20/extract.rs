@@ -0,0 +1,348 @@
+//! Limits on the entries of uploaded archives, and their extraction.
+
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    os::unix::fs::symlink,
+    path::{Component, Path, PathBuf},
+};
+
+use tar::{Archive, Entry, EntryType};
+
+use crate::config::ArchiveConfig;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExtractError {
+    #[error("more than {0} entries")]
+    TooManyEntries(usize),
+    #[error("more than {0} bytes once unpacked")]
+    TooLarge(u64),
+    #[error("entry `{0}` has an absolute path")]
+    AbsolutePath(String),
+    #[error("entry `{0}` leaves the archive")]
+    PathTraversal(String),
+    #[error("link `{path}` points outside the archive, to `{target}`")]
+    ExternalLink { path: String, target: String },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl ExtractError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ExtractError::TooManyEntries(_) => "too_many_entries",
+            ExtractError::TooLarge(_) => "unpacked_too_large",
+            ExtractError::AbsolutePath(_) => "absolute_path",
+            ExtractError::PathTraversal(_) => "path_traversal",
+            ExtractError::ExternalLink { .. } => "external_link",
+            ExtractError::Io(_) => "invalid_archive",
+        }
+    }
+}
+
+/// The target of a link entry.
+#[derive(Clone, Copy, Debug)]
+pub enum Link<'a> {
+    /// Relative to the directory of the link.
+    Symbolic(&'a Path),
+    /// Relative to the root of the archive.
+    Hard(&'a Path),
+}
+
+/// Checks the entries of an archive, in order, before they are read.
+#[derive(Debug)]
+pub struct Guard {
+    max_entries: usize,
+    max_unpacked_size: u64,
+    entries: usize,
+    unpacked: u64,
+}
+
+impl Guard {
+    pub fn new(config: &ArchiveConfig) -> Self {
+        Self {
+            max_entries: config.max_entries,
+            max_unpacked_size: config.max_unpacked_size,
+            entries: 0,
+            unpacked: 0,
+        }
+    }
+
+    /// Count an entry of `size` bytes, and return its path relative to the
+    /// root of the archive.
+    pub fn entry(&mut self, path: &Path, size: u64) -> Result<PathBuf, ExtractError> {
+        self.entries += 1;
+        if self.entries > self.max_entries {
+            return Err(ExtractError::TooManyEntries(self.max_entries));
+        }
+        self.unpacked = self.unpacked.saturating_add(size);
+        if self.unpacked > self.max_unpacked_size {
+            return Err(ExtractError::TooLarge(self.max_unpacked_size));
+        }
+        relative(path)
+    }
+
+    /// Check that the link at `path`, as returned by [`Guard::entry`],
+    /// stays inside the archive.
+    pub fn link(&self, path: &Path, link: Link) -> Result<(), ExtractError> {
+        let (base, target) = match link {
+            Link::Symbolic(target) => (path.parent().unwrap_or(Path::new("")), target),
+            Link::Hard(target) => (Path::new(""), target),
+        };
+        match resolve(base, target) {
+            Some(_) => Ok(()),
+            None => Err(ExtractError::ExternalLink {
+                path: path.display().to_string(),
+                target: target.display().to_string(),
+            }),
+        }
+    }
+
+    /// [`Guard::entry`] and [`Guard::link`] for a tar entry. Sparse files
+    /// count with their unpacked size.
+    pub fn tar_entry<R: Read>(&mut self, entry: &Entry<R>) -> Result<PathBuf, ExtractError> {
+        let path = self.entry(&entry.path()?, entry.size())?;
+        if let Some(target) = entry.link_name()? {
+            match entry.header().entry_type() {
+                EntryType::Symlink => self.link(&path, Link::Symbolic(&target))?,
+                EntryType::Link => self.link(&path, Link::Hard(&target))?,
+                _ => {}
+            }
+        }
+        Ok(path)
+    }
+}
+
+/// `path` without its `.` components, unless it is absolute or has `..`
+/// components.
+fn relative(path: &Path) -> Result<PathBuf, ExtractError> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                return Err(ExtractError::PathTraversal(path.display().to_string()))
+            }
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(ExtractError::AbsolutePath(path.display().to_string()))
+            }
+        }
+    }
+    Ok(relative)
+}
+
+/// Where `target` leads from the directory `base`, unless it leaves the
+/// root.
+fn resolve(base: &Path, target: &Path) -> Option<PathBuf> {
+    let mut resolved: Vec<_> = base.components().collect();
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => resolved.push(component),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                resolved.pop()?;
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(resolved.iter().collect())
+}
+
+/// Unpack a tar archive into the empty directory `dst`, checking every
+/// entry with a [`Guard`]. Nothing is written through a symlink, and only
+/// files, directories and links are unpacked.
+pub fn unpack<R: Read>(
+    archive: &mut Archive<R>,
+    dst: &Path,
+    config: &ArchiveConfig,
+) -> Result<(), ExtractError> {
+    let mut guard = Guard::new(config);
+    let mut links = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = guard.tar_entry(&entry)?;
+        if path.as_os_str().is_empty() {
+            continue;
+        }
+        let dest = dst.join(&path);
+        check_parents(dst, &path)?;
+        // replace, rather than follow, the symlink of an earlier entry
+        if fs::symlink_metadata(&dest).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            fs::remove_file(&dest)?;
+        }
+
+        match entry.header().entry_type() {
+            EntryType::Directory => fs::create_dir_all(&dest)?,
+            EntryType::Regular | EntryType::Continuous => {
+                create_parent(&dest)?;
+                io::copy(&mut entry, &mut File::create(&dest)?)?;
+            }
+            EntryType::Symlink => {
+                let target = entry.link_name()?.unwrap_or_default().into_owned();
+                create_parent(&dest)?;
+                symlink(target, &dest)?;
+                links.push(path);
+            }
+            EntryType::Link => {
+                let target = relative(&entry.link_name()?.unwrap_or_default())?;
+                check_parents(dst, &target)?;
+                create_parent(&dest)?;
+                // which may be a symlink
+                fs::hard_link(dst.join(target), &dest)?;
+                links.push(path);
+            }
+            _ => {}
+        }
+    }
+
+    // a link inside the archive may lead out through another one
+    let root = dst.canonicalize()?;
+    for path in links {
+        let link = dst.join(&path);
+        if let Ok(target) = link.canonicalize() {
+            if !target.starts_with(&root) {
+                return Err(ExtractError::ExternalLink {
+                    path: path.display().to_string(),
+                    target: fs::read_link(&link)?.display().to_string(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Fail when a parent directory of `path` under `dst` is a symlink, which
+/// writing or linking `path` would follow.
+fn check_parents(dst: &Path, path: &Path) -> Result<(), ExtractError> {
+    let mut parent = dst.to_path_buf();
+    for component in path.parent().into_iter().flat_map(Path::components) {
+        parent.push(component);
+        match fs::symlink_metadata(&parent) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(ExtractError::PathTraversal(path.display().to_string()))
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    Ok(())
+}
+
+fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tar::{Builder, Header};
+
+    use super::*;
+
+    fn config() -> ArchiveConfig {
+        ArchiveConfig {
+            max_entries: 4,
+            max_unpacked_size: 16,
+            ..ArchiveConfig::default()
+        }
+    }
+
+    /// Entries with the raw paths and link targets given, which the tar
+    /// builder would refuse.
+    fn tar(entries: &[(EntryType, &str, &str, &[u8])]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for (kind, path, target, content) in entries {
+            let mut header = Header::new_old();
+            let old = header.as_old_mut();
+            old.name[..path.len()].copy_from_slice(path.as_bytes());
+            old.linkname[..target.len()].copy_from_slice(target.as_bytes());
+            header.set_entry_type(*kind);
+            header.set_mode(0o644);
+            header.set_size(content.len() as u64);
+            header.set_cksum();
+            builder.append(&header, *content).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn unpack_tar(entries: &[(EntryType, &str, &str, &[u8])]) -> Result<(), ExtractError> {
+        let dst = tempfile::tempdir().unwrap();
+        let tar = tar(entries);
+        unpack(&mut Archive::new(tar.as_slice()), dst.path(), &config())
+    }
+
+    #[test]
+    fn unpacks() {
+        let dst = tempfile::tempdir().unwrap();
+        let tar = tar(&[
+            (EntryType::Directory, "./src/", "", b""),
+            (EntryType::Regular, "src/lib.rs", "", b"fn main() {}"),
+            (EntryType::Symlink, "src/main.rs", "lib.rs", b""),
+            (EntryType::Link, "lib.rs", "src/lib.rs", b""),
+        ]);
+        unpack(&mut Archive::new(tar.as_slice()), dst.path(), &config()).unwrap();
+
+        for path in ["src/lib.rs", "src/main.rs", "lib.rs"] {
+            let content = fs::read(dst.path().join(path)).unwrap();
+            assert_eq!(content, b"fn main() {}");
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let entry = (EntryType::Regular, "a", "", &b""[..]);
+        assert!(matches!(
+            unpack_tar(&[entry; 5]),
+            Err(ExtractError::TooManyEntries(4))
+        ));
+        assert!(matches!(
+            unpack_tar(&[(EntryType::Regular, "a", "", &[0; 17])]),
+            Err(ExtractError::TooLarge(16))
+        ));
+    }
+
+    #[test]
+    fn unsafe_paths() {
+        assert!(matches!(
+            unpack_tar(&[(EntryType::Regular, "/etc/passwd", "", b"")]),
+            Err(ExtractError::AbsolutePath(_))
+        ));
+        assert!(matches!(
+            unpack_tar(&[(EntryType::Regular, "src/../../evil", "", b"")]),
+            Err(ExtractError::PathTraversal(_))
+        ));
+        for (kind, target) in [
+            (EntryType::Symlink, "/etc"),
+            (EntryType::Symlink, "../.."),
+            (EntryType::Link, "../etc/passwd"),
+        ] {
+            assert!(matches!(
+                unpack_tar(&[(kind, "src/link", target, b"")]),
+                Err(ExtractError::ExternalLink { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn unsafe_links() {
+        // writing through a symlink that points inside the archive
+        assert!(matches!(
+            unpack_tar(&[
+                (EntryType::Symlink, "dir", ".", b""),
+                (EntryType::Regular, "dir/file", "", b""),
+            ]),
+            Err(ExtractError::PathTraversal(_))
+        ));
+        // `here/..` stays inside on paper, but `here` is the root
+        assert!(matches!(
+            unpack_tar(&[
+                (EntryType::Symlink, "here", ".", b""),
+                (EntryType::Symlink, "up", "here/..", b""),
+            ]),
+            Err(ExtractError::ExternalLink { .. })
+        ));
+    }
+}
@@ -0,0 +1,464 @@
+//! `POST /20/archive/manifest`: every entry of a tar, compressed tar or zip
+//! archive.
+
+use std::{
+    io::{self, Cursor, Read, Seek},
+    path::Path,
+};
+
+use axum::{body::Body, extract::State, http::HeaderMap};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use flate2::read::GzDecoder;
+use globset::GlobMatcher;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tar::{Archive, EntryType};
+use zip::ZipArchive;
+
+use super::{
+    extract::{ExtractError, Guard, Link},
+    glob, read_body, BodyReader,
+};
+use crate::{
+    challenge::{
+        error::AppError,
+        extract::{Json, Query},
+    },
+    config::ArchiveConfig,
+};
+
+/// Bytes read to recognize the format.
+const HEAD_LEN: u64 = 512;
+
+/// File type bits of a Unix mode.
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    #[serde(rename = "tar")]
+    Tar,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    #[serde(rename = "tar.zst")]
+    TarZst,
+    #[serde(rename = "zip")]
+    Zip,
+}
+
+impl Format {
+    /// Recognize an archive by its magic bytes. Anything else is read as
+    /// tar, whose oldest format has none.
+    fn detect(head: &[u8]) -> Self {
+        match head {
+            [0x1f, 0x8b, ..] => Self::TarGz,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Self::TarZst,
+            // an empty zip starts with its end of central directory
+            [b'P', b'K', 3, 4, ..] | [b'P', b'K', 5, 6, ..] => Self::Zip,
+            _ => Self::Tar,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    File,
+    Directory,
+    Symlink,
+    Hardlink,
+    Other,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// Relative to the root, without `.` components or the trailing `/` of
+    /// directories.
+    pub path: String,
+    pub size: u64,
+    /// Permission bits, unknown for zip entries not made on Unix.
+    pub mode: Option<u32>,
+    /// Zip entries record a local time, read as UTC.
+    pub mtime: Option<DateTime<Utc>>,
+    #[serde(rename = "type")]
+    pub kind: Kind,
+    pub link_target: Option<String>,
+    /// Of the content of files.
+    pub sha256: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Summary {
+    pub entries: usize,
+    pub files: usize,
+    pub directories: usize,
+    pub links: usize,
+    /// Size of the files.
+    pub total_size: u64,
+}
+
+impl Summary {
+    fn of(entries: &[Entry]) -> Self {
+        let mut summary = Self {
+            entries: entries.len(),
+            ..Self::default()
+        };
+        for entry in entries {
+            match entry.kind {
+                Kind::File => {
+                    summary.files += 1;
+                    summary.total_size += entry.size;
+                }
+                Kind::Directory => summary.directories += 1,
+                Kind::Symlink | Kind::Hardlink => summary.links += 1,
+                Kind::Other => {}
+            }
+        }
+        summary
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Manifest {
+    pub format: Format,
+    pub entries: Vec<Entry>,
+    pub summary: Summary,
+}
+
+/// Query string of `POST /archive/manifest`.
+#[derive(Deserialize, Debug)]
+pub struct ManifestQuery {
+    /// Only list the entries whose path matches, e.g. `src/**/*.rs`.
+    glob: Option<String>,
+}
+
+pub async fn manifest(
+    State(config): State<ArchiveConfig>,
+    Query(query): Query<ManifestQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<Manifest>, AppError> {
+    let glob = query.glob.as_deref().map(glob).transpose()?;
+
+    let guard = Guard::new(&config);
+    let manifest = read_body(&headers, body, config.max_body_size, move |reader| {
+        read_manifest(reader, glob.as_ref(), guard)
+    })
+    .await?;
+    Ok(Json(manifest))
+}
+
+/// Every entry of the archive, which the guard checks whether it matches or
+/// not.
+fn read_manifest(
+    mut reader: BodyReader,
+    glob: Option<&GlobMatcher>,
+    mut guard: Guard,
+) -> Result<Manifest, ExtractError> {
+    let mut head = Vec::new();
+    (&mut reader).take(HEAD_LEN).read_to_end(&mut head)?;
+    let format = Format::detect(&head);
+    let mut reader = Cursor::new(head).chain(reader);
+
+    let entries = match format {
+        Format::Tar => read_tar(reader, glob, &mut guard)?,
+        Format::TarGz => read_tar(GzDecoder::new(reader), glob, &mut guard)?,
+        Format::TarZst => read_tar(zstd::Decoder::new(reader)?, glob, &mut guard)?,
+        Format::Zip => {
+            // the directory of a zip is at its end, spool it to disk
+            let mut file = tempfile::tempfile()?;
+            io::copy(&mut reader, &mut file)?;
+            read_zip(file, glob, &mut guard)?
+        }
+    };
+    let summary = Summary::of(&entries);
+    Ok(Manifest {
+        format,
+        entries,
+        summary,
+    })
+}
+
+fn read_tar(
+    reader: impl Read,
+    glob: Option<&GlobMatcher>,
+    guard: &mut Guard,
+) -> Result<Vec<Entry>, ExtractError> {
+    let mut entries = Vec::new();
+    for entry in Archive::new(reader).entries()? {
+        let mut entry = entry?;
+        let path = guard.tar_entry(&entry)?.to_string_lossy().into_owned();
+        if !glob.is_none_or(|glob| glob.is_match(&path)) {
+            continue;
+        }
+
+        let header = entry.header();
+        let kind = match header.entry_type() {
+            EntryType::Regular | EntryType::Continuous => Kind::File,
+            EntryType::Directory => Kind::Directory,
+            EntryType::Symlink => Kind::Symlink,
+            EntryType::Link => Kind::Hardlink,
+            _ => Kind::Other,
+        };
+        let mode = header.mode()? & 0o7777;
+        let mtime = DateTime::from_timestamp(header.mtime()? as i64, 0);
+        let link_target = entry
+            .link_name()?
+            .map(|target| target.to_string_lossy().into_owned());
+        let sha256 = match kind {
+            Kind::File => Some(sha256(&mut entry)?),
+            _ => None,
+        };
+        entries.push(Entry {
+            path,
+            size: entry.size(),
+            mode: Some(mode),
+            mtime,
+            kind,
+            link_target,
+            sha256,
+        });
+    }
+    Ok(entries)
+}
+
+fn read_zip(
+    reader: impl Read + Seek,
+    glob: Option<&GlobMatcher>,
+    guard: &mut Guard,
+) -> Result<Vec<Entry>, ExtractError> {
+    let mut archive = ZipArchive::new(reader).map_err(io::Error::from)?;
+    let mut entries = Vec::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(io::Error::from)?;
+        let size = file.size();
+        let relative = guard.entry(Path::new(file.name()), size)?;
+
+        let unix_mode = file.unix_mode();
+        let kind = if file.is_dir() {
+            Kind::Directory
+        } else if unix_mode.is_some_and(|mode| mode & S_IFMT == S_IFLNK) {
+            Kind::Symlink
+        } else {
+            Kind::File
+        };
+        let modified = file.last_modified();
+        let mtime = NaiveDate::from_ymd_opt(
+            modified.year().into(),
+            modified.month().into(),
+            modified.day().into(),
+        )
+        .and_then(|date| {
+            date.and_hms_opt(
+                modified.hour().into(),
+                modified.minute().into(),
+                modified.second().into(),
+            )
+        })
+        .map(|mtime| Utc.from_utc_datetime(&mtime));
+
+        // the content is read up to its declared size, which the guard counted
+        let mut content = (&mut file).take(size);
+        // the target of a symlink is its content
+        let link_target = match kind {
+            Kind::Symlink => {
+                let mut target = String::new();
+                content.read_to_string(&mut target)?;
+                guard.link(&relative, Link::Symbolic(Path::new(&target)))?;
+                Some(target)
+            }
+            _ => None,
+        };
+        let path = relative.to_string_lossy().into_owned();
+        if !glob.is_none_or(|glob| glob.is_match(&path)) {
+            continue;
+        }
+        let sha256 = match kind {
+            Kind::File => Some(sha256(&mut content)?),
+            _ => None,
+        };
+        entries.push(Entry {
+            path,
+            size,
+            mode: unix_mode.map(|mode| mode & 0o7777),
+            mtime,
+            kind,
+            link_target,
+            sha256,
+        });
+    }
+    Ok(entries)
+}
+
+fn sha256(reader: &mut impl Read) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(reader, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use axum::http::StatusCode;
+    use axum_test::TestServer;
+    use flate2::{write::GzEncoder, Compression};
+    use zip::{write::FileOptions, ZipWriter};
+
+    use super::*;
+    use crate::{
+        challenge::{day20::task, error::assert_problem},
+        config::ArchiveConfig,
+    };
+
+    const README: &[u8] = b"ho ho ho\n";
+    const LIB: &[u8] = b"pub fn gift() {}\n";
+
+    fn tar() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(EntryType::Directory);
+        header.set_mode(0o755);
+        header.set_mtime(1703030400);
+        header.set_size(0);
+        builder
+            .append_data(&mut header, "src/", io::empty())
+            .unwrap();
+        for (path, content) in [("README", README), ("src/lib.rs", LIB)] {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(0o644);
+            header.set_mtime(1703030400);
+            header.set_size(content.len() as u64);
+            builder.append_data(&mut header, path, content).unwrap();
+        }
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_mode(0o777);
+        header.set_mtime(1703030400);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "README.md", "README")
+            .unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    fn zip() -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().unix_permissions(0o644);
+        zip.add_directory("src/", options.unix_permissions(0o755))
+            .unwrap();
+        for (path, content) in [("README", README), ("src/lib.rs", LIB)] {
+            zip.start_file(path, options).unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.add_symlink("README.md", "README", options.unix_permissions(0o777))
+            .unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    async fn post(archive: Vec<u8>, glob: Option<&str>) -> Manifest {
+        let server = TestServer::new(task(&ArchiveConfig::default())).unwrap();
+        let mut request = server.post("/archive/manifest");
+        if let Some(glob) = glob {
+            request = request.add_query_param("glob", glob);
+        }
+        let response = request.bytes(archive.into()).await;
+        response.assert_status_ok();
+        response.json()
+    }
+
+    fn paths(manifest: &Manifest) -> Vec<(&str, Kind)> {
+        manifest
+            .entries
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.kind))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn formats() {
+        let tar = tar();
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&tar).unwrap();
+        let archives = [
+            (Format::Tar, tar.clone()),
+            (Format::TarGz, gz.finish().unwrap()),
+            (Format::TarZst, zstd::encode_all(tar.as_slice(), 0).unwrap()),
+            (Format::Zip, zip()),
+        ];
+
+        for (format, archive) in archives {
+            let manifest = post(archive, None).await;
+            assert_eq!(manifest.format, format);
+            assert_eq!(
+                paths(&manifest),
+                [
+                    ("src", Kind::Directory),
+                    ("README", Kind::File),
+                    ("src/lib.rs", Kind::File),
+                    ("README.md", Kind::Symlink),
+                ]
+            );
+            let readme = &manifest.entries[1];
+            assert_eq!(readme.size, README.len() as u64);
+            assert_eq!(readme.mode, Some(0o644));
+            assert_eq!(
+                readme.sha256.as_deref(),
+                Some(hex::encode(Sha256::digest(README)).as_str())
+            );
+            assert_eq!(manifest.entries[3].link_target.as_deref(), Some("README"));
+            assert_eq!(
+                manifest.summary,
+                Summary {
+                    entries: 4,
+                    files: 2,
+                    directories: 1,
+                    links: 1,
+                    total_size: (README.len() + LIB.len()) as u64,
+                }
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn glob() {
+        let manifest = post(tar(), Some("src/*.rs")).await;
+        assert_eq!(paths(&manifest), [("src/lib.rs", Kind::File)]);
+        assert_eq!(manifest.summary.total_size, LIB.len() as u64);
+
+        // `*` doesn't match `/`
+        let manifest = post(zip(), Some("*")).await;
+        assert_eq!(
+            paths(&manifest),
+            [
+                ("src", Kind::Directory),
+                ("README", Kind::File),
+                ("README.md", Kind::Symlink),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid() {
+        let server = TestServer::new(task(&ArchiveConfig::default())).unwrap();
+
+        let response = server
+            .post("/archive/manifest")
+            .add_query_param("glob", "src/[")
+            .bytes(tar().into())
+            .await;
+        assert_problem(&response, StatusCode::BAD_REQUEST, "bad_request");
+
+        let mut truncated = zip();
+        truncated.truncate(100);
+        let response = server
+            .post("/archive/manifest")
+            .bytes(truncated.into())
+            .await;
+        assert_problem(
+            &response,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_archive",
+        );
+    }
+}
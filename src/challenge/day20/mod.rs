@@ -0,0 +1,421 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderMap},
+    response::IntoResponse,
+    routing::post,
+    Router,
+};
+use futures::{StreamExt, TryStreamExt};
+use git2::Repository;
+use globset::{GlobBuilder, GlobMatcher};
+use tar::Archive;
+use tokio_util::io::{StreamReader, SyncIoBridge};
+
+use self::extract::{ExtractError, Guard};
+use super::error::AppError;
+use crate::config::ArchiveConfig;
+
+pub mod extract;
+mod manifest;
+mod repository;
+mod search;
+
+/// A request body, read on the blocking pool.
+type BodyReader = Box<dyn io::Read + Send>;
+
+pub fn task(config: &ArchiveConfig) -> Router {
+    Router::new()
+        .route("/archive_files", post(count_archive_files))
+        .route("/archive_files_size", post(get_archive_files_size))
+        .route("/cookie", post(find_cookie))
+        .route("/archive/manifest", post(manifest::manifest))
+        .route("/git/search", post(search::search))
+        .route("/git/refs", post(repository::refs))
+        .route("/git/log", post(repository::log))
+        .route("/git/diff", post(repository::diff))
+        .with_state(config.clone())
+}
+
+/// A glob matching paths, in which `*` doesn't match `/`.
+fn glob(pattern: &str) -> Result<GlobMatcher, AppError> {
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map(|glob| glob.compile_matcher())
+        .map_err(|e| AppError::BadRequest(e.to_string()))
+}
+
+/// Stream `body` into `read`, which runs on the blocking pool, failing once
+/// more than `limit` bytes were received.
+async fn read_body<T, F>(
+    headers: &HeaderMap,
+    body: Body,
+    limit: u64,
+    read: F,
+) -> Result<T, AppError>
+where
+    T: Send + 'static,
+    F: FnOnce(BodyReader) -> Result<T, ExtractError> + Send + 'static,
+{
+    let length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+    if length.is_some_and(|length| length > limit) {
+        return Err(AppError::PayloadTooLarge(limit));
+    }
+
+    // readers may wrap the error of the body
+    let exceeded = Arc::new(AtomicBool::new(false));
+    let flag = exceeded.clone();
+    let mut received = 0;
+    let chunks = body
+        .into_data_stream()
+        .map_err(io::Error::other)
+        .map(move |chunk| {
+            let chunk = chunk?;
+            received += chunk.len() as u64;
+            if received > limit {
+                flag.store(true, Ordering::Relaxed);
+                let message = format!("archive larger than {limit} bytes");
+                return Err(io::Error::other(message));
+            }
+            Ok(chunk)
+        });
+    let reader: BodyReader = Box::new(SyncIoBridge::new(StreamReader::new(chunks)));
+
+    tokio::task::spawn_blocking(move || read(reader))
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?
+        .map_err(|e| {
+            if exceeded.load(Ordering::Relaxed) {
+                AppError::PayloadTooLarge(limit)
+            } else {
+                e.into()
+            }
+        })
+}
+
+/// [`read_body`] for a tar archive.
+async fn read_archive<T, F>(
+    headers: &HeaderMap,
+    body: Body,
+    limit: u64,
+    read: F,
+) -> Result<T, AppError>
+where
+    T: Send + 'static,
+    F: FnOnce(Archive<BodyReader>) -> Result<T, ExtractError> + Send + 'static,
+{
+    read_body(headers, body, limit, move |reader| {
+        read(Archive::new(reader))
+    })
+    .await
+}
+
+async fn count_archive_files(
+    State(config): State<ArchiveConfig>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, AppError> {
+    let mut guard = Guard::new(&config);
+    let count = read_archive(&headers, body, config.max_body_size, move |mut archive| {
+        let mut count = 0;
+        for file in archive.entries()? {
+            let file = file?;
+            let path = guard.tar_entry(&file)?;
+            tracing::debug!("path:{} size:{}", path.display(), file.size());
+            count += 1;
+        }
+        Ok(count)
+    })
+    .await?;
+    Ok(count.to_string())
+}
+
+async fn get_archive_files_size(
+    State(config): State<ArchiveConfig>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, AppError> {
+    let mut guard = Guard::new(&config);
+    let size = read_archive(&headers, body, config.max_body_size, move |mut archive| {
+        let mut size = 0;
+        for file in archive.entries()? {
+            let file = file?;
+            guard.tar_entry(&file)?;
+            size += file.size();
+        }
+        Ok(size)
+    })
+    .await?;
+    Ok(size.to_string())
+}
+
+/// Unpack the repository in the tarball `body` into a temporary directory,
+/// and run `inspect` on it on the blocking pool.
+async fn with_repository<T, F>(
+    headers: &HeaderMap,
+    body: Body,
+    config: &ArchiveConfig,
+    inspect: F,
+) -> Result<T, AppError>
+where
+    T: Send + 'static,
+    F: FnOnce(&Repository) -> Result<T, AppError> + Send + 'static,
+{
+    let dir = tempfile::tempdir().map_err(|e| AppError::Internal(e.to_string()))?;
+    let path = dir.path().to_path_buf();
+    let config = config.clone();
+    read_archive(headers, body, config.max_body_size, move |mut archive| {
+        extract::unpack(&mut archive, &path, &config)
+    })
+    .await?;
+
+    tokio::task::spawn_blocking(move || inspect(&Repository::open(dir.path())?))
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?
+}
+
+/// The author and id of the last commit of `christmas` with a `santa.txt`
+/// containing `COOKIE`.
+async fn find_cookie(
+    State(config): State<ArchiveConfig>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, AppError> {
+    let search = search::Search {
+        reference: "christmas".to_string(),
+        paths: Some(glob("**/santa.txt")?),
+        pattern: Some(search::Pattern::Literal("COOKIE".to_string())),
+        traversal: search::Traversal::FirstParent,
+        limit: 1,
+    };
+    let matches = with_repository(&headers, body, &config, move |repo| {
+        search::search_history(repo, &search)
+    })
+    .await?;
+
+    let commit = matches
+        .first()
+        .ok_or_else(|| AppError::NotFound("no santa.txt with a cookie".to_string()))?;
+    Ok(format!("{} {}", commit.commit.author, commit.commit.id))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::*;
+    use crate::challenge::error::assert_problem;
+    use axum::http::StatusCode;
+    use axum_test::TestServer;
+    use git2::{Commit, IndexAddOption, Oid, Signature, Time};
+    use tempfile::TempDir;
+
+    const COOKIE_JAR: &[u8] = include_bytes!("../../../assets/cookiejar.tar");
+
+    /// Commit the work tree, with `files` written and `removed` deleted.
+    fn commit(
+        repo: &Repository,
+        files: &[(&str, &str)],
+        removed: &[&str],
+        parents: &[Oid],
+        time: i64,
+    ) -> Oid {
+        let workdir = repo.workdir().unwrap();
+        for (path, content) in files {
+            let path = workdir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        for path in removed {
+            fs::remove_file(workdir.join(path)).unwrap();
+        }
+        let mut index = repo.index().unwrap();
+        index.add_all(["*"], IndexAddOption::DEFAULT, None).unwrap();
+        index.update_all(["*"], None).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+
+        let author = Signature::new("Santa", "santa@northpole", &Time::new(time, 0)).unwrap();
+        let parents: Vec<Commit> = parents
+            .iter()
+            .map(|id| repo.find_commit(*id).unwrap())
+            .collect();
+        let parents: Vec<&Commit> = parents.iter().collect();
+        repo.commit(None, &author, &author, "ho ho ho", &tree, &parents)
+            .unwrap()
+    }
+
+    /// A repository whose `christmas` branch, its `HEAD`, merged `recipe`:
+    ///
+    /// ```text
+    /// root -- milk ------- merge
+    ///             \       /
+    ///              recipe
+    /// ```
+    pub(super) fn repository() -> (TempDir, [Oid; 4]) {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let root = commit(&repo, &[("santa.txt", "COOKIE")], &[], &[], 1);
+        let milk = commit(&repo, &[("santa.txt", "milk")], &[], &[root], 2);
+        let recipe = commit(
+            &repo,
+            &[("elf/recipe.txt", "COOKIE dough")],
+            &[],
+            &[milk],
+            3,
+        );
+        let merge = commit(&repo, &[], &["elf/recipe.txt"], &[milk, recipe], 4);
+        repo.branch("christmas", &repo.find_commit(merge).unwrap(), false)
+            .unwrap();
+        repo.set_head("refs/heads/christmas").unwrap();
+        (dir, [root, milk, recipe, merge])
+    }
+
+    pub(super) fn tarball(dir: &Path) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        builder.append_dir_all(".", dir).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    #[tokio::test]
+    async fn invalid_archive() {
+        let app = task(&ArchiveConfig::default());
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let truncated = COOKIE_JAR[..700].to_vec();
+        let response = server.post("/archive_files").bytes(truncated.into()).await;
+
+        assert_problem(
+            &response,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_archive",
+        );
+
+        let response = server
+            .post("/archive_files_size")
+            .bytes(COOKIE_JAR[..700].to_vec().into())
+            .await;
+
+        assert_problem(
+            &response,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_archive",
+        );
+    }
+
+    #[tokio::test]
+    async fn archive_files() {
+        let server = TestServer::new(task(&ArchiveConfig::default())).unwrap();
+        let northpole = include_bytes!("../../../assets/northpole20231220.tar").to_vec();
+
+        let response = server
+            .post("/archive_files")
+            .bytes(northpole.clone().into())
+            .await;
+        response.assert_text("6");
+
+        let response = server
+            .post("/archive_files_size")
+            .bytes(northpole.into())
+            .await;
+        response.assert_text("1196282");
+    }
+
+    #[tokio::test]
+    async fn archive_too_large() {
+        let config = ArchiveConfig {
+            max_body_size: 1024,
+            ..ArchiveConfig::default()
+        };
+        let server = TestServer::new(task(&config)).unwrap();
+
+        let response = server
+            .post("/archive_files")
+            .bytes(COOKIE_JAR.to_vec().into())
+            .await;
+        assert_problem(
+            &response,
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+        );
+
+        // without a length, the limit is hit while streaming
+        let chunks = futures::stream::iter(COOKIE_JAR.chunks(512).map(Ok::<_, io::Error>));
+        let read = read_archive(
+            &HeaderMap::new(),
+            Body::from_stream(chunks),
+            1024,
+            |mut archive| {
+                for entry in archive.entries()? {
+                    entry?;
+                }
+                Ok(())
+            },
+        )
+        .await;
+        assert!(matches!(read, Err(AppError::PayloadTooLarge(1024))));
+    }
+
+    #[tokio::test]
+    async fn too_many_entries() {
+        let config = ArchiveConfig {
+            max_entries: 100,
+            ..ArchiveConfig::default()
+        };
+        let server = TestServer::new(task(&config)).unwrap();
+
+        for path in ["/archive_files", "/archive_files_size", "/cookie"] {
+            let response = server.post(path).bytes(COOKIE_JAR.to_vec().into()).await;
+            assert_problem(
+                &response,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "too_many_entries",
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn cookie() {
+        let server = TestServer::new(task(&ArchiveConfig::default())).unwrap();
+
+        let response = server
+            .post("/cookie")
+            .bytes(COOKIE_JAR.to_vec().into())
+            .await;
+        response.assert_text("Grinch 71dfab551a1958b35b7436c54b7455dcec99a12c");
+    }
+
+    #[tokio::test]
+    async fn not_a_repository() {
+        let app = task(&ArchiveConfig::default());
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server
+            .post("/cookie")
+            .bytes(
+                include_bytes!("../../../assets/northpole20231220.tar")
+                    .to_vec()
+                    .into(),
+            )
+            .await;
+
+        assert_problem(&response, StatusCode::NOT_FOUND, "git_not_found");
+    }
+}
@@ -0,0 +1,470 @@
+//! `POST /20/git/refs`, `/20/git/log` and `/20/git/diff`: the branches, tags,
+//! history and changes of an uploaded repository.
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderMap},
+    response::IntoResponse,
+};
+use chrono::{DateTime, Utc};
+use git2::{BranchType, Commit, Delta, DiffFormat, DiffOptions, Oid, Patch, Repository};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    search::{default_ref, history, Traversal},
+    with_repository,
+};
+use crate::{
+    challenge::{
+        error::AppError,
+        extract::{Json, Query},
+    },
+    config::ArchiveConfig,
+};
+
+const MAX_LIMIT: usize = 100;
+
+/// Who made a commit, and when.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CommitInfo {
+    pub id: String,
+    pub author: String,
+    pub email: String,
+    pub time: DateTime<Utc>,
+}
+
+impl CommitInfo {
+    pub fn of(commit: &Commit) -> Self {
+        let author = commit.author();
+        Self {
+            id: commit.id().to_string(),
+            author: String::from_utf8_lossy(author.name_bytes()).into_owned(),
+            email: String::from_utf8_lossy(author.email_bytes()).into_owned(),
+            time: DateTime::from_timestamp(author.when().seconds(), 0).unwrap_or_default(),
+        }
+    }
+}
+
+/// A branch or tag and the commit it points to.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Ref {
+    pub name: String,
+    pub commit: CommitInfo,
+    pub summary: Option<String>,
+}
+
+impl Ref {
+    fn new(name: String, commit: &Commit) -> Self {
+        Self {
+            name,
+            commit: CommitInfo::of(commit),
+            summary: commit.summary().map(str::to_string),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Refs {
+    /// The branch `HEAD` is on, if any.
+    pub head: Option<String>,
+    pub branches: Vec<Ref>,
+    pub tags: Vec<Ref>,
+}
+
+pub async fn refs(
+    State(config): State<ArchiveConfig>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<Refs>, AppError> {
+    Ok(Json(
+        with_repository(&headers, body, &config, list_refs).await?,
+    ))
+}
+
+fn list_refs(repo: &Repository) -> Result<Refs, AppError> {
+    let head = repo
+        .head()
+        .ok()
+        .filter(|head| head.is_branch())
+        .and_then(|head| head.shorthand().map(str::to_string));
+
+    let mut branches = Vec::new();
+    for branch in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = branch?;
+        let name = String::from_utf8_lossy(branch.name_bytes()?).into_owned();
+        branches.push(Ref::new(name, &branch.get().peel_to_commit()?));
+    }
+
+    let mut tags = Vec::new();
+    for name in repo.tag_names(None)?.iter().flatten() {
+        // tags of trees and blobs are left out
+        let tag = repo.revparse_single(&format!("refs/tags/{name}"))?;
+        if let Ok(commit) = tag.peel_to_commit() {
+            tags.push(Ref::new(name.to_string(), &commit));
+        }
+    }
+    Ok(Refs {
+        head,
+        branches,
+        tags,
+    })
+}
+
+/// Query string of `POST /git/log`.
+#[derive(Deserialize, Debug)]
+pub struct LogQuery {
+    #[serde(rename = "ref", default = "default_ref")]
+    reference: String,
+    #[serde(default)]
+    traversal: Traversal,
+    #[serde(default = "default_limit")]
+    limit: usize,
+    /// The `next_cursor` of the previous page.
+    cursor: Option<String>,
+}
+
+fn default_limit() -> usize {
+    20
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum FileStatus {
+    Added,
+    Deleted,
+    Modified,
+    Renamed,
+    Copied,
+    TypeChanged,
+    Other,
+}
+
+impl From<Delta> for FileStatus {
+    fn from(delta: Delta) -> Self {
+        match delta {
+            Delta::Added => FileStatus::Added,
+            Delta::Deleted => FileStatus::Deleted,
+            Delta::Modified => FileStatus::Modified,
+            Delta::Renamed => FileStatus::Renamed,
+            Delta::Copied => FileStatus::Copied,
+            Delta::Typechange => FileStatus::TypeChanged,
+            _ => FileStatus::Other,
+        }
+    }
+}
+
+/// A file changed by a commit. Binary files count no lines.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct FileChange {
+    pub path: String,
+    pub status: FileStatus,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct DiffStats {
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct LogEntry {
+    #[serde(flatten)]
+    pub commit: CommitInfo,
+    pub message: String,
+    pub parents: Vec<String>,
+    pub stats: DiffStats,
+    pub files: Vec<FileChange>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Log {
+    pub commits: Vec<LogEntry>,
+    pub next_cursor: Option<String>,
+}
+
+pub async fn log(
+    State(config): State<ArchiveConfig>,
+    Query(query): Query<LogQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<Log>, AppError> {
+    if !(1..=MAX_LIMIT).contains(&query.limit) {
+        return Err(AppError::BadRequest(format!(
+            "limit must be between 1 and {MAX_LIMIT}"
+        )));
+    }
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(Oid::from_str)
+        .transpose()
+        .map_err(|_| AppError::BadRequest("invalid cursor".to_string()))?;
+    let log = with_repository(&headers, body, &config, move |repo| {
+        read_log(repo, &query, cursor)
+    })
+    .await?;
+    Ok(Json(log))
+}
+
+/// A page of the commits reachable from the reference of `query`, newest
+/// first, starting at `cursor`.
+fn read_log(repo: &Repository, query: &LogQuery, cursor: Option<Oid>) -> Result<Log, AppError> {
+    let mut started = cursor.is_none();
+    let mut commits = Vec::new();
+    let mut next_cursor = None;
+    for id in history(repo, &query.reference, query.traversal)? {
+        let id = id?;
+        started |= Some(id) == cursor;
+        if !started {
+            continue;
+        }
+        if commits.len() == query.limit {
+            next_cursor = Some(id.to_string());
+            break;
+        }
+        commits.push(log_entry(repo, &repo.find_commit(id)?)?);
+    }
+    if !started {
+        return Err(AppError::BadRequest(format!(
+            "cursor is not in the history of {}",
+            query.reference
+        )));
+    }
+    Ok(Log {
+        commits,
+        next_cursor,
+    })
+}
+
+/// `commit` with its changes to its first parent, or to nothing for a root
+/// commit.
+fn log_entry(repo: &Repository, commit: &Commit) -> Result<LogEntry, AppError> {
+    let parent = commit
+        .parents()
+        .next()
+        .map(|parent| parent.tree())
+        .transpose()?;
+    let mut diff = repo.diff_tree_to_tree(parent.as_ref(), Some(&commit.tree()?), None)?;
+    diff.find_similar(None)?;
+
+    let stats = diff.stats()?;
+    let mut files = Vec::new();
+    for (index, delta) in diff.deltas().enumerate() {
+        let (_, insertions, deletions) = match Patch::from_diff(&diff, index)? {
+            Some(patch) => patch.line_stats()?,
+            None => (0, 0, 0),
+        };
+        let path = delta.new_file().path().or(delta.old_file().path());
+        files.push(FileChange {
+            path: path
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+            status: delta.status().into(),
+            insertions,
+            deletions,
+        });
+    }
+
+    Ok(LogEntry {
+        commit: CommitInfo::of(commit),
+        message: String::from_utf8_lossy(commit.message_bytes()).into_owned(),
+        parents: commit.parent_ids().map(|id| id.to_string()).collect(),
+        stats: DiffStats {
+            files_changed: stats.files_changed(),
+            insertions: stats.insertions(),
+            deletions: stats.deletions(),
+        },
+        files,
+    })
+}
+
+/// Query string of `POST /git/diff`.
+#[derive(Deserialize, Debug)]
+pub struct DiffQuery {
+    from: String,
+    to: String,
+    /// Unchanged lines around each hunk.
+    #[serde(default = "default_context")]
+    context: u32,
+}
+
+fn default_context() -> u32 {
+    3
+}
+
+pub async fn diff(
+    State(config): State<ArchiveConfig>,
+    Query(query): Query<DiffQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<impl IntoResponse, AppError> {
+    let patch = with_repository(&headers, body, &config, move |repo| {
+        unified_diff(repo, &query)
+    })
+    .await?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/x-diff; charset=utf-8")],
+        patch,
+    ))
+}
+
+/// The changes from the tree of `from` to the tree of `to`, as `git diff`
+/// prints them.
+fn unified_diff(repo: &Repository, query: &DiffQuery) -> Result<String, AppError> {
+    let from = repo.revparse_single(&query.from)?.peel_to_tree()?;
+    let to = repo.revparse_single(&query.to)?.peel_to_tree()?;
+    let mut options = DiffOptions::new();
+    options.context_lines(query.context);
+    let mut diff = repo.diff_tree_to_tree(Some(&from), Some(&to), Some(&mut options))?;
+    diff.find_similar(None)?;
+
+    let mut patch = Vec::new();
+    diff.print(DiffFormat::Patch, |_, _, line| {
+        // headers and hunk headers carry their own text
+        if let origin @ ('+' | '-' | ' ') = line.origin() {
+            patch.push(origin as u8);
+        }
+        patch.extend_from_slice(line.content());
+        true
+    })?;
+    Ok(String::from_utf8_lossy(&patch).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use axum_test::TestServer;
+
+    use super::*;
+    use crate::challenge::{
+        day20::{
+            task,
+            tests::{repository, tarball},
+        },
+        error::assert_problem,
+    };
+
+    fn log_query(query: &str) -> LogQuery {
+        let uri = format!("/?{query}").parse().unwrap();
+        axum::extract::Query::<LogQuery>::try_from_uri(&uri)
+            .unwrap()
+            .0
+    }
+
+    fn ids(log: &Log) -> Vec<String> {
+        log.commits
+            .iter()
+            .map(|entry| entry.commit.id.clone())
+            .collect()
+    }
+
+    #[test]
+    fn log_pages() {
+        let (dir, [root, milk, recipe, merge]) = repository();
+        let repo = Repository::open(dir.path()).unwrap();
+
+        let page = read_log(&repo, &log_query("limit=2"), None).unwrap();
+        assert_eq!(ids(&page), [merge.to_string(), milk.to_string()]);
+        assert_eq!(page.next_cursor, Some(root.to_string()));
+        let page = read_log(&repo, &log_query("limit=2"), Some(root)).unwrap();
+        assert_eq!(ids(&page), [root.to_string()]);
+        assert_eq!(page.next_cursor, None);
+
+        let all = read_log(&repo, &log_query("traversal=all"), None).unwrap();
+        assert_eq!(all.commits.len(), 4);
+        let entry = &all.commits[1];
+        assert_eq!(entry.commit.id, recipe.to_string());
+        assert_eq!(entry.message, "ho ho ho");
+        assert_eq!(entry.parents, [milk.to_string()]);
+        assert_eq!(
+            entry.files,
+            [FileChange {
+                path: "elf/recipe.txt".to_string(),
+                status: FileStatus::Added,
+                insertions: 1,
+                deletions: 0,
+            }]
+        );
+
+        // merges are compared to their first parent
+        assert_eq!(all.commits[0].parents.len(), 2);
+        assert_eq!(all.commits[0].stats, DiffStats::default());
+        let modified = &all.commits[2];
+        assert_eq!(modified.files[0].status, FileStatus::Modified);
+        assert_eq!(
+            modified.stats,
+            DiffStats {
+                files_changed: 1,
+                insertions: 1,
+                deletions: 1,
+            }
+        );
+
+        let elsewhere = read_log(&repo, &log_query(&format!("ref={milk}")), Some(merge));
+        assert!(matches!(elsewhere, Err(AppError::BadRequest(_))));
+    }
+
+    #[test]
+    fn diff_between() {
+        let (dir, _) = repository();
+        let repo = Repository::open(dir.path()).unwrap();
+        let query = DiffQuery {
+            from: "christmas~2".to_string(),
+            to: "christmas".to_string(),
+            context: 3,
+        };
+
+        let patch = unified_diff(&repo, &query).unwrap();
+        assert!(patch.starts_with("diff --git a/santa.txt b/santa.txt\n"));
+        assert!(patch.contains("\n-COOKIE\n"));
+        assert!(patch.contains("\n+milk\n"));
+        assert!(!patch.contains("recipe.txt"));
+    }
+
+    #[tokio::test]
+    async fn routes() {
+        let (dir, [root, _, _, merge]) = repository();
+        let repo = Repository::open(dir.path()).unwrap();
+        repo.tag_lightweight("v1", &repo.find_object(root, None).unwrap(), false)
+            .unwrap();
+        let server = TestServer::new(task(&ArchiveConfig::default())).unwrap();
+
+        let response = server
+            .post("/git/refs")
+            .bytes(tarball(dir.path()).into())
+            .await;
+        let refs = response.json::<Refs>();
+        assert_eq!(refs.head.as_deref(), Some("christmas"));
+        assert_eq!(refs.branches.len(), 1);
+        assert_eq!(refs.branches[0].commit.id, merge.to_string());
+        assert_eq!(refs.branches[0].summary.as_deref(), Some("ho ho ho"));
+        assert_eq!(refs.tags[0].name, "v1");
+        assert_eq!(refs.tags[0].commit.id, root.to_string());
+
+        let response = server
+            .post("/git/log")
+            .add_query_param("ref", "v1")
+            .bytes(tarball(dir.path()).into())
+            .await;
+        assert_eq!(ids(&response.json::<Log>()), [root.to_string()]);
+
+        let response = server
+            .post("/git/diff")
+            .add_query_param("from", "v1")
+            .add_query_param("to", "HEAD")
+            .bytes(tarball(dir.path()).into())
+            .await;
+        assert!(response.text().contains("\n+milk\n"));
+
+        let response = server
+            .post("/git/log")
+            .add_query_param("cursor", "not-a-commit")
+            .bytes(tarball(dir.path()).into())
+            .await;
+        assert_problem(&response, StatusCode::BAD_REQUEST, "bad_request");
+    }
+}
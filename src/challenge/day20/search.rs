@@ -0,0 +1,302 @@
+//! `POST /20/git/search`: the commits of an uploaded repository whose files
+//! match a path and content pattern.
+
+use std::collections::HashMap;
+
+use axum::{body::Body, extract::State, http::HeaderMap};
+use fancy_regex::Regex;
+use git2::{ObjectType, Oid, Repository, Revwalk, Sort, TreeWalkMode, TreeWalkResult};
+use globset::GlobMatcher;
+use serde::{Deserialize, Serialize};
+
+use super::{glob, repository::CommitInfo, with_repository};
+use crate::{
+    challenge::{
+        error::AppError,
+        extract::{Json, Query},
+    },
+    config::ArchiveConfig,
+};
+
+const MAX_LIMIT: usize = 1000;
+
+/// Which parents of a commit are searched.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Traversal {
+    /// Only the first parent, i.e. the history of the branch itself.
+    #[default]
+    FirstParent,
+    /// Every ancestor, merged branches included.
+    All,
+}
+
+/// Query string of `POST /git/search`.
+#[derive(Deserialize, Debug)]
+pub struct SearchQuery {
+    /// Branch, tag or any revision git understands.
+    #[serde(rename = "ref", default = "default_ref")]
+    reference: String,
+    /// Glob the paths of the files must match, e.g. `**/santa.txt`.
+    path: Option<String>,
+    /// What the content of the files must contain.
+    pattern: Option<String>,
+    /// Read `pattern` as a regex, with the syntax of day 15.
+    #[serde(default)]
+    regex: bool,
+    #[serde(default)]
+    traversal: Traversal,
+    #[serde(default = "default_limit")]
+    limit: usize,
+}
+
+pub(super) fn default_ref() -> String {
+    "HEAD".to_string()
+}
+
+fn default_limit() -> usize {
+    100
+}
+
+#[derive(Debug)]
+pub enum Pattern {
+    Literal(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn is_match(&self, content: &[u8]) -> Result<bool, AppError> {
+        let content = String::from_utf8_lossy(content);
+        Ok(match self {
+            Pattern::Literal(literal) => content.contains(literal.as_str()),
+            Pattern::Regex(regex) => regex.is_match(&content)?,
+        })
+    }
+}
+
+/// A validated [`SearchQuery`].
+#[derive(Debug)]
+pub struct Search {
+    pub reference: String,
+    pub paths: Option<GlobMatcher>,
+    pub pattern: Option<Pattern>,
+    pub traversal: Traversal,
+    pub limit: usize,
+}
+
+impl TryFrom<SearchQuery> for Search {
+    type Error = AppError;
+
+    fn try_from(query: SearchQuery) -> Result<Self, Self::Error> {
+        if !(1..=MAX_LIMIT).contains(&query.limit) {
+            return Err(AppError::BadRequest(format!(
+                "limit must be between 1 and {MAX_LIMIT}"
+            )));
+        }
+        let pattern = match query.pattern {
+            Some(pattern) if query.regex => Some(Pattern::Regex(
+                Regex::new(&pattern).map_err(|e| AppError::BadRequest(e.to_string()))?,
+            )),
+            Some(pattern) => Some(Pattern::Literal(pattern)),
+            None => None,
+        };
+        Ok(Self {
+            reference: query.reference,
+            paths: query.path.as_deref().map(glob).transpose()?,
+            pattern,
+            traversal: query.traversal,
+            limit: query.limit,
+        })
+    }
+}
+
+/// A commit with matching files.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CommitMatch {
+    #[serde(flatten)]
+    pub commit: CommitInfo,
+    pub paths: Vec<String>,
+}
+
+pub async fn search(
+    State(config): State<ArchiveConfig>,
+    Query(query): Query<SearchQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<Vec<CommitMatch>>, AppError> {
+    let search = Search::try_from(query)?;
+    let matches = with_repository(&headers, body, &config, move |repo| {
+        search_history(repo, &search)
+    })
+    .await?;
+    Ok(Json(matches))
+}
+
+/// The ids of the commits reachable from `reference`, newest first.
+pub(super) fn history<'r>(
+    repo: &'r Repository,
+    reference: &str,
+    traversal: Traversal,
+) -> Result<Revwalk<'r>, AppError> {
+    let start = repo.revparse_single(reference)?.peel_to_commit()?;
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+    walk.push(start.id())?;
+    if traversal == Traversal::FirstParent {
+        walk.simplify_first_parent()?;
+    }
+    Ok(walk)
+}
+
+/// The commits reachable from the reference of `search` with files
+/// matching it, newest first.
+pub fn search_history(repo: &Repository, search: &Search) -> Result<Vec<CommitMatch>, AppError> {
+    let walk = history(repo, &search.reference, search.traversal)?;
+
+    // most blobs are the same from one commit to the next
+    let mut blobs = HashMap::<Oid, bool>::new();
+    let mut matches = Vec::new();
+    for id in walk {
+        let commit = repo.find_commit(id?)?;
+        let mut files = Vec::new();
+        commit.tree()?.walk(TreeWalkMode::PreOrder, |dir, entry| {
+            if entry.kind() == Some(ObjectType::Blob) {
+                let path = format!("{dir}{}", String::from_utf8_lossy(entry.name_bytes()));
+                if search
+                    .paths
+                    .as_ref()
+                    .is_none_or(|paths| paths.is_match(&path))
+                {
+                    files.push((path, entry.id()));
+                }
+            }
+            TreeWalkResult::Ok
+        })?;
+
+        let mut paths = Vec::new();
+        for (path, blob) in files {
+            let matched = match (blobs.get(&blob), &search.pattern) {
+                (Some(matched), _) => *matched,
+                (None, None) => true,
+                (None, Some(pattern)) => {
+                    let matched = pattern.is_match(repo.find_blob(blob)?.content())?;
+                    blobs.insert(blob, matched);
+                    matched
+                }
+            };
+            if matched {
+                paths.push(path);
+            }
+        }
+        if paths.is_empty() {
+            continue;
+        }
+
+        matches.push(CommitMatch {
+            commit: CommitInfo::of(&commit),
+            paths,
+        });
+        if matches.len() == search.limit {
+            break;
+        }
+    }
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use axum_test::TestServer;
+    use chrono::DateTime;
+
+    use super::*;
+    use crate::challenge::{
+        day20::{
+            task,
+            tests::{repository, tarball},
+        },
+        error::assert_problem,
+    };
+
+    fn search(repo: &Repository, query: &str) -> Vec<(Oid, Vec<String>)> {
+        let uri = format!("/?{query}").parse().unwrap();
+        let axum::extract::Query(query) =
+            axum::extract::Query::<SearchQuery>::try_from_uri(&uri).unwrap();
+        search_history(repo, &query.try_into().unwrap())
+            .unwrap()
+            .into_iter()
+            .map(|found| (found.commit.id.parse().unwrap(), found.paths))
+            .collect()
+    }
+
+    #[test]
+    fn history() {
+        let (dir, [root, milk, recipe, merge]) = repository();
+        let repo = Repository::open(dir.path()).unwrap();
+        let santa = || vec!["santa.txt".to_string()];
+
+        // the root commit is searched too
+        assert_eq!(
+            search(&repo, "ref=christmas&path=santa.txt&pattern=COOKIE"),
+            [(root, santa())]
+        );
+        assert_eq!(
+            search(&repo, "ref=christmas&pattern=COOKIE&traversal=all"),
+            [
+                (recipe, vec!["elf/recipe.txt".to_string()]),
+                (root, santa())
+            ]
+        );
+        assert_eq!(
+            search(&repo, "ref=christmas&pattern=^mil%2Bk$&regex=true"),
+            [(merge, santa()), (milk, santa())]
+        );
+        assert_eq!(
+            search(&repo, "ref=christmas&path=elf/*&traversal=all&limit=1"),
+            [(recipe, vec!["elf/recipe.txt".to_string()])]
+        );
+        assert!(search(&repo, &format!("ref={milk}&path=elf/*")).is_empty());
+    }
+
+    #[tokio::test]
+    async fn search_route() {
+        let (dir, [root, ..]) = repository();
+        let server = TestServer::new(task(&ArchiveConfig::default())).unwrap();
+
+        let response = server
+            .post("/git/search")
+            .add_query_param("ref", "christmas")
+            .add_query_param("pattern", "COOKIE")
+            .bytes(tarball(dir.path()).into())
+            .await;
+        let matches = response.json::<Vec<CommitMatch>>();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].commit.id, root.to_string());
+        assert_eq!(matches[0].commit.author, "Santa");
+        assert_eq!(
+            matches[0].commit.time,
+            DateTime::from_timestamp(1, 0).unwrap()
+        );
+
+        let response = server
+            .post("/cookie")
+            .bytes(tarball(dir.path()).into())
+            .await;
+        response.assert_text(format!("Santa {root}"));
+
+        let response = server
+            .post("/git/search")
+            .add_query_param("ref", "easter")
+            .bytes(tarball(dir.path()).into())
+            .await;
+        assert_problem(&response, StatusCode::NOT_FOUND, "git_not_found");
+
+        let response = server
+            .post("/git/search")
+            .add_query_param("pattern", "(unclosed")
+            .add_query_param("regex", "true")
+            .bytes(tarball(dir.path()).into())
+            .await;
+        assert_problem(&response, StatusCode::BAD_REQUEST, "bad_request");
+    }
+}
@@ -1,40 +1,56 @@
-use axum::{extract::Path, response::IntoResponse, routing::get, Router};
+use axum::{response::IntoResponse, routing::get, Router};
 use country_boundaries::{CountryBoundaries, LatLon, BOUNDARIES_ODBL_360X180};
 use dms_coordinates::DMS;
 use s2::{cell::Cell, cellid::CellID};
 
+use super::{error::AppError, extract::Path};
+
 pub fn task() -> Router {
     Router::new()
         .route("/coords/:id", get(get_coords))
         .route("/country/:id", get(get_country))
 }
 
-async fn get_coords(Path(id): Path<String>) -> impl IntoResponse {
-    let (latitude_angle, longitude_angle) = get_coordinates(id);
+async fn get_coords(Path(id): Path<String>) -> Result<impl IntoResponse, AppError> {
+    let (latitude_angle, longitude_angle) = get_coordinates(&id)?;
 
     let latitude = DMS::from_ddeg_latitude(latitude_angle);
     let longitude = DMS::from_ddeg_longitude(longitude_angle);
 
-    format!("{} {}", format_dms(latitude), format_dms(longitude))
+    Ok(format!(
+        "{} {}",
+        format_dms(latitude),
+        format_dms(longitude)
+    ))
 }
 
-async fn get_country(Path(id): Path<String>) -> impl IntoResponse {
-    let (latitude_angle, longitude_angle) = get_coordinates(id);
-    let boundaries = CountryBoundaries::from_reader(BOUNDARIES_ODBL_360X180).unwrap();
-    let ids = boundaries.ids(LatLon::new(latitude_angle, longitude_angle).unwrap());
+async fn get_country(Path(id): Path<String>) -> Result<impl IntoResponse, AppError> {
+    let (latitude_angle, longitude_angle) = get_coordinates(&id)?;
+    let boundaries = CountryBoundaries::from_reader(BOUNDARIES_ODBL_360X180)
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let location = LatLon::new(latitude_angle, longitude_angle)
+        .map_err(|_| AppError::InvalidCellId(id.clone()))?;
+    let ids = boundaries.ids(location);
 
-    let country = rust_iso3166::from_alpha2(ids.last().unwrap()).unwrap();
+    let country = ids
+        .last()
+        .and_then(|alpha2| rust_iso3166::from_alpha2(alpha2))
+        .ok_or_else(|| AppError::NotFound(format!("no country found for cell `{id}`")))?;
 
-    country.name.split(' ').next().unwrap()
+    Ok(country.name.split(' ').next().unwrap_or(country.name))
 }
 
-fn get_coordinates(id: String) -> (f64, f64) {
-    let id = u64::from_str_radix(&id, 2).unwrap();
-    let point = Cell::from(CellID(id)).center();
+fn get_coordinates(id: &str) -> Result<(f64, f64), AppError> {
+    let cell_id = u64::from_str_radix(id, 2)
+        .map(CellID)
+        .ok()
+        .filter(|cell_id| cell_id.is_valid())
+        .ok_or_else(|| AppError::InvalidCellId(id.to_string()))?;
+    let point = Cell::from(cell_id).center();
     let latitude = point.latitude().deg();
     let longitude = point.longitude().deg();
 
-    (latitude, longitude)
+    Ok((latitude, longitude))
 }
 
 fn format_dms(dms: DMS) -> String {
@@ -43,6 +59,34 @@ fn format_dms(dms: DMS) -> String {
         dms.degrees,
         dms.minutes,
         dms.seconds,
-        dms.cardinal.unwrap()
+        dms.cardinal.map(|c| c.to_string()).unwrap_or_default()
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenge::error::assert_problem;
+    use axum::http::StatusCode;
+    use axum_test::TestServer;
+
+    #[tokio::test]
+    async fn invalid_cell_id() {
+        let app = task();
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server
+            .get("/coords/0100111110010011000110011001010101011111000010100011110001011011")
+            .await;
+        response.assert_status(StatusCode::OK);
+
+        let response = server.get("/coords/not-binary").await;
+        assert_problem(&response, StatusCode::BAD_REQUEST, "invalid_cell_id");
+
+        let response = server.get("/country/0").await;
+        assert_problem(&response, StatusCode::BAD_REQUEST, "invalid_cell_id");
+    }
+}
@@ -1,65 +1,92 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, str::FromStr};
 
 use axum::{response::IntoResponse, routing::post, Router};
 use glam::{IVec3, UVec2};
 use pathfinding::directed::bfs::bfs;
 
+use super::error::AppError;
+
 pub fn task() -> Router {
     Router::new()
         .route("/integers", post(get_present))
         .route("/rocket", post(get_path))
 }
 
-async fn get_present(text: String) -> impl IntoResponse {
-    let dict = text
+fn parse_number<T: FromStr>(text: &str) -> Result<T, AppError> {
+    text.trim()
+        .parse::<T>()
+        .map_err(|_| AppError::InvalidInteger(text.to_string()))
+}
+
+async fn get_present(text: String) -> Result<impl IntoResponse, AppError> {
+    let mut dict = HashMap::new();
+    for line in text
         .lines()
         .map(|line| line.trim())
         .filter(|line| !line.is_empty())
-        .map(|num| num.trim().parse::<u64>().unwrap())
-        .fold(HashMap::new(), |mut dict, num| {
-            if dict.remove(&num).is_none() {
-                dict.insert(num, ());
-            }
-            dict
-        });
+    {
+        let num = parse_number::<u64>(line)?;
+        if dict.remove(&num).is_none() {
+            dict.insert(num, ());
+        }
+    }
 
-    let ord_num = *dict.keys().next().unwrap();
+    let ord_num = *dict
+        .keys()
+        .next()
+        .ok_or_else(|| AppError::BadRequest("every number has a pair".to_string()))?;
+
+    Ok("🎁".repeat(ord_num as usize))
+}
 
-    "🎁".repeat(ord_num as usize)
+fn parse_line<T: FromStr>(line: &str, len: usize) -> Result<Vec<T>, AppError> {
+    let numbers = line
+        .split(' ')
+        .map(parse_number::<T>)
+        .collect::<Result<Vec<_>, _>>()?;
+    if numbers.len() != len {
+        return Err(AppError::BadRequest(format!(
+            "expected {len} numbers but got `{line}`"
+        )));
+    }
+    Ok(numbers)
 }
 
-async fn get_path(text: String) -> impl IntoResponse {
+async fn get_path(text: String) -> Result<impl IntoResponse, AppError> {
     let mut input = text
         .lines()
         .map(|line| line.trim())
         .filter(|line| !line.is_empty());
+    let mut next_line = || {
+        input
+            .next()
+            .ok_or_else(|| AppError::BadRequest("unexpected end of input".to_string()))
+    };
 
-    let num_of_stars = input.next().unwrap().parse::<usize>().unwrap();
+    let num_of_stars = parse_number::<usize>(next_line()?)?;
+    if num_of_stars == 0 {
+        return Err(AppError::BadRequest("there are no stars".to_string()));
+    }
 
-    let map = input
-        .clone()
-        .take(num_of_stars)
-        .map(|line| {
-            line.split(' ')
-                .map(|num| num.parse::<i32>().unwrap())
-                .collect::<Vec<_>>()
-        })
-        .map(|coords| IVec3::new(coords[0], coords[1], coords[2]))
-        .collect::<Vec<_>>();
-
-    let mut input = input.skip(num_of_stars).clone();
+    let mut map = Vec::with_capacity(num_of_stars);
+    for _ in 0..num_of_stars {
+        let coords = parse_line::<i32>(next_line()?, 3)?;
+        map.push(IVec3::new(coords[0], coords[1], coords[2]));
+    }
 
-    let num_of_portals = input.next().unwrap().parse::<usize>().unwrap();
+    let num_of_portals = parse_number::<usize>(next_line()?)?;
 
-    let portals = input
-        .take(num_of_portals)
-        .map(|line| {
-            line.split(' ')
-                .map(|num| num.parse::<u32>().unwrap())
-                .collect::<Vec<_>>()
-        })
-        .map(|connection| UVec2::new(connection[0], connection[1]))
-        .collect::<Vec<_>>();
+    let mut portals = Vec::with_capacity(num_of_portals);
+    for _ in 0..num_of_portals {
+        let connection = parse_line::<u32>(next_line()?, 2)?;
+        if connection.iter().any(|&star| star as usize >= num_of_stars) {
+            return Err(AppError::BadRequest(format!(
+                "portal {} {} leads to an unknown star",
+                connection[0], connection[1]
+            )));
+        }
+        portals.push(UVec2::new(connection[0], connection[1]));
+    }
 
     let path = bfs(
         &0,
@@ -72,11 +99,52 @@ async fn get_path(text: String) -> impl IntoResponse {
         },
         |p| *p == num_of_stars - 1,
     )
-    .unwrap();
+    .ok_or_else(|| AppError::NotFound("no path to the last star".to_string()))?;
 
     let distance = path.windows(2).fold(0.0, |acc, p| {
         acc + ((map[p[0]] - map[p[1]]).length_squared() as f32).sqrt()
     });
 
-    format!("{} {:.3}", path.len() - 1, distance)
+    Ok(format!("{} {:.3}", path.len() - 1, distance))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenge::error::assert_problem;
+    use axum::http::StatusCode;
+    use axum_test::TestServer;
+
+    #[tokio::test]
+    async fn invalid_integers() {
+        let app = task();
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server.post("/integers").text("888\n77\nseven\n").await;
+        assert_problem(&response, StatusCode::BAD_REQUEST, "invalid_integer");
+
+        let response = server.post("/integers").text("888\n888\n").await;
+        assert_problem(&response, StatusCode::BAD_REQUEST, "bad_request");
+    }
+
+    #[tokio::test]
+    async fn invalid_rocket() {
+        let app = task();
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server.post("/rocket").text("2\n0 0 0\n1 1\n").await;
+        assert_problem(&response, StatusCode::BAD_REQUEST, "bad_request");
+
+        let response = server
+            .post("/rocket")
+            .text("2\n0 0 0\n1 1 1\n1\n1 0\n")
+            .await;
+        assert_problem(&response, StatusCode::NOT_FOUND, "not_found");
+    }
 }
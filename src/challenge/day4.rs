@@ -1,6 +1,10 @@
-use axum::{http::StatusCode, response::IntoResponse, routing::post, Json, Router};
+use std::cmp::Ordering;
+
+use axum::{http::StatusCode, response::IntoResponse, routing::post, Router};
 use serde::{Deserialize, Serialize};
 
+use super::{error::AppError, extract::Json};
+
 #[derive(Deserialize)]
 struct Serdeer {
     name: String,
@@ -33,17 +37,25 @@ async fn calculate_combined_strength(Json(payload): Json<Vec<Serdeer>>) -> impl
     (StatusCode::OK, strength.to_string())
 }
 
-async fn handle_cursed_candy_eating_contest(Json(payload): Json<Vec<Serdeer>>) -> Json<Report> {
+async fn handle_cursed_candy_eating_contest(
+    Json(payload): Json<Vec<Serdeer>>,
+) -> Result<Json<Report>, AppError> {
+    let empty = || AppError::BadRequest("no reindeer entered the contest".to_string());
     let fastest_deer = payload
         .iter()
-        .max_by(|x, y| x.speed.partial_cmp(&y.speed).unwrap())
-        .unwrap();
-    let tallest_deer = payload.iter().max_by_key(|x| x.height).unwrap();
-    let magician = payload.iter().max_by_key(|x| x.snow_magic_power).unwrap();
+        .max_by(|x, y| x.speed.partial_cmp(&y.speed).unwrap_or(Ordering::Equal))
+        .ok_or_else(empty)?;
+    let tallest_deer = payload.iter().max_by_key(|x| x.height).ok_or_else(empty)?;
+    let magician = payload
+        .iter()
+        .max_by_key(|x| x.snow_magic_power)
+        .ok_or_else(empty)?;
     let consumer = payload
         .iter()
         .max_by_key(|x| x.candies_eat_yesterday)
-        .unwrap();
+        .ok_or_else(empty)?;
+
+    let missing = |name: &str, field: &str| AppError::BadRequest(format!("{name} has no {field}"));
 
     let report = Report {
         fastest: format!(
@@ -53,26 +65,34 @@ async fn handle_cursed_candy_eating_contest(Json(payload): Json<Vec<Serdeer>>) -
         tallest: format!(
             "{} is standing tall with his {} cm wide antlers",
             tallest_deer.name,
-            tallest_deer.antler_width.unwrap()
+            tallest_deer
+                .antler_width
+                .ok_or_else(|| missing(&tallest_deer.name, "antler_width"))?
         ),
         magician: format!(
             "{} could blast you away with a snow magic power of {}",
             magician.name,
-            magician.snow_magic_power.unwrap()
+            magician
+                .snow_magic_power
+                .ok_or_else(|| missing(&magician.name, "snow_magic_power"))?
         ),
         consumer: format!(
             "{} ate lots of candies, but also some {}",
             consumer.name,
-            consumer.favorite_food.as_ref().unwrap()
+            consumer
+                .favorite_food
+                .as_ref()
+                .ok_or_else(|| missing(&consumer.name, "favorite_food"))?
         ),
     };
 
-    Json(report)
+    Ok(Json(report))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::challenge::error::assert_problem;
     use axum::http::StatusCode;
     use axum_test::TestServer;
     use serde_json::json;
@@ -143,4 +163,33 @@ mod tests {
           "consumer": "Dancer ate lots of candies, but also some grass"
         }));
     }
+
+    #[tokio::test]
+    async fn empty_contest() {
+        let app = task();
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server.post("/contest").json(&json!([])).await;
+
+        assert_problem(&response, StatusCode::BAD_REQUEST, "bad_request");
+    }
+
+    #[tokio::test]
+    async fn missing_contest_field() {
+        let app = task();
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server
+            .post("/contest")
+            .json(&json!([{ "name": "Dasher", "strength": 5, "height": 80 }]))
+            .await;
+
+        assert_problem(&response, StatusCode::BAD_REQUEST, "bad_request");
+    }
 }
@@ -1,6 +1,11 @@
-use axum::{extract::Query, http::StatusCode, response::IntoResponse, routing::post, Json, Router};
+use axum::{response::IntoResponse, routing::post, Router};
 use serde::Deserialize;
 
+use super::{
+    error::AppError,
+    extract::{Json, Query},
+};
+
 #[derive(Deserialize)]
 struct Pagination {
     #[serde(default)]
@@ -16,10 +21,15 @@ pub fn task() -> Router {
 async fn list_names(
     pagination: Query<Pagination>,
     Json(names): Json<Vec<String>>,
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse, AppError> {
     let pagination: Pagination = pagination.0;
+    if pagination.split == Some(0) {
+        return Err(AppError::BadRequest("split cannot be 0".to_string()));
+    }
     let offset = pagination.offset;
-    let limit = pagination.limit.unwrap_or(names.len() - offset);
+    let limit = pagination
+        .limit
+        .unwrap_or(names.len().saturating_sub(offset));
 
     let list = names
         .iter()
@@ -27,13 +37,35 @@ async fn list_names(
         .take(limit)
         .cloned()
         .collect::<Vec<String>>();
-    if let Some(split) = pagination.split {
-        if split == 0 {
-            (StatusCode::BAD_REQUEST, "split cannot be 0").into_response()
-        } else {
-            Json(list.chunks(split).collect::<Vec<&[String]>>()).into_response()
-        }
+    Ok(if let Some(split) = pagination.split {
+        Json(list.chunks(split).collect::<Vec<&[String]>>()).into_response()
     } else {
         Json(list).into_response()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenge::error::assert_problem;
+    use axum::http::StatusCode;
+    use axum_test::TestServer;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn split_zero() {
+        let app = task();
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server
+            .post("/")
+            .add_query_param("split", 0)
+            .json(&json!(["Ava", "Caleb", "Mia"]))
+            .await;
+
+        assert_problem(&response, StatusCode::BAD_REQUEST, "bad_request");
     }
 }
@@ -1,6 +1,8 @@
-use axum::{routing::post, Json, Router};
+use axum::{routing::post, Router};
 use serde::{Deserialize, Serialize};
 
+use super::extract::Json;
+
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 struct Report {
     elf: usize,
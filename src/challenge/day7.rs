@@ -1,8 +1,10 @@
-use axum::{routing::get, Json, Router};
+use axum::{routing::get, Router};
 use axum_extra::extract::cookie::CookieJar;
 use base64::{engine::general_purpose, Engine as _};
 use serde::{Deserialize, Serialize};
 
+use super::{error::AppError, extract::Json};
+
 type Recipe = std::collections::HashMap<String, i64>;
 
 #[derive(Serialize, Deserialize)]
@@ -28,54 +30,62 @@ pub fn task() -> Router {
         .route("/bake", get(bake_cookie))
 }
 
-async fn decode_cookie(jar: CookieJar) -> Json<SimpleRecipe> {
-    let encoded_recipe = jar.get("recipe").unwrap();
-    let decoded_recipe = general_purpose::STANDARD
-        .decode(encoded_recipe.value())
-        .unwrap();
-    let recipe = serde_json::from_slice::<SimpleRecipe>(&decoded_recipe).unwrap();
+fn read_recipe_cookie(jar: &CookieJar) -> Result<Vec<u8>, AppError> {
+    let encoded_recipe = jar.get("recipe").ok_or(AppError::MissingCookie("recipe"))?;
+    Ok(general_purpose::STANDARD.decode(encoded_recipe.value())?)
+}
+
+async fn decode_cookie(jar: CookieJar) -> Result<Json<SimpleRecipe>, AppError> {
+    let decoded_recipe = read_recipe_cookie(&jar)?;
+    let recipe = serde_json::from_slice::<SimpleRecipe>(&decoded_recipe)?;
 
-    Json(recipe)
+    Ok(Json(recipe))
 }
 
-async fn bake_cookie(jar: CookieJar) -> Json<Report> {
-    let encoded_recipe = jar.get("recipe").unwrap();
-    let decoded_recipe = general_purpose::STANDARD
-        .decode(encoded_recipe.value())
-        .unwrap();
+async fn bake_cookie(jar: CookieJar) -> Result<Json<Report>, AppError> {
+    let decoded_recipe = read_recipe_cookie(&jar)?;
 
-    let mut kitchen = serde_json::from_slice::<Kitchen>(&decoded_recipe).unwrap();
+    let mut kitchen = serde_json::from_slice::<Kitchen>(&decoded_recipe)?;
+    let overflow = || AppError::BadRequest("the amounts are out of range".to_string());
 
     let cookies = kitchen
         .recipe
         .iter()
         .map(|(ingredient, amount_needed)| {
             if amount_needed == &0 {
-                i64::MAX
+                Ok(i64::MAX)
             } else if let Some(amount_in_store) = kitchen.pantry.get(ingredient) {
-                amount_in_store / amount_needed
+                amount_in_store
+                    .checked_div(*amount_needed)
+                    .ok_or_else(overflow)
             } else {
-                0
+                Ok(0)
             }
         })
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
         .min()
         .unwrap_or(0);
 
     for (ingredient, amount_in_store) in kitchen.pantry.iter_mut() {
         if let Some(amount_needed) = kitchen.recipe.get(ingredient) {
-            *amount_in_store -= amount_needed * cookies
+            *amount_in_store = amount_needed
+                .checked_mul(cookies)
+                .and_then(|used| amount_in_store.checked_sub(used))
+                .ok_or_else(overflow)?;
         }
     }
 
-    Json(Report {
+    Ok(Json(Report {
         cookies,
         pantry: kitchen.pantry,
-    })
+    }))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::challenge::error::assert_problem;
     use axum::http::StatusCode;
     use axum_extra::extract::cookie::Cookie;
     use axum_test::TestServer;
@@ -161,4 +171,97 @@ mod tests {
         assert_eq!(*recipe.pantry.get("cobblestone").unwrap_or(&0), 64);
         assert_eq!(*recipe.pantry.get("stick").unwrap_or(&0), 4);
     }
+
+    #[tokio::test]
+    async fn overflowing_division() {
+        let app = task();
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        let data = serde_json::json!({
+            "recipe": {"flour": -1},
+            "pantry": {"flour": i64::MIN},
+        });
+        let b64 = general_purpose::STANDARD.encode(serde_json::to_vec(&data).unwrap());
+
+        // Send the request.
+        let response = server
+            .get("/bake")
+            .add_cookie(Cookie::new("recipe", b64))
+            .await;
+
+        assert_problem(&response, StatusCode::BAD_REQUEST, "bad_request");
+    }
+
+    #[tokio::test]
+    async fn overflowing_multiplication() {
+        let app = task();
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // the sugar allows i64::MIN cookies, which need twice as much flour
+        let data = serde_json::json!({
+            "recipe": {"flour": 2, "sugar": 1},
+            "pantry": {"flour": 0, "sugar": i64::MIN},
+        });
+        let b64 = general_purpose::STANDARD.encode(serde_json::to_vec(&data).unwrap());
+
+        // Send the request.
+        let response = server
+            .get("/bake")
+            .add_cookie(Cookie::new("recipe", b64))
+            .await;
+
+        assert_problem(&response, StatusCode::BAD_REQUEST, "bad_request");
+    }
+
+    #[tokio::test]
+    async fn missing_cookie() {
+        let app = task();
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server.get("/decode").await;
+
+        assert_problem(&response, StatusCode::BAD_REQUEST, "missing_cookie");
+    }
+
+    #[tokio::test]
+    async fn invalid_base64() {
+        let app = task();
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server
+            .get("/bake")
+            .add_cookie(Cookie::new("recipe", "not base64!"))
+            .await;
+
+        assert_problem(&response, StatusCode::BAD_REQUEST, "invalid_base64");
+    }
+
+    #[tokio::test]
+    async fn invalid_json() {
+        let app = task();
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server
+            .get("/bake")
+            .add_cookie(Cookie::new(
+                "recipe",
+                general_purpose::STANDARD.encode(r#"{"recipe":{}}"#),
+            ))
+            .await;
+
+        assert_problem(&response, StatusCode::BAD_REQUEST, "invalid_json");
+    }
 }
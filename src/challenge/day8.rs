@@ -1,15 +1,11 @@
-use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::get,
-    Router,
-};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::get, Router};
 
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use super::{error::AppError, extract::Path};
+
 #[derive(Serialize, Deserialize)]
 struct QueryResponse {
     data: i32,
@@ -33,8 +29,8 @@ pub fn task() -> Router {
         .with_state(client)
 }
 
-async fn get_weight(number: i32, client: Client) -> f64 {
-    if let Ok(response) = client
+async fn get_weight(number: i32, client: Client) -> Result<f64, AppError> {
+    let data: Value = client
         .post("https://graphqlpokemon.favware.tech/v8")
         .header("Content-Type", "application/json")
         .body(format!(
@@ -45,34 +41,34 @@ async fn get_weight(number: i32, client: Client) -> f64 {
             number
         ))
         .send()
-        .await
-    {
-            let data: Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
-            let weight = data["data"]["getPokemonByDexNumber"]["weight"]
-        .as_f64()
-        .unwrap();
-            return weight;
-    }
+        .await?
+        .json()
+        .await?;
 
-    -1.0
+    data["data"]["getPokemonByDexNumber"]["weight"]
+        .as_f64()
+        .ok_or_else(|| AppError::NotFound(format!("pokemon #{number} not found")))
 }
 
 async fn get_pokemon_weight(
     Path(number): Path<i32>,
     State(client): State<Client>,
-) -> impl IntoResponse {
-    let weight = get_weight(number, client).await;
-    (StatusCode::OK, weight.to_string())
+) -> Result<impl IntoResponse, AppError> {
+    let weight = get_weight(number, client).await?;
+    Ok((StatusCode::OK, weight.to_string()))
 }
 
-async fn drop_pokemon(Path(number): Path<i32>, State(client): State<Client>) -> impl IntoResponse {
-    let weight = get_weight(number, client).await;
+async fn drop_pokemon(
+    Path(number): Path<i32>,
+    State(client): State<Client>,
+) -> Result<impl IntoResponse, AppError> {
+    let weight = get_weight(number, client).await?;
 
     let height: f64 = 10.0;
     let gravity: f64 = 9.825;
     let momentum = (gravity * height * 2.0).sqrt() * weight;
 
-    (StatusCode::OK, momentum.to_string())
+    Ok((StatusCode::OK, momentum.to_string()))
 }
 
 #[cfg(test)]
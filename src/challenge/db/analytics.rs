@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, Days, Months, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::challenge::error::AppError;
+
+/// Most buckets a single analytics query may return per series.
+pub const MAX_BUCKETS: usize = 1000;
+
+/// Width of a time bucket. Weeks start on Monday, all buckets are in UTC.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    #[default]
+    Day,
+    Week,
+    Month,
+}
+
+impl Bucket {
+    /// Unit understood by Postgres' `date_trunc`.
+    pub fn unit(self) -> &'static str {
+        match self {
+            Bucket::Day => "day",
+            Bucket::Week => "week",
+            Bucket::Month => "month",
+        }
+    }
+
+    /// First day of the bucket containing `date`.
+    pub fn start(self, date: NaiveDate) -> NaiveDate {
+        match self {
+            Bucket::Day => date,
+            Bucket::Week => date - Days::new(date.weekday().num_days_from_monday().into()),
+            Bucket::Month => date.with_day(1).unwrap(),
+        }
+    }
+
+    fn next(self, start: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            Bucket::Day => start.checked_add_days(Days::new(1)),
+            Bucket::Week => start.checked_add_days(Days::new(7)),
+            Bucket::Month => start.checked_add_months(Months::new(1)),
+        }
+    }
+
+    /// Start of every bucket overlapping `from..=to`.
+    pub fn range(self, from: NaiveDate, to: NaiveDate) -> Result<Vec<NaiveDate>, AppError> {
+        let mut starts = Vec::new();
+        let mut start = Some(self.start(from));
+        while let Some(date) = start.filter(|date| *date <= to) {
+            if starts.len() == MAX_BUCKETS {
+                return Err(AppError::BadRequest(format!(
+                    "the range spans more than {MAX_BUCKETS} buckets"
+                )));
+            }
+            starts.push(date);
+            start = self.next(date);
+        }
+        Ok(starts)
+    }
+}
+
+/// What the orders are totalled by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Group {
+    Region,
+    Gift,
+}
+
+/// Query string of the analytics endpoints; `from` and `to` are inclusive.
+#[derive(Deserialize, Clone, Debug)]
+pub struct AnalyticsQuery {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    #[serde(default)]
+    pub bucket: Bucket,
+}
+
+/// Ordered quantity of one region or gift within one bucket.
+#[derive(Debug, PartialEq)]
+pub struct BucketTotal {
+    pub name: String,
+    pub start: NaiveDate,
+    pub total: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Point {
+    pub start: NaiveDate,
+    pub total: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Series {
+    pub name: String,
+    pub buckets: Vec<Point>,
+}
+
+/// Turn the non-empty buckets returned by a store into one series per name,
+/// with a zero total for every bucket in between.
+pub fn fill_gaps(totals: Vec<BucketTotal>, starts: &[NaiveDate]) -> Vec<Series> {
+    let mut series = BTreeMap::<String, BTreeMap<NaiveDate, i64>>::new();
+    for total in totals {
+        *series
+            .entry(total.name)
+            .or_default()
+            .entry(total.start)
+            .or_default() += total.total;
+    }
+
+    series
+        .into_iter()
+        .map(|(name, totals)| Series {
+            name,
+            buckets: starts
+                .iter()
+                .map(|start| Point {
+                    start: *start,
+                    total: totals.get(start).copied().unwrap_or_default(),
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> NaiveDate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn bucket_range() {
+        // 2024-01-03 is a Wednesday
+        let starts = Bucket::Week
+            .range(date("2024-01-03"), date("2024-01-15"))
+            .unwrap();
+        assert_eq!(
+            starts,
+            vec![date("2024-01-01"), date("2024-01-08"), date("2024-01-15")]
+        );
+
+        let starts = Bucket::Month
+            .range(date("2023-12-24"), date("2024-02-01"))
+            .unwrap();
+        assert_eq!(
+            starts,
+            vec![date("2023-12-01"), date("2024-01-01"), date("2024-02-01")]
+        );
+
+        assert!(Bucket::Day
+            .range(date("2020-01-01"), date("2024-01-01"))
+            .is_err());
+    }
+
+    #[test]
+    fn gaps_are_filled() {
+        let starts = Bucket::Day
+            .range(date("2024-01-01"), date("2024-01-03"))
+            .unwrap();
+        let totals = vec![BucketTotal {
+            name: "Doll".to_string(),
+            start: date("2024-01-02"),
+            total: 8,
+        }];
+
+        let series = fill_gaps(totals, &starts);
+        let totals = series[0]
+            .buckets
+            .iter()
+            .map(|point| point.total)
+            .collect::<Vec<_>>();
+        assert_eq!(totals, vec![0, 8, 0]);
+    }
+}
@@ -0,0 +1,282 @@
+use std::cmp::Ordering;
+
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+
+use super::{Order, Region};
+
+pub const DEFAULT_LIMIT: usize = 20;
+pub const MAX_LIMIT: usize = 100;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Value of the sort column of a row.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(untagged)]
+pub enum Key {
+    Int(i32),
+    Text(String),
+}
+
+/// Position after which the next page starts: the sort column, and the sort
+/// key and id of the last row of the previous page, sent to clients as
+/// opaque base64.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cursor {
+    pub sort: String,
+    pub key: Key,
+    pub id: i32,
+}
+
+impl TryFrom<String> for Cursor {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let json = general_purpose::URL_SAFE_NO_PAD
+            .decode(value)
+            .map_err(|_| "invalid cursor".to_string())?;
+        let (sort, key, id) =
+            serde_json::from_slice(&json).map_err(|_| "invalid cursor".to_string())?;
+        Ok(Self { sort, key, id })
+    }
+}
+
+impl From<Cursor> for String {
+    fn from(cursor: Cursor) -> Self {
+        let json = serde_json::to_vec(&(cursor.sort, cursor.key, cursor.id)).unwrap();
+        general_purpose::URL_SAFE_NO_PAD.encode(json)
+    }
+}
+
+/// Columns orders can be sorted by, ties are broken by id.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderSort {
+    #[default]
+    Id,
+    RegionId,
+    GiftName,
+    Quantity,
+}
+
+impl OrderSort {
+    pub fn column(self) -> &'static str {
+        match self {
+            OrderSort::Id => "id",
+            OrderSort::RegionId => "region_id",
+            OrderSort::GiftName => "gift_name",
+            OrderSort::Quantity => "quantity",
+        }
+    }
+
+    pub fn key(self, order: &Order) -> Key {
+        match self {
+            OrderSort::Id => Key::Int(order.id),
+            OrderSort::RegionId => Key::Int(order.region_id),
+            OrderSort::GiftName => Key::Text(order.gift_name.clone()),
+            OrderSort::Quantity => Key::Int(order.quantity),
+        }
+    }
+
+    pub fn cursor(self, order: &Order) -> Cursor {
+        Cursor {
+            sort: self.column().to_string(),
+            key: self.key(order),
+            id: order.id,
+        }
+    }
+
+    /// Whether `cursor` was issued for this column.
+    pub fn fits(self, cursor: &Cursor) -> bool {
+        cursor.sort == self.column()
+            && match cursor.key {
+                Key::Text(_) => self == OrderSort::GiftName,
+                Key::Int(_) => self != OrderSort::GiftName,
+            }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RegionSort {
+    #[default]
+    Id,
+    Name,
+}
+
+impl RegionSort {
+    pub fn column(self) -> &'static str {
+        match self {
+            RegionSort::Id => "id",
+            RegionSort::Name => "name",
+        }
+    }
+
+    pub fn key(self, region: &Region) -> Key {
+        match self {
+            RegionSort::Id => Key::Int(region.id),
+            RegionSort::Name => Key::Text(region.name.clone()),
+        }
+    }
+
+    pub fn cursor(self, region: &Region) -> Cursor {
+        Cursor {
+            sort: self.column().to_string(),
+            key: self.key(region),
+            id: region.id,
+        }
+    }
+
+    /// Whether `cursor` was issued for this column.
+    pub fn fits(self, cursor: &Cursor) -> bool {
+        cursor.sort == self.column()
+            && match cursor.key {
+                Key::Text(_) => self == RegionSort::Name,
+                Key::Int(_) => self == RegionSort::Id,
+            }
+    }
+}
+
+fn default_limit() -> usize {
+    DEFAULT_LIMIT
+}
+
+/// Query string of `GET /orders`.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct OrderQuery {
+    pub region_id: Option<i32>,
+    pub gift_name: Option<String>,
+    pub min_quantity: Option<i32>,
+    pub max_quantity: Option<i32>,
+    #[serde(default)]
+    pub sort: OrderSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub cursor: Option<Cursor>,
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+impl OrderQuery {
+    pub fn matches(&self, order: &Order) -> bool {
+        self.region_id.is_none_or(|id| order.region_id == id)
+            && self
+                .gift_name
+                .as_ref()
+                .is_none_or(|name| &order.gift_name == name)
+            && self.min_quantity.is_none_or(|min| order.quantity >= min)
+            && self.max_quantity.is_none_or(|max| order.quantity <= max)
+    }
+}
+
+/// Query string of `GET /regions`.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct RegionQuery {
+    pub name: Option<String>,
+    #[serde(default)]
+    pub sort: RegionSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub cursor: Option<Cursor>,
+    #[serde(default = "default_limit")]
+    pub limit: usize,
+}
+
+impl RegionQuery {
+    pub fn matches(&self, region: &Region) -> bool {
+        self.name.as_ref().is_none_or(|name| &region.name == name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<Cursor>,
+}
+
+impl<T> Page<T> {
+    /// Build a page from up to `limit + 1` rows; the extra row only tells
+    /// that there is a next page.
+    pub fn new(mut items: Vec<T>, limit: usize, cursor: impl Fn(&T) -> Cursor) -> Self {
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items.last().map(cursor)
+        } else {
+            None
+        };
+        Self { items, next_cursor }
+    }
+}
+
+/// Sort `rows` by `(key, id)` and return at most `limit` of them, starting
+/// after `cursor`.
+pub fn select<T>(
+    mut rows: Vec<T>,
+    key: impl Fn(&T) -> (Key, i32),
+    order: SortOrder,
+    cursor: Option<&Cursor>,
+    limit: usize,
+) -> Vec<T> {
+    let compare = |a: &(Key, i32), b: &(Key, i32)| match order {
+        SortOrder::Asc => a.cmp(b),
+        SortOrder::Desc => b.cmp(a),
+    };
+    rows.sort_by(|a, b| compare(&key(a), &key(b)));
+    rows.into_iter()
+        .filter(|row| {
+            cursor.is_none_or(|cursor| {
+                compare(&key(row), &(cursor.key.clone(), cursor.id)) == Ordering::Greater
+            })
+        })
+        .take(limit)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trip() {
+        let cursor = Cursor {
+            sort: "gift_name".to_string(),
+            key: Key::Text("Toy Train".to_string()),
+            id: 3,
+        };
+        let encoded = String::from(cursor.clone());
+        assert_eq!(Cursor::try_from(encoded).unwrap(), cursor);
+        assert!(Cursor::try_from("nope".to_string()).is_err());
+
+        assert!(OrderSort::GiftName.fits(&cursor));
+        assert!(!OrderSort::Quantity.fits(&cursor));
+        let forged = Cursor {
+            key: Key::Int(3),
+            ..cursor
+        };
+        assert!(!OrderSort::GiftName.fits(&forged));
+    }
+
+    #[test]
+    fn select_after_cursor() {
+        let rows = vec![(1, 5), (2, 3), (3, 5), (4, 1)];
+        let key = |row: &(i32, i32)| (Key::Int(row.1), row.0);
+
+        let page = select(rows.clone(), key, SortOrder::Desc, None, 2);
+        assert_eq!(page, vec![(3, 5), (1, 5)]);
+
+        let cursor = Cursor {
+            sort: "quantity".to_string(),
+            key: Key::Int(5),
+            id: 1,
+        };
+        let page = select(rows, key, SortOrder::Desc, Some(&cursor), 2);
+        assert_eq!(page, vec![(2, 3), (4, 1)]);
+    }
+}
@@ -0,0 +1,428 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::RwLock,
+};
+
+use async_trait::async_trait;
+use chrono::{NaiveDate, Utc};
+use tokio::sync::watch;
+
+use super::{
+    analytics::{AnalyticsQuery, BucketTotal, Group},
+    dedup_batch,
+    list::{select, OrderQuery, RegionQuery},
+    validate_orders, validate_regions, GiftStore, InsertSummary, OnConflict, Order, OrderPatch,
+    RankBy, RankedGift, Region, RegionPatch, RegionTopList, RegionTotal, TopListQuery,
+};
+use crate::challenge::error::AppError;
+
+/// Store keeping everything in process memory, used for tests and for
+/// running without a database.
+pub struct MemoryStore {
+    data: RwLock<Data>,
+    changes: watch::Sender<u64>,
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self {
+            data: RwLock::default(),
+            changes: watch::channel(0).0,
+        }
+    }
+}
+
+impl MemoryStore {
+    /// Wake the subscribers, called after the data lock is released.
+    fn changed(&self) {
+        self.changes.send_modify(|version| *version += 1);
+    }
+}
+
+#[derive(Default)]
+struct Data {
+    orders: BTreeMap<i32, Order>,
+    regions: BTreeMap<i32, Region>,
+}
+
+impl Data {
+    /// Sum of `value` per gift of the orders matching `filter`.
+    fn gift_counts(
+        &self,
+        filter: impl Fn(&Order) -> bool,
+        value: impl Fn(&Order) -> i64,
+    ) -> HashMap<&str, i64> {
+        self.orders.values().filter(|order| filter(order)).fold(
+            HashMap::new(),
+            |mut counts, order| {
+                *counts.entry(order.gift_name.as_str()).or_default() += value(order);
+                counts
+            },
+        )
+    }
+
+    /// Number of regions every gift is ordered in.
+    fn gift_reach(&self) -> HashMap<&str, i64> {
+        let mut regions = HashMap::<&str, HashSet<i32>>::new();
+        for order in self.orders.values() {
+            regions
+                .entry(order.gift_name.as_str())
+                .or_default()
+                .insert(order.region_id);
+        }
+        regions
+            .into_iter()
+            .map(|(gift, regions)| (gift, regions.len() as i64))
+            .collect()
+    }
+}
+
+/// Insert rows with unique ids into `table`, leaving it untouched if the
+/// batch fails.
+fn upsert<T>(
+    table: &mut BTreeMap<i32, T>,
+    rows: Vec<T>,
+    id: impl Fn(&T) -> i32,
+    on_conflict: OnConflict,
+) -> Result<InsertSummary, AppError> {
+    if on_conflict == OnConflict::Fail {
+        if let Some(row) = rows.iter().find(|row| table.contains_key(&id(row))) {
+            return Err(AppError::Conflict(format!("id {} already exists", id(row))));
+        }
+    }
+
+    let mut summary = InsertSummary::default();
+    for row in rows {
+        let exists = table.contains_key(&id(&row));
+        match (exists, on_conflict) {
+            (false, _) => summary.inserted += 1,
+            (true, OnConflict::Update) => summary.updated += 1,
+            (true, _) => {
+                summary.rejected += 1;
+                continue;
+            }
+        }
+        table.insert(id(&row), row);
+    }
+
+    Ok(summary)
+}
+
+/// Sort gifts by count descending, then by name.
+fn rank_gifts(counts: HashMap<&str, i64>) -> Vec<(&str, i64)> {
+    let mut gifts = counts.into_iter().collect::<Vec<_>>();
+    gifts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    gifts
+}
+
+/// Number the first `num` ranked gifts, ties share the rank of the first one
+/// like SQL's `RANK()`.
+fn number_gifts(ranked: Vec<(&str, i64)>, num: usize) -> Vec<RankedGift> {
+    let mut gifts = Vec::<RankedGift>::new();
+    for (index, (gift, count)) in ranked.into_iter().take(num).enumerate() {
+        let rank = match gifts.last() {
+            Some(previous) if previous.count == count => previous.rank,
+            _ => index as i64 + 1,
+        };
+        gifts.push(RankedGift {
+            gift: gift.to_string(),
+            count,
+            rank,
+        });
+    }
+    gifts
+}
+
+#[async_trait]
+impl GiftStore for MemoryStore {
+    fn subscribe(&self) -> watch::Receiver<u64> {
+        self.changes.subscribe()
+    }
+
+    async fn reset(&self) -> Result<(), AppError> {
+        let mut data = self.data.write().unwrap();
+        data.orders.clear();
+        data.regions.clear();
+        drop(data);
+
+        self.changed();
+        Ok(())
+    }
+
+    async fn insert_orders(
+        &self,
+        orders: Vec<Order>,
+        on_conflict: OnConflict,
+    ) -> Result<InsertSummary, AppError> {
+        validate_orders(&orders)?;
+        let (orders, rejected) = dedup_batch(orders, |order| order.id, on_conflict)?;
+        let mut data = self.data.write().unwrap();
+        let (orders, unknown): (Vec<_>, Vec<_>) = orders
+            .into_iter()
+            .partition(|order| data.regions.contains_key(&order.region_id));
+        if let Some(order) = unknown.first() {
+            if on_conflict != OnConflict::Skip {
+                return Err(AppError::Unprocessable(format!(
+                    "region {} of order {} does not exist",
+                    order.region_id, order.id
+                )));
+            }
+        }
+        let now = Utc::now();
+        let orders = orders
+            .into_iter()
+            .map(|mut order| {
+                order.created_at = match data.orders.get(&order.id) {
+                    Some(existing) => existing.created_at,
+                    None => order.created_at.or(Some(now)),
+                };
+                order
+            })
+            .collect();
+        let mut summary = upsert(&mut data.orders, orders, |order| order.id, on_conflict)?;
+        drop(data);
+
+        self.changed();
+        summary.rejected += rejected + unknown.len() as u64;
+        Ok(summary)
+    }
+
+    async fn insert_regions(
+        &self,
+        regions: Vec<Region>,
+        on_conflict: OnConflict,
+    ) -> Result<InsertSummary, AppError> {
+        validate_regions(&regions)?;
+        let (regions, rejected) = dedup_batch(regions, |region| region.id, on_conflict)?;
+        let mut data = self.data.write().unwrap();
+        let mut summary = upsert(&mut data.regions, regions, |region| region.id, on_conflict)?;
+        drop(data);
+
+        self.changed();
+        summary.rejected += rejected;
+        Ok(summary)
+    }
+
+    async fn total_quantity(&self) -> Result<i64, AppError> {
+        let data = self.data.read().unwrap();
+        Ok(data
+            .orders
+            .values()
+            .map(|order| order.quantity as i64)
+            .sum())
+    }
+
+    async fn popular_gift(&self) -> Result<Option<String>, AppError> {
+        let data = self.data.read().unwrap();
+        Ok(
+            rank_gifts(data.gift_counts(|_| true, |order| order.quantity as i64))
+                .first()
+                .map(|(gift, _)| gift.to_string()),
+        )
+    }
+
+    async fn region_totals(&self) -> Result<Vec<RegionTotal>, AppError> {
+        let data = self.data.read().unwrap();
+        let mut totals = BTreeMap::<&str, i64>::new();
+        for order in data.orders.values() {
+            if let Some(region) = data.regions.get(&order.region_id) {
+                *totals.entry(region.name.as_str()).or_default() += order.quantity as i64;
+            }
+        }
+
+        Ok(totals
+            .into_iter()
+            .map(|(region, total)| RegionTotal {
+                region: region.to_string(),
+                total,
+            })
+            .collect())
+    }
+
+    async fn region_toplist(
+        &self,
+        num: usize,
+        query: &TopListQuery,
+    ) -> Result<Vec<RegionTopList>, AppError> {
+        let data = self.data.read().unwrap();
+        let names = query.regions();
+        let reach = data.gift_reach();
+        let mut toplist = BTreeMap::<&str, Vec<RankedGift>>::new();
+        for region in data.regions.values() {
+            if names
+                .as_ref()
+                .is_some_and(|names| !names.contains(&region.name))
+            {
+                continue;
+            }
+            let in_region = |order: &Order| {
+                data.regions
+                    .get(&order.region_id)
+                    .is_some_and(|r| r.name == region.name)
+            };
+            let counts = match query.rank_by {
+                RankBy::Quantity => data.gift_counts(in_region, |order| order.quantity as i64),
+                RankBy::Orders => data.gift_counts(in_region, |_| 1),
+                RankBy::Reach => data
+                    .gift_counts(in_region, |_| 0)
+                    .into_keys()
+                    .map(|gift| (gift, reach[gift]))
+                    .collect(),
+            };
+            toplist.insert(region.name.as_str(), number_gifts(rank_gifts(counts), num));
+        }
+
+        Ok(toplist
+            .into_iter()
+            .map(|(region, gifts)| RegionTopList {
+                region: region.to_string(),
+                top_gifts: gifts.iter().map(|gift| gift.gift.clone()).collect(),
+                gifts,
+            })
+            .collect())
+    }
+
+    async fn order(&self, id: i32) -> Result<Option<Order>, AppError> {
+        let data = self.data.read().unwrap();
+        Ok(data.orders.get(&id).cloned())
+    }
+
+    async fn update_order(&self, id: i32, patch: OrderPatch) -> Result<Order, AppError> {
+        let mut data = self.data.write().unwrap();
+        let mut order = data
+            .orders
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| AppError::NotFound(format!("order {id} does not exist")))?;
+        patch.apply(&mut order);
+        validate_orders(std::slice::from_ref(&order))?;
+        if !data.regions.contains_key(&order.region_id) {
+            return Err(AppError::Unprocessable(format!(
+                "region {} of order {id} does not exist",
+                order.region_id
+            )));
+        }
+        data.orders.insert(id, order.clone());
+        drop(data);
+
+        self.changed();
+        Ok(order)
+    }
+
+    async fn delete_order(&self, id: i32) -> Result<(), AppError> {
+        let mut data = self.data.write().unwrap();
+        if data.orders.remove(&id).is_none() {
+            return Err(AppError::NotFound(format!("order {id} does not exist")));
+        }
+        drop(data);
+
+        self.changed();
+        Ok(())
+    }
+
+    async fn list_orders(&self, query: &OrderQuery) -> Result<Vec<Order>, AppError> {
+        let data = self.data.read().unwrap();
+        let orders = data
+            .orders
+            .values()
+            .filter(|order| query.matches(order))
+            .cloned()
+            .collect();
+
+        Ok(select(
+            orders,
+            |order| (query.sort.key(order), order.id),
+            query.order,
+            query.cursor.as_ref(),
+            query.limit,
+        ))
+    }
+
+    async fn region(&self, id: i32) -> Result<Option<Region>, AppError> {
+        let data = self.data.read().unwrap();
+        Ok(data.regions.get(&id).cloned())
+    }
+
+    async fn update_region(&self, id: i32, patch: RegionPatch) -> Result<Region, AppError> {
+        let mut data = self.data.write().unwrap();
+        let region = data
+            .regions
+            .get_mut(&id)
+            .ok_or_else(|| AppError::NotFound(format!("region {id} does not exist")))?;
+        let mut updated = region.clone();
+        patch.apply(&mut updated);
+        validate_regions(std::slice::from_ref(&updated))?;
+        *region = updated.clone();
+        drop(data);
+
+        self.changed();
+        Ok(updated)
+    }
+
+    async fn delete_region(&self, id: i32) -> Result<(), AppError> {
+        let mut data = self.data.write().unwrap();
+        if data.orders.values().any(|order| order.region_id == id) {
+            return Err(AppError::Conflict(format!("region {id} still has orders")));
+        }
+        if data.regions.remove(&id).is_none() {
+            return Err(AppError::NotFound(format!("region {id} does not exist")));
+        }
+        drop(data);
+
+        self.changed();
+        Ok(())
+    }
+
+    async fn list_regions(&self, query: &RegionQuery) -> Result<Vec<Region>, AppError> {
+        let data = self.data.read().unwrap();
+        let regions = data
+            .regions
+            .values()
+            .filter(|region| query.matches(region))
+            .cloned()
+            .collect();
+
+        Ok(select(
+            regions,
+            |region| (query.sort.key(region), region.id),
+            query.order,
+            query.cursor.as_ref(),
+            query.limit,
+        ))
+    }
+
+    async fn bucket_totals(
+        &self,
+        group: Group,
+        query: &AnalyticsQuery,
+    ) -> Result<Vec<BucketTotal>, AppError> {
+        let data = self.data.read().unwrap();
+        let mut totals = BTreeMap::<(&str, NaiveDate), i64>::new();
+        for order in data.orders.values() {
+            let date = match order.created_at {
+                Some(created_at) => created_at.date_naive(),
+                None => continue,
+            };
+            if date < query.from || date > query.to {
+                continue;
+            }
+            let name = match group {
+                Group::Region => match data.regions.get(&order.region_id) {
+                    Some(region) => region.name.as_str(),
+                    None => continue,
+                },
+                Group::Gift => order.gift_name.as_str(),
+            };
+            *totals.entry((name, query.bucket.start(date))).or_default() += order.quantity as i64;
+        }
+
+        Ok(totals
+            .into_iter()
+            .map(|((name, start), total)| BucketTotal {
+                name: name.to_string(),
+                start,
+                total,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,378 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use axum::extract::State;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::sync::watch;
+
+use super::{
+    error::AppError,
+    extract::{Json, Query},
+};
+
+pub mod analytics;
+pub mod list;
+mod memory;
+mod postgres;
+
+use analytics::{AnalyticsQuery, BucketTotal, Group};
+use list::{OrderQuery, RegionQuery};
+pub use memory::MemoryStore;
+pub use postgres::PgStore;
+
+#[derive(Clone)]
+pub struct MyState {
+    pub store: Arc<dyn GiftStore>,
+    /// Set when the store is backed by Postgres.
+    pub pool: Option<PgPool>,
+}
+
+impl MyState {
+    pub fn postgres(pool: PgPool) -> Self {
+        Self {
+            store: Arc::new(PgStore::new(pool.clone())),
+            pool: Some(pool),
+        }
+    }
+
+    pub fn memory() -> Self {
+        Self {
+            store: Arc::new(MemoryStore::default()),
+            pool: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, sqlx::FromRow)]
+pub struct Order {
+    pub id: i32,
+    pub region_id: i32,
+    pub gift_name: String,
+    pub quantity: i32,
+    /// Set by the store when the order is first inserted, unless given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, sqlx::FromRow)]
+pub struct Region {
+    pub id: i32,
+    pub name: String,
+}
+
+/// Fields of an order to change, the others are kept.
+#[derive(Deserialize, Debug, Default)]
+pub struct OrderPatch {
+    pub region_id: Option<i32>,
+    pub gift_name: Option<String>,
+    pub quantity: Option<i32>,
+}
+
+impl OrderPatch {
+    pub fn apply(self, order: &mut Order) {
+        if let Some(region_id) = self.region_id {
+            order.region_id = region_id;
+        }
+        if let Some(gift_name) = self.gift_name {
+            order.gift_name = gift_name;
+        }
+        if let Some(quantity) = self.quantity {
+            order.quantity = quantity;
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct RegionPatch {
+    pub name: Option<String>,
+}
+
+impl RegionPatch {
+    pub fn apply(self, region: &mut Region) {
+        if let Some(name) = self.name {
+            region.name = name;
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct RegionTotal {
+    pub region: String,
+    pub total: i64,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct RegionTopList {
+    pub region: String,
+    pub top_gifts: Vec<String>,
+    /// The same gifts with their ranking.
+    pub gifts: Vec<RankedGift>,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq)]
+pub struct RankedGift {
+    pub gift: String,
+    /// Value of the ranking metric.
+    pub count: i64,
+    /// Gifts with the same count share a rank.
+    pub rank: i64,
+}
+
+/// Metric the gifts of a region are ranked by, ties are broken by name.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RankBy {
+    /// Ordered quantity in the region.
+    #[default]
+    Quantity,
+    /// Number of orders in the region.
+    Orders,
+    /// Number of regions the gift is ordered in.
+    Reach,
+}
+
+impl RankBy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RankBy::Quantity => "quantity",
+            RankBy::Orders => "orders",
+            RankBy::Reach => "reach",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct TopListQuery {
+    #[serde(default)]
+    pub rank_by: RankBy,
+    /// Comma separated names of the regions to list, all by default.
+    pub regions: Option<String>,
+}
+
+impl TopListQuery {
+    pub fn regions(&self) -> Option<Vec<String>> {
+        self.regions.as_ref().map(|regions| {
+            regions
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect()
+        })
+    }
+}
+
+/// What a bulk insert does with rows whose id already exists.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    /// Reject the whole batch.
+    #[default]
+    Fail,
+    /// Keep the existing row and reject the new one.
+    Skip,
+    /// Overwrite the existing row.
+    Update,
+}
+
+#[derive(Deserialize, Default)]
+pub struct InsertParams {
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq)]
+pub struct InsertSummary {
+    pub inserted: u64,
+    pub updated: u64,
+    pub rejected: u64,
+}
+
+/// Resolve duplicate ids within one batch according to `on_conflict`: the
+/// first row wins for `skip`, the last one for `update`.
+fn dedup_batch<T>(
+    rows: Vec<T>,
+    id: impl Fn(&T) -> i32,
+    on_conflict: OnConflict,
+) -> Result<(Vec<T>, u64), AppError> {
+    let mut index = HashMap::new();
+    let mut unique = Vec::with_capacity(rows.len());
+    let mut rejected = 0;
+    for row in rows {
+        match index.entry(id(&row)) {
+            Entry::Vacant(entry) => {
+                entry.insert(unique.len());
+                unique.push(row);
+            }
+            Entry::Occupied(entry) => {
+                rejected += 1;
+                match on_conflict {
+                    OnConflict::Fail => {
+                        return Err(AppError::Conflict(format!(
+                            "id {} appears more than once",
+                            entry.key()
+                        )))
+                    }
+                    OnConflict::Skip => {}
+                    OnConflict::Update => unique[*entry.get()] = row,
+                }
+            }
+        }
+    }
+
+    Ok((unique, rejected))
+}
+
+/// Check the columns that the schema constrains, so that a bad row is reported
+/// the same way by every store.
+pub(crate) fn validate_orders(orders: &[Order]) -> Result<(), AppError> {
+    for order in orders {
+        if order.quantity <= 0 {
+            return Err(AppError::Unprocessable(format!(
+                "order {} has a non-positive quantity",
+                order.id
+            )));
+        }
+        if order.gift_name.chars().count() > 50 {
+            return Err(AppError::Unprocessable(format!(
+                "order {} has a gift name longer than 50 characters",
+                order.id
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn validate_regions(regions: &[Region]) -> Result<(), AppError> {
+    match regions
+        .iter()
+        .find(|region| region.name.chars().count() > 50)
+    {
+        Some(region) => Err(AppError::Unprocessable(format!(
+            "region {} has a name longer than 50 characters",
+            region.id
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Storage of the gift orders and regions behind days 13 and 18.
+#[async_trait]
+pub trait GiftStore: Send + Sync {
+    /// Receiver whose value changes after every write to the orders or
+    /// regions.
+    fn subscribe(&self) -> watch::Receiver<u64>;
+
+    /// Remove every order and region.
+    async fn reset(&self) -> Result<(), AppError>;
+
+    /// Insert all orders in one transaction. Orders of unknown regions are
+    /// rejected with `skip` and fail the batch otherwise. Updated orders keep
+    /// their `created_at`.
+    async fn insert_orders(
+        &self,
+        orders: Vec<Order>,
+        on_conflict: OnConflict,
+    ) -> Result<InsertSummary, AppError>;
+
+    /// Insert all regions in one transaction.
+    async fn insert_regions(
+        &self,
+        regions: Vec<Region>,
+        on_conflict: OnConflict,
+    ) -> Result<InsertSummary, AppError>;
+
+    /// Sum of the quantity of every order.
+    async fn total_quantity(&self) -> Result<i64, AppError>;
+
+    /// The gift with the highest ordered quantity, if there are any orders.
+    async fn popular_gift(&self) -> Result<Option<String>, AppError>;
+
+    /// Ordered quantity per region, sorted by region name.
+    async fn region_totals(&self) -> Result<Vec<RegionTotal>, AppError>;
+
+    /// The `num` top ranked gifts of every region, sorted by region name.
+    async fn region_toplist(
+        &self,
+        num: usize,
+        query: &TopListQuery,
+    ) -> Result<Vec<RegionTopList>, AppError>;
+
+    async fn order(&self, id: i32) -> Result<Option<Order>, AppError>;
+
+    /// Change some fields of an existing order.
+    async fn update_order(&self, id: i32, patch: OrderPatch) -> Result<Order, AppError>;
+
+    async fn delete_order(&self, id: i32) -> Result<(), AppError>;
+
+    /// Orders matching the filters of `query`, at most `query.limit` of them.
+    async fn list_orders(&self, query: &OrderQuery) -> Result<Vec<Order>, AppError>;
+
+    async fn region(&self, id: i32) -> Result<Option<Region>, AppError>;
+
+    /// Change some fields of an existing region.
+    async fn update_region(&self, id: i32, patch: RegionPatch) -> Result<Region, AppError>;
+
+    /// Delete a region, which fails while orders still refer to it.
+    async fn delete_region(&self, id: i32) -> Result<(), AppError>;
+
+    /// Regions matching the filters of `query`, at most `query.limit` of them.
+    async fn list_regions(&self, query: &RegionQuery) -> Result<Vec<Region>, AppError>;
+
+    /// Ordered quantity per region or gift and per bucket of creation time,
+    /// leaving out empty buckets.
+    async fn bucket_totals(
+        &self,
+        group: Group,
+        query: &AnalyticsQuery,
+    ) -> Result<Vec<BucketTotal>, AppError>;
+}
+
+pub async fn reset(State(state): State<MyState>) -> Result<(), AppError> {
+    state.store.reset().await
+}
+
+pub async fn insert_orders(
+    State(state): State<MyState>,
+    Query(params): Query<InsertParams>,
+    Json(data): Json<Vec<Order>>,
+) -> Result<Json<InsertSummary>, AppError> {
+    let summary = state.store.insert_orders(data, params.on_conflict).await?;
+    Ok(Json(summary))
+}
+
+pub async fn insert_regions(
+    State(state): State<MyState>,
+    Query(params): Query<InsertParams>,
+    Json(data): Json<Vec<Region>>,
+) -> Result<Json<InsertSummary>, AppError> {
+    let summary = state.store.insert_regions(data, params.on_conflict).await?;
+    Ok(Json(summary))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dedup_within_batch() {
+        let rows = vec![(1, "a"), (2, "b"), (1, "c")];
+
+        let (unique, rejected) = dedup_batch(rows.clone(), |row| row.0, OnConflict::Skip).unwrap();
+        assert_eq!(unique, vec![(1, "a"), (2, "b")]);
+        assert_eq!(rejected, 1);
+
+        let (unique, rejected) =
+            dedup_batch(rows.clone(), |row| row.0, OnConflict::Update).unwrap();
+        assert_eq!(unique, vec![(1, "c"), (2, "b")]);
+        assert_eq!(rejected, 1);
+
+        let error = dedup_batch(rows, |row| row.0, OnConflict::Fail).unwrap_err();
+        assert_eq!(error.code(), "conflict");
+    }
+}
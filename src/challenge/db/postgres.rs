@@ -0,0 +1,841 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{postgres::PgListener, PgExecutor, PgPool, Postgres, QueryBuilder};
+use tokio::sync::watch;
+
+use super::{
+    analytics::{AnalyticsQuery, BucketTotal, Group},
+    dedup_batch,
+    list::{Cursor, Key, OrderQuery, RegionQuery, SortOrder},
+    validate_orders, validate_regions, GiftStore, InsertSummary, OnConflict, Order, OrderPatch,
+    RankedGift, Region, RegionPatch, RegionTopList, RegionTotal, TopListQuery,
+};
+use crate::challenge::error::AppError;
+
+/// Channel notified by every change, so that all instances sharing the
+/// database see each other's writes.
+const CHANNEL: &str = "gift_store";
+
+pub struct PgStore {
+    pool: PgPool,
+    changes: watch::Sender<u64>,
+}
+
+impl PgStore {
+    /// Must be called within a Tokio runtime, which runs the listener of
+    /// change notifications.
+    pub fn new(pool: PgPool) -> Self {
+        let (changes, _) = watch::channel(0);
+        tokio::spawn(listen(pool.clone(), changes.clone()));
+        Self { pool, changes }
+    }
+}
+
+async fn listen(pool: PgPool, changes: watch::Sender<u64>) {
+    let mut listener = match PgListener::connect_with(&pool).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            tracing::error!("failed to listen to store changes: {e}");
+            return;
+        }
+    };
+    if let Err(e) = listener.listen(CHANNEL).await {
+        tracing::error!("failed to listen to store changes: {e}");
+        return;
+    }
+
+    loop {
+        match listener.recv().await {
+            Ok(_) => changes.send_modify(|version| *version += 1),
+            Err(e) => {
+                // the listener reconnects on the next call
+                tracing::warn!("lost store change notifications: {e}");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+/// Notify the listeners of `CHANNEL`. Within a transaction the notification
+/// is only sent on commit.
+async fn notify(executor: impl PgExecutor<'_>) -> Result<(), AppError> {
+    sqlx::query!("NOTIFY gift_store").execute(executor).await?;
+    Ok(())
+}
+
+/// Report a violated constraint as a client error instead of a server failure.
+fn conflict(error: sqlx::Error) -> AppError {
+    match error.as_database_error() {
+        Some(e) if e.is_unique_violation() => AppError::Conflict(e.message().to_string()),
+        Some(e) if e.is_foreign_key_violation() || e.is_check_violation() => {
+            AppError::Unprocessable(e.message().to_string())
+        }
+        _ => error.into(),
+    }
+}
+
+/// Append the keyset condition, ordering and limit of a list query.
+fn push_page(
+    builder: &mut QueryBuilder<'_, Postgres>,
+    column: &'static str,
+    order: SortOrder,
+    cursor: Option<&Cursor>,
+    limit: usize,
+) {
+    let (compare, direction) = match order {
+        SortOrder::Asc => (">", "ASC"),
+        SortOrder::Desc => ("<", "DESC"),
+    };
+    if let Some(cursor) = cursor {
+        builder.push(format_args!(" AND ({column}, id) {compare} ("));
+        match &cursor.key {
+            Key::Int(value) => builder.push_bind(*value),
+            Key::Text(value) => builder.push_bind(value.clone()),
+        };
+        builder.push(", ").push_bind(cursor.id).push(")");
+    }
+    builder
+        .push(format_args!(
+            " ORDER BY {column} {direction}, id {direction} LIMIT "
+        ))
+        .push_bind(limit as i64);
+}
+
+#[async_trait]
+impl GiftStore for PgStore {
+    fn subscribe(&self) -> watch::Receiver<u64> {
+        self.changes.subscribe()
+    }
+
+    async fn reset(&self) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query!("TRUNCATE orders, regions")
+            .execute(&mut *tx)
+            .await?;
+        notify(&mut *tx).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn insert_orders(
+        &self,
+        orders: Vec<Order>,
+        on_conflict: OnConflict,
+    ) -> Result<InsertSummary, AppError> {
+        validate_orders(&orders)?;
+        let (orders, rejected) = dedup_batch(orders, |order| order.id, on_conflict)?;
+        let ids = orders.iter().map(|order| order.id).collect::<Vec<_>>();
+        let region_ids = orders
+            .iter()
+            .map(|order| order.region_id)
+            .collect::<Vec<_>>();
+        let gift_names = orders
+            .iter()
+            .map(|order| order.gift_name.clone())
+            .collect::<Vec<_>>();
+        let quantities = orders
+            .iter()
+            .map(|order| order.quantity)
+            .collect::<Vec<_>>();
+        let created_at = orders
+            .iter()
+            .map(|order| order.created_at)
+            .collect::<Vec<_>>();
+
+        let mut tx = self.pool.begin().await?;
+        let mut summary = match on_conflict {
+            OnConflict::Fail => {
+                let result = sqlx::query!(
+                    "INSERT INTO orders (id, region_id, gift_name, quantity, created_at)
+                    SELECT id, region_id, gift_name, quantity, COALESCE(created_at, now())
+                    FROM UNNEST($1::INT[], $2::INT[], $3::TEXT[], $4::INT[], $5::TIMESTAMPTZ[])
+                        AS batch (id, region_id, gift_name, quantity, created_at)",
+                    &ids[..],
+                    &region_ids[..],
+                    &gift_names[..],
+                    &quantities[..],
+                    &created_at[..] as &[Option<DateTime<Utc>>],
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(conflict)?;
+                InsertSummary {
+                    inserted: result.rows_affected(),
+                    ..Default::default()
+                }
+            }
+            OnConflict::Skip => {
+                let result = sqlx::query!(
+                    "INSERT INTO orders (id, region_id, gift_name, quantity, created_at)
+                    SELECT id, region_id, gift_name, quantity, COALESCE(created_at, now())
+                    FROM UNNEST($1::INT[], $2::INT[], $3::TEXT[], $4::INT[], $5::TIMESTAMPTZ[])
+                        AS batch (id, region_id, gift_name, quantity, created_at)
+                    WHERE EXISTS (SELECT 1 FROM regions WHERE regions.id = batch.region_id)
+                    ON CONFLICT (id) DO NOTHING",
+                    &ids[..],
+                    &region_ids[..],
+                    &gift_names[..],
+                    &quantities[..],
+                    &created_at[..] as &[Option<DateTime<Utc>>],
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(conflict)?;
+                InsertSummary {
+                    inserted: result.rows_affected(),
+                    rejected: ids.len() as u64 - result.rows_affected(),
+                    ..Default::default()
+                }
+            }
+            OnConflict::Update => {
+                // xmax is only set on rows that were updated
+                let records = sqlx::query!(
+                    r#"INSERT INTO orders (id, region_id, gift_name, quantity, created_at)
+                    SELECT id, region_id, gift_name, quantity, COALESCE(created_at, now())
+                    FROM UNNEST($1::INT[], $2::INT[], $3::TEXT[], $4::INT[], $5::TIMESTAMPTZ[])
+                        AS batch (id, region_id, gift_name, quantity, created_at)
+                    ON CONFLICT (id) DO UPDATE SET
+                        region_id = EXCLUDED.region_id,
+                        gift_name = EXCLUDED.gift_name,
+                        quantity = EXCLUDED.quantity
+                    RETURNING (xmax = 0) AS "inserted!""#,
+                    &ids[..],
+                    &region_ids[..],
+                    &gift_names[..],
+                    &quantities[..],
+                    &created_at[..] as &[Option<DateTime<Utc>>],
+                )
+                .fetch_all(&mut *tx)
+                .await
+                .map_err(conflict)?;
+                let inserted = records.iter().filter(|record| record.inserted).count() as u64;
+                InsertSummary {
+                    inserted,
+                    updated: records.len() as u64 - inserted,
+                    ..Default::default()
+                }
+            }
+        };
+        notify(&mut *tx).await?;
+        tx.commit().await?;
+
+        summary.rejected += rejected;
+        Ok(summary)
+    }
+
+    async fn insert_regions(
+        &self,
+        regions: Vec<Region>,
+        on_conflict: OnConflict,
+    ) -> Result<InsertSummary, AppError> {
+        validate_regions(&regions)?;
+        let (regions, rejected) = dedup_batch(regions, |region| region.id, on_conflict)?;
+        let ids = regions.iter().map(|region| region.id).collect::<Vec<_>>();
+        let names = regions
+            .iter()
+            .map(|region| region.name.clone())
+            .collect::<Vec<_>>();
+
+        let mut tx = self.pool.begin().await?;
+        let mut summary = match on_conflict {
+            OnConflict::Fail => {
+                let result = sqlx::query!(
+                    "INSERT INTO regions (id, name)
+                    SELECT * FROM UNNEST($1::INT[], $2::TEXT[])",
+                    &ids[..],
+                    &names[..],
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(conflict)?;
+                InsertSummary {
+                    inserted: result.rows_affected(),
+                    ..Default::default()
+                }
+            }
+            OnConflict::Skip => {
+                let result = sqlx::query!(
+                    "INSERT INTO regions (id, name)
+                    SELECT * FROM UNNEST($1::INT[], $2::TEXT[])
+                    ON CONFLICT (id) DO NOTHING",
+                    &ids[..],
+                    &names[..],
+                )
+                .execute(&mut *tx)
+                .await
+                .map_err(conflict)?;
+                InsertSummary {
+                    inserted: result.rows_affected(),
+                    rejected: ids.len() as u64 - result.rows_affected(),
+                    ..Default::default()
+                }
+            }
+            OnConflict::Update => {
+                let records = sqlx::query!(
+                    r#"INSERT INTO regions (id, name)
+                    SELECT * FROM UNNEST($1::INT[], $2::TEXT[])
+                    ON CONFLICT (id) DO UPDATE SET name = EXCLUDED.name
+                    RETURNING (xmax = 0) AS "inserted!""#,
+                    &ids[..],
+                    &names[..],
+                )
+                .fetch_all(&mut *tx)
+                .await
+                .map_err(conflict)?;
+                let inserted = records.iter().filter(|record| record.inserted).count() as u64;
+                InsertSummary {
+                    inserted,
+                    updated: records.len() as u64 - inserted,
+                    ..Default::default()
+                }
+            }
+        };
+        notify(&mut *tx).await?;
+        tx.commit().await?;
+
+        summary.rejected += rejected;
+        Ok(summary)
+    }
+
+    async fn total_quantity(&self) -> Result<i64, AppError> {
+        let record = sqlx::query!(r#"SELECT COALESCE(SUM(quantity), 0) as "total!" FROM orders"#)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(record.total)
+    }
+
+    async fn popular_gift(&self) -> Result<Option<String>, AppError> {
+        let record = sqlx::query!(
+            r#"SELECT gift_name as "popular!", SUM(quantity) AS gift_count
+                FROM orders
+                GROUP BY gift_name
+                ORDER BY gift_count DESC, gift_name
+                LIMIT 1"#
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(record.map(|record| record.popular))
+    }
+
+    async fn region_totals(&self) -> Result<Vec<RegionTotal>, AppError> {
+        let records = sqlx::query_as!(
+            RegionTotal,
+            r#"SELECT regions.name as "region!", SUM(orders.quantity) as "total!" FROM orders
+        INNER JOIN regions ON orders.region_id=regions.id GROUP BY regions.name ORDER BY regions.name"#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(records)
+    }
+
+    async fn region_toplist(
+        &self,
+        num: usize,
+        query: &TopListQuery,
+    ) -> Result<Vec<RegionTopList>, AppError> {
+        let regions = query.regions();
+        // every listed region is returned, those without orders have no gift
+        let records = sqlx::query!(
+            r#"WITH gifts AS (
+                SELECT regions.name AS region, orders.gift_name AS gift,
+                    SUM(orders.quantity) AS quantity, COUNT(*) AS orders
+                FROM orders INNER JOIN regions ON orders.region_id = regions.id
+                GROUP BY regions.name, orders.gift_name
+            ), reach AS (
+                SELECT gift_name AS gift, COUNT(DISTINCT region_id) AS reach
+                FROM orders GROUP BY gift_name
+            ), ranked AS (
+                SELECT gifts.region, gifts.gift,
+                    CASE $2
+                        WHEN 'orders' THEN gifts.orders
+                        WHEN 'reach' THEN reach.reach
+                        ELSE gifts.quantity
+                    END AS count
+                FROM gifts INNER JOIN reach ON gifts.gift = reach.gift
+            ), numbered AS (
+                SELECT region, gift, count,
+                    RANK() OVER (PARTITION BY region ORDER BY count DESC) AS rank,
+                    ROW_NUMBER() OVER (PARTITION BY region ORDER BY count DESC, gift) AS position
+                FROM ranked
+            )
+            SELECT names.name as "region!", numbered.gift as "gift?",
+                numbered.count as "count?", numbered.rank as "rank?"
+            FROM (SELECT DISTINCT name FROM regions) AS names
+            LEFT JOIN numbered ON numbered.region = names.name AND numbered.position <= $1
+            WHERE $3::TEXT[] IS NULL OR names.name = ANY($3)
+            ORDER BY names.name, numbered.position"#,
+            num as i64,
+            query.rank_by.as_str(),
+            regions.as_deref(),
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut toplist = BTreeMap::<String, Vec<RankedGift>>::new();
+        for record in records {
+            let gifts = toplist.entry(record.region).or_default();
+            if let (Some(gift), Some(count), Some(rank)) = (record.gift, record.count, record.rank)
+            {
+                gifts.push(RankedGift { gift, count, rank });
+            }
+        }
+
+        Ok(toplist
+            .into_iter()
+            .map(|(region, gifts)| RegionTopList {
+                region,
+                top_gifts: gifts.iter().map(|gift| gift.gift.clone()).collect(),
+                gifts,
+            })
+            .collect())
+    }
+
+    async fn order(&self, id: i32) -> Result<Option<Order>, AppError> {
+        let order = sqlx::query_as!(
+            Order,
+            r#"SELECT id, region_id, gift_name, quantity, created_at as "created_at?"
+                FROM orders WHERE id = $1"#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(order)
+    }
+
+    async fn update_order(&self, id: i32, patch: OrderPatch) -> Result<Order, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let mut order = sqlx::query_as!(
+            Order,
+            r#"SELECT id, region_id, gift_name, quantity, created_at as "created_at?"
+                FROM orders WHERE id = $1 FOR UPDATE"#,
+            id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("order {id} does not exist")))?;
+        patch.apply(&mut order);
+        validate_orders(std::slice::from_ref(&order))?;
+        sqlx::query!(
+            "UPDATE orders SET region_id = $2, gift_name = $3, quantity = $4 WHERE id = $1",
+            id,
+            order.region_id,
+            order.gift_name,
+            order.quantity,
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(conflict)?;
+        notify(&mut *tx).await?;
+        tx.commit().await?;
+
+        Ok(order)
+    }
+
+    async fn delete_order(&self, id: i32) -> Result<(), AppError> {
+        let result = sqlx::query!("DELETE FROM orders WHERE id = $1", id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("order {id} does not exist")));
+        }
+        notify(&self.pool).await
+    }
+
+    async fn list_orders(&self, query: &OrderQuery) -> Result<Vec<Order>, AppError> {
+        let mut builder = QueryBuilder::new(
+            "SELECT id, region_id, gift_name, quantity, created_at FROM orders WHERE TRUE",
+        );
+        if let Some(region_id) = query.region_id {
+            builder.push(" AND region_id = ").push_bind(region_id);
+        }
+        if let Some(gift_name) = &query.gift_name {
+            builder
+                .push(" AND gift_name = ")
+                .push_bind(gift_name.clone());
+        }
+        if let Some(min) = query.min_quantity {
+            builder.push(" AND quantity >= ").push_bind(min);
+        }
+        if let Some(max) = query.max_quantity {
+            builder.push(" AND quantity <= ").push_bind(max);
+        }
+        push_page(
+            &mut builder,
+            query.sort.column(),
+            query.order,
+            query.cursor.as_ref(),
+            query.limit,
+        );
+
+        let orders = builder
+            .build_query_as::<Order>()
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(orders)
+    }
+
+    async fn region(&self, id: i32) -> Result<Option<Region>, AppError> {
+        let region = sqlx::query_as!(Region, "SELECT id, name FROM regions WHERE id = $1", id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(region)
+    }
+
+    async fn update_region(&self, id: i32, patch: RegionPatch) -> Result<Region, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let mut region = sqlx::query_as!(
+            Region,
+            "SELECT id, name FROM regions WHERE id = $1 FOR UPDATE",
+            id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("region {id} does not exist")))?;
+        patch.apply(&mut region);
+        validate_regions(std::slice::from_ref(&region))?;
+        sqlx::query!(
+            "UPDATE regions SET name = $2 WHERE id = $1",
+            id,
+            region.name
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(conflict)?;
+        notify(&mut *tx).await?;
+        tx.commit().await?;
+
+        Ok(region)
+    }
+
+    async fn delete_region(&self, id: i32) -> Result<(), AppError> {
+        let result = sqlx::query!("DELETE FROM regions WHERE id = $1", id)
+            .execute(&self.pool)
+            .await
+            .map_err(|error| match error.as_database_error() {
+                Some(e) if e.is_foreign_key_violation() => {
+                    AppError::Conflict(format!("region {id} still has orders"))
+                }
+                _ => error.into(),
+            })?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound(format!("region {id} does not exist")));
+        }
+        notify(&self.pool).await
+    }
+
+    async fn list_regions(&self, query: &RegionQuery) -> Result<Vec<Region>, AppError> {
+        let mut builder = QueryBuilder::new("SELECT id, name FROM regions WHERE TRUE");
+        if let Some(name) = &query.name {
+            builder.push(" AND name = ").push_bind(name.clone());
+        }
+        push_page(
+            &mut builder,
+            query.sort.column(),
+            query.order,
+            query.cursor.as_ref(),
+            query.limit,
+        );
+
+        let regions = builder
+            .build_query_as::<Region>()
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(regions)
+    }
+
+    async fn bucket_totals(
+        &self,
+        group: Group,
+        query: &AnalyticsQuery,
+    ) -> Result<Vec<BucketTotal>, AppError> {
+        let totals = match group {
+            Group::Region => {
+                sqlx::query_as!(
+                    BucketTotal,
+                    r#"SELECT regions.name as "name!",
+                        date_trunc($1, orders.created_at AT TIME ZONE 'UTC')::DATE as "start!",
+                        SUM(orders.quantity) as "total!"
+                    FROM orders INNER JOIN regions ON orders.region_id = regions.id
+                    WHERE orders.created_at >= $2::DATE::TIMESTAMP AT TIME ZONE 'UTC'
+                        AND orders.created_at < ($3::DATE + 1)::TIMESTAMP AT TIME ZONE 'UTC'
+                    GROUP BY 1, 2 ORDER BY 1, 2"#,
+                    query.bucket.unit(),
+                    query.from,
+                    query.to,
+                )
+                .fetch_all(&self.pool)
+                .await?
+            }
+            Group::Gift => {
+                sqlx::query_as!(
+                    BucketTotal,
+                    r#"SELECT gift_name as "name!",
+                        date_trunc($1, created_at AT TIME ZONE 'UTC')::DATE as "start!",
+                        SUM(quantity) as "total!"
+                    FROM orders
+                    WHERE created_at >= $2::DATE::TIMESTAMP AT TIME ZONE 'UTC'
+                        AND created_at < ($3::DATE + 1)::TIMESTAMP AT TIME ZONE 'UTC'
+                    GROUP BY 1, 2 ORDER BY 1, 2"#,
+                    query.bucket.unit(),
+                    query.from,
+                    query.to,
+                )
+                .fetch_all(&self.pool)
+                .await?
+            }
+        };
+
+        Ok(totals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenge::db::{MemoryStore, RankBy};
+
+    fn order(id: i32, quantity: i32) -> Order {
+        Order {
+            id,
+            region_id: 1,
+            gift_name: "Toy Train".to_string(),
+            quantity,
+            created_at: None,
+        }
+    }
+
+    fn region(id: i32, name: &str) -> Region {
+        Region {
+            id,
+            name: name.to_string(),
+        }
+    }
+
+    async fn region_names(store: &PgStore) -> Vec<String> {
+        let mut names = store
+            .region_toplist(0, &TopListQuery::default())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|list| list.region)
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[sqlx::test]
+    async fn insert_orders(pool: PgPool) {
+        let store = PgStore::new(pool);
+        store
+            .insert_regions(vec![region(1, "North Pole")], OnConflict::Fail)
+            .await
+            .unwrap();
+        let summary = store
+            .insert_orders(vec![order(1, 1), order(2, 2)], OnConflict::Fail)
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            InsertSummary {
+                inserted: 2,
+                ..Default::default()
+            }
+        );
+
+        // the new order of a batch holding a stored id is rolled back too
+        let error = store
+            .insert_orders(vec![order(3, 3), order(1, 10)], OnConflict::Fail)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, AppError::Conflict(_)));
+        assert_eq!(store.total_quantity().await.unwrap(), 3);
+
+        let summary = store
+            .insert_orders(vec![order(1, 10), order(3, 3)], OnConflict::Skip)
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            InsertSummary {
+                inserted: 1,
+                rejected: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(store.total_quantity().await.unwrap(), 6);
+
+        // the last of the duplicates within the batch wins
+        let summary = store
+            .insert_orders(
+                vec![order(1, 10), order(4, 4), order(4, 5)],
+                OnConflict::Update,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            InsertSummary {
+                inserted: 1,
+                updated: 1,
+                rejected: 1,
+            }
+        );
+        assert_eq!(store.total_quantity().await.unwrap(), 10 + 2 + 3 + 5);
+    }
+
+    #[sqlx::test]
+    async fn insert_regions(pool: PgPool) {
+        let store = PgStore::new(pool);
+        store
+            .insert_regions(vec![region(1, "North Pole")], OnConflict::Fail)
+            .await
+            .unwrap();
+
+        let error = store
+            .insert_regions(
+                vec![region(2, "Lapland"), region(1, "South Pole")],
+                OnConflict::Fail,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(error, AppError::Conflict(_)));
+        assert_eq!(region_names(&store).await, vec!["North Pole"]);
+
+        let summary = store
+            .insert_regions(
+                vec![region(1, "South Pole"), region(2, "Lapland")],
+                OnConflict::Skip,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            InsertSummary {
+                inserted: 1,
+                rejected: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(region_names(&store).await, vec!["Lapland", "North Pole"]);
+
+        let summary = store
+            .insert_regions(
+                vec![region(1, "South Pole"), region(3, "Greenland")],
+                OnConflict::Update,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            InsertSummary {
+                inserted: 1,
+                updated: 1,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            region_names(&store).await,
+            vec!["Greenland", "Lapland", "South Pole"]
+        );
+    }
+
+    #[sqlx::test]
+    async fn orders_of_unknown_regions(pool: PgPool) {
+        let store = PgStore::new(pool);
+        store
+            .insert_regions(vec![region(1, "North Pole")], OnConflict::Fail)
+            .await
+            .unwrap();
+        let unknown = Order {
+            region_id: 2,
+            ..order(2, 2)
+        };
+
+        let error = store
+            .insert_orders(vec![order(1, 1), unknown.clone()], OnConflict::Fail)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, AppError::Unprocessable(_)));
+        assert_eq!(store.total_quantity().await.unwrap(), 0);
+
+        // skipped like a stored id
+        let summary = store
+            .insert_orders(vec![order(1, 1), unknown.clone()], OnConflict::Skip)
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            InsertSummary {
+                inserted: 1,
+                rejected: 1,
+                ..Default::default()
+            }
+        );
+
+        let error = store
+            .insert_orders(vec![unknown], OnConflict::Update)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, AppError::Unprocessable(_)));
+        assert_eq!(store.total_quantity().await.unwrap(), 1);
+    }
+
+    #[sqlx::test]
+    async fn region_toplist_matches_memory(pool: PgPool) {
+        let regions = vec![
+            region(1, "North Pole"),
+            region(2, "Lapland"),
+            region(3, "Greenland"),
+        ];
+        let orders = [
+            (1, 1, "Toy Train", 5),
+            (2, 1, "Doll", 2),
+            (3, 1, "Doll", 2),
+            (4, 1, "Ball", 1),
+            (5, 2, "Ball", 4),
+            (6, 2, "Doll", 4),
+            (7, 2, "Kite", 1),
+        ]
+        .into_iter()
+        .map(|(id, region_id, gift_name, quantity)| Order {
+            id,
+            region_id,
+            gift_name: gift_name.to_string(),
+            quantity,
+            created_at: None,
+        })
+        .collect::<Vec<_>>();
+
+        let pg = PgStore::new(pool);
+        let memory = MemoryStore::default();
+        for store in [&pg as &dyn GiftStore, &memory] {
+            store
+                .insert_regions(regions.clone(), OnConflict::Fail)
+                .await
+                .unwrap();
+            store
+                .insert_orders(orders.clone(), OnConflict::Fail)
+                .await
+                .unwrap();
+        }
+
+        for rank_by in [RankBy::Quantity, RankBy::Orders, RankBy::Reach] {
+            for regions in [None, Some("Lapland, Greenland".to_string())] {
+                let query = TopListQuery { rank_by, regions };
+                assert_eq!(
+                    pg.region_toplist(2, &query).await.unwrap(),
+                    memory.region_toplist(2, &query).await.unwrap(),
+                    "{query:?}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use axum::{
+    extract::{
+        multipart::{MultipartError, MultipartRejection},
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        ws::rejection::WebSocketUpgradeRejection,
+    },
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use super::day20::extract::ExtractError;
+
+/// Error returned by every fallible handler, rendered as an RFC 7807
+/// `application/problem+json` body.
+#[derive(Debug, thiserror::Error)]
+pub enum AppError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    Unprocessable(String),
+    #[error("{0}")]
+    Unavailable(String),
+    #[error("body larger than {0} bytes")]
+    PayloadTooLarge(u64),
+    #[error("unsupported media type `{0}`")]
+    UnsupportedMediaType(String),
+    #[error("cookie `{0}` is missing")]
+    MissingCookie(&'static str),
+    #[error("invalid base64: {0}")]
+    InvalidBase64(#[from] base64::DecodeError),
+    #[error("invalid JSON: {0}")]
+    InvalidJson(#[from] serde_json::Error),
+    #[error("`{0}` is not a valid integer")]
+    InvalidInteger(String),
+    #[error("`{0}` is not a valid ULID")]
+    InvalidUlid(String),
+    #[error("`{0}` is not a valid S2 cell id")]
+    InvalidCellId(String),
+    #[error("invalid image: {0}")]
+    InvalidImage(String),
+    #[error("invalid multipart body: {0}")]
+    InvalidMultipart(#[from] MultipartError),
+    #[error("invalid archive: {0}")]
+    InvalidArchive(#[source] std::io::Error),
+    #[error("unsafe archive: {0}")]
+    UnsafeArchive(#[source] ExtractError),
+    #[error("git error: {0}")]
+    Git(#[from] git2::Error),
+    #[error("upstream request failed: {0}")]
+    Upstream(#[from] reqwest::Error),
+    /// Boxed, as it is much larger than every other variant.
+    #[error("regex error: {0}")]
+    Regex(#[source] Box<fancy_regex::Error>),
+    #[error("database error: {0}")]
+    Database(#[from] sqlx::Error),
+    /// A request refused by one of the extractors of axum.
+    #[error("{detail}")]
+    Rejected {
+        status: StatusCode,
+        code: &'static str,
+        detail: String,
+    },
+    #[error("{0}")]
+    Internal(String),
+}
+
+/// Problem details object as described by RFC 7807, extended with a
+/// machine readable `code`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    pub code: String,
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::BadRequest(_)
+            | AppError::MissingCookie(_)
+            | AppError::InvalidBase64(_)
+            | AppError::InvalidJson(_)
+            | AppError::InvalidInteger(_)
+            | AppError::InvalidUlid(_)
+            | AppError::InvalidCellId(_)
+            | AppError::InvalidMultipart(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::Unprocessable(_)
+            | AppError::InvalidImage(_)
+            | AppError::InvalidArchive(_)
+            | AppError::UnsafeArchive(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Git(e) if e.code() == git2::ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            AppError::Git(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Upstream(_) => StatusCode::BAD_GATEWAY,
+            AppError::Rejected { status, .. } => *status,
+            AppError::Regex(_) | AppError::Database(_) | AppError::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::Unprocessable(_) => "unprocessable",
+            AppError::Unavailable(_) => "unavailable",
+            AppError::PayloadTooLarge(_) => "payload_too_large",
+            AppError::UnsupportedMediaType(_) => "unsupported_media_type",
+            AppError::MissingCookie(_) => "missing_cookie",
+            AppError::InvalidBase64(_) => "invalid_base64",
+            AppError::InvalidJson(_) => "invalid_json",
+            AppError::InvalidInteger(_) => "invalid_integer",
+            AppError::InvalidUlid(_) => "invalid_ulid",
+            AppError::InvalidCellId(_) => "invalid_cell_id",
+            AppError::InvalidImage(_) => "invalid_image",
+            AppError::InvalidMultipart(_) => "invalid_multipart",
+            AppError::InvalidArchive(_) => "invalid_archive",
+            AppError::UnsafeArchive(e) => e.code(),
+            AppError::Git(e) if e.code() == git2::ErrorCode::NotFound => "git_not_found",
+            AppError::Git(_) => "invalid_repository",
+            AppError::Upstream(_) => "upstream_error",
+            AppError::Regex(_) => "regex_error",
+            AppError::Database(_) => "database_error",
+            AppError::Rejected { code, .. } => code,
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+}
+
+impl From<fancy_regex::Error> for AppError {
+    fn from(e: fancy_regex::Error) -> Self {
+        AppError::Regex(Box::new(e))
+    }
+}
+
+impl AppError {
+    fn rejected(status: StatusCode, code: &'static str, detail: String) -> Self {
+        AppError::Rejected {
+            status,
+            code,
+            detail,
+        }
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        let code = match rejection {
+            JsonRejection::JsonDataError(_) | JsonRejection::JsonSyntaxError(_) => "invalid_json",
+            JsonRejection::MissingJsonContentType(_) => "unsupported_media_type",
+            _ => "invalid_body",
+        };
+        AppError::rejected(rejection.status(), code, rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::rejected(rejection.status(), "invalid_query", rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        AppError::rejected(rejection.status(), "invalid_path", rejection.body_text())
+    }
+}
+
+impl From<MultipartRejection> for AppError {
+    fn from(rejection: MultipartRejection) -> Self {
+        AppError::rejected(
+            rejection.status(),
+            "invalid_multipart",
+            rejection.body_text(),
+        )
+    }
+}
+
+impl From<WebSocketUpgradeRejection> for AppError {
+    fn from(rejection: WebSocketUpgradeRejection) -> Self {
+        AppError::rejected(
+            rejection.status(),
+            "invalid_websocket_upgrade",
+            rejection.body_text(),
+        )
+    }
+}
+
+impl From<ExtractError> for AppError {
+    fn from(e: ExtractError) -> Self {
+        match e {
+            ExtractError::Io(e) => AppError::InvalidArchive(e),
+            e => AppError::UnsafeArchive(e),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        // server side failures are logged but not leaked to the client
+        let detail = if status.is_server_error() {
+            tracing::error!("{self}");
+            status
+                .canonical_reason()
+                .unwrap_or("Internal Server Error")
+                .to_string()
+        } else {
+            self.to_string()
+        };
+
+        let problem = Problem {
+            kind: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            detail,
+            code: self.code().to_string(),
+        };
+
+        (
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            Json(problem),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+pub fn assert_problem(response: &axum_test::TestResponse, status: StatusCode, code: &str) {
+    response.assert_status(status);
+    assert!(response
+        .headers()
+        .get("content-type")
+        .is_some_and(|v| v == "application/problem+json"));
+
+    let problem = response.json::<Problem>();
+    assert_eq!(problem.status, status.as_u16());
+    assert_eq!(problem.code, code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_and_code() {
+        let error = AppError::InvalidUlid("nope".to_string());
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(error.code(), "invalid_ulid");
+
+        let error = AppError::Internal("disk on fire".to_string());
+        assert_eq!(error.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error.code(), "internal_error");
+    }
+
+    #[test]
+    fn server_errors_hide_detail() {
+        let response = AppError::Internal("disk on fire".to_string()).into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/problem+json"
+        );
+    }
+}
@@ -0,0 +1,533 @@
+use std::{future::Future, io, pin::pin};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use bytes::Bytes;
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncBufReadExt;
+use tokio_util::io::StreamReader;
+
+use super::{
+    db::{
+        list::{Cursor, OrderQuery, Page, RegionQuery},
+        validate_orders, GiftStore, InsertParams, InsertSummary, MyState, OnConflict, Order,
+    },
+    error::AppError,
+    extract::{Json, Query},
+};
+
+/// Rows inserted per transaction while importing.
+const IMPORT_BATCH: usize = 500;
+/// CSV header of the exports, also written when a table is empty.
+const ORDER_COLUMNS: &[&str] = &["id", "region_id", "gift_name", "quantity", "created_at"];
+const REGION_COLUMNS: &[&str] = &["id", "name"];
+/// Rows fetched per query while exporting.
+const EXPORT_PAGE: usize = 500;
+/// Errors reported in an import summary, later ones are only counted.
+const MAX_IMPORT_ERRORS: usize = 100;
+
+/// CSV and NDJSON import and export of the orders and regions.
+pub fn task(state: MyState) -> Router {
+    Router::new()
+        .route("/orders/import", post(import_orders))
+        .route("/orders/export", get(export_orders))
+        .route("/regions/export", get(export_regions))
+        .with_state(state)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Csv,
+    Ndjson,
+}
+
+impl Format {
+    fn from_mime(mime: &str) -> Option<Self> {
+        // ignore parameters such as `charset`
+        match mime.split(';').next().unwrap_or_default().trim() {
+            "text/csv" => Some(Format::Csv),
+            "application/x-ndjson" | "application/ndjson" => Some(Format::Ndjson),
+            _ => None,
+        }
+    }
+
+    /// Format of a request body, given by its `Content-Type`.
+    fn of_content(headers: &HeaderMap) -> Result<Self, AppError> {
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        Self::from_mime(content_type)
+            .ok_or_else(|| AppError::UnsupportedMediaType(content_type.to_string()))
+    }
+
+    /// First supported format listed in `Accept`, CSV otherwise.
+    fn accepted(headers: &HeaderMap) -> Self {
+        headers
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|accept| accept.split(',').find_map(Self::from_mime))
+            .unwrap_or(Format::Csv)
+    }
+
+    fn mime(self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv",
+            Format::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    /// Serialize one chunk of rows, the CSV `header` is only written for the
+    /// first chunk, even when it has no rows.
+    fn encode<T: Serialize>(
+        self,
+        header: &[&str],
+        rows: &[T],
+        first: bool,
+    ) -> Result<Bytes, AppError> {
+        let mut buffer = Vec::new();
+        match self {
+            Format::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(&mut buffer);
+                if first {
+                    writer
+                        .write_record(header)
+                        .map_err(|e| AppError::Internal(e.to_string()))?;
+                }
+                for row in rows {
+                    writer
+                        .serialize(row)
+                        .map_err(|e| AppError::Internal(e.to_string()))?;
+                }
+                writer
+                    .flush()
+                    .map_err(|e| AppError::Internal(e.to_string()))?;
+            }
+            Format::Ndjson => {
+                for row in rows {
+                    serde_json::to_writer(&mut buffer, row)?;
+                    buffer.push(b'\n');
+                }
+            }
+        }
+        Ok(buffer.into())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct RowError {
+    /// 1-based number of the CSV record or NDJSON line.
+    pub row: u64,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    pub inserted: u64,
+    pub updated: u64,
+    pub rejected: u64,
+    pub errors: Vec<RowError>,
+}
+
+/// Collects parsed orders into batches and inserts them.
+struct Import<'a> {
+    store: &'a dyn GiftStore,
+    on_conflict: OnConflict,
+    batch: Vec<(u64, Order)>,
+    summary: ImportSummary,
+}
+
+impl Import<'_> {
+    fn reject(&mut self, rows: usize, row: u64, message: String) {
+        self.summary.rejected += rows as u64;
+        if self.summary.errors.len() < MAX_IMPORT_ERRORS {
+            self.summary.errors.push(RowError { row, message });
+        }
+    }
+
+    async fn push(&mut self, row: u64, order: Result<Order, String>) -> Result<(), AppError> {
+        let order = match order {
+            Ok(order) => order,
+            Err(message) => {
+                self.reject(1, row, message);
+                return Ok(());
+            }
+        };
+        if let Err(e) = validate_orders(std::slice::from_ref(&order)) {
+            self.reject(1, row, e.to_string());
+            return Ok(());
+        }
+
+        self.batch.push((row, order));
+        if self.batch.len() == IMPORT_BATCH {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Insert the pending batch. When the store refuses it, its rows are
+    /// inserted one by one instead, so that only the rows at fault are
+    /// rejected. Server side failures abort the import.
+    async fn flush(&mut self) -> Result<(), AppError> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        let batch = std::mem::take(&mut self.batch);
+        let orders = batch.iter().map(|(_, order)| order.clone()).collect();
+        match self.store.insert_orders(orders, self.on_conflict).await {
+            Ok(summary) => self.count(summary),
+            Err(e) if e.status().is_client_error() => {
+                for (row, order) in batch {
+                    match self
+                        .store
+                        .insert_orders(vec![order], self.on_conflict)
+                        .await
+                    {
+                        Ok(summary) => self.count(summary),
+                        Err(e) if e.status().is_client_error() => {
+                            self.reject(1, row, e.to_string())
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            Err(e) => return Err(e),
+        }
+        Ok(())
+    }
+
+    fn count(&mut self, summary: InsertSummary) {
+        self.summary.inserted += summary.inserted;
+        self.summary.updated += summary.updated;
+        self.summary.rejected += summary.rejected;
+    }
+}
+
+async fn import_orders(
+    State(state): State<MyState>,
+    Query(params): Query<InsertParams>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Json<ImportSummary>, AppError> {
+    let format = Format::of_content(&headers)?;
+    let reader = StreamReader::new(body.into_data_stream().map_err(io::Error::other));
+    let mut import = Import {
+        store: state.store.as_ref(),
+        on_conflict: params.on_conflict,
+        batch: Vec::with_capacity(IMPORT_BATCH),
+        summary: ImportSummary::default(),
+    };
+
+    match format {
+        Format::Csv => {
+            let mut records = pin!(csv_async::AsyncReaderBuilder::new()
+                .trim(csv_async::Trim::All)
+                .create_deserializer(reader)
+                .into_deserialize::<Order>());
+            let mut row = 0;
+            while let Some(record) = records.next().await {
+                row += 1;
+                import.push(row, record.map_err(|e| e.to_string())).await?;
+            }
+        }
+        Format::Ndjson => {
+            let mut lines = reader.lines();
+            let mut row = 0;
+            while let Some(line) = lines
+                .next_line()
+                .await
+                .map_err(|e| AppError::BadRequest(e.to_string()))?
+            {
+                row += 1;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                import
+                    .push(row, serde_json::from_str(&line).map_err(|e| e.to_string()))
+                    .await?;
+            }
+        }
+    }
+    import.flush().await?;
+
+    Ok(Json(import.summary))
+}
+
+/// Follow the cursors of `fetch` until the last page.
+fn pages<T, F, Fut>(fetch: F) -> impl Stream<Item = Result<Vec<T>, AppError>>
+where
+    F: Fn(Option<Cursor>) -> Fut,
+    Fut: Future<Output = Result<Page<T>, AppError>>,
+{
+    stream::try_unfold((fetch, Some(None)), |(fetch, cursor)| async move {
+        let cursor = match cursor {
+            Some(cursor) => cursor,
+            None => return Ok(None),
+        };
+        let page = fetch(cursor).await?;
+        Ok(Some((page.items, (fetch, page.next_cursor.map(Some)))))
+    })
+}
+
+fn export<T: Serialize>(
+    format: Format,
+    header: &'static [&'static str],
+    pages: impl Stream<Item = Result<Vec<T>, AppError>> + Send + 'static,
+) -> Response {
+    let body = pages
+        .enumerate()
+        .map(move |(index, rows)| rows.and_then(|rows| format.encode(header, &rows, index == 0)));
+
+    (
+        [(header::CONTENT_TYPE, format.mime())],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
+
+async fn export_orders(State(state): State<MyState>, headers: HeaderMap) -> Response {
+    let store = state.store;
+    let pages = pages(move |cursor| {
+        let store = store.clone();
+        async move {
+            let query = OrderQuery {
+                cursor,
+                limit: EXPORT_PAGE + 1,
+                ..Default::default()
+            };
+            let orders = store.list_orders(&query).await?;
+            Ok(Page::new(orders, EXPORT_PAGE, |order| {
+                query.sort.cursor(order)
+            }))
+        }
+    });
+
+    export(Format::accepted(&headers), ORDER_COLUMNS, pages)
+}
+
+async fn export_regions(State(state): State<MyState>, headers: HeaderMap) -> Response {
+    let store = state.store;
+    let pages = pages(move |cursor| {
+        let store = store.clone();
+        async move {
+            let query = RegionQuery {
+                cursor,
+                limit: EXPORT_PAGE + 1,
+                ..Default::default()
+            };
+            let regions = store.list_regions(&query).await?;
+            Ok(Page::new(regions, EXPORT_PAGE, |region| {
+                query.sort.cursor(region)
+            }))
+        }
+    });
+
+    export(Format::accepted(&headers), REGION_COLUMNS, pages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenge::{db::Region, error::assert_problem};
+    use axum::http::StatusCode;
+    use axum_test::TestServer;
+    use serde_json::json;
+
+    async fn seeded_state() -> MyState {
+        let state = MyState::memory();
+        let regions = vec![
+            Region {
+                id: 1,
+                name: "North Pole".to_string(),
+            },
+            Region {
+                id: 2,
+                name: "Europe".to_string(),
+            },
+        ];
+        state
+            .store
+            .insert_regions(regions, OnConflict::Fail)
+            .await
+            .unwrap();
+        state
+    }
+
+    #[tokio::test]
+    async fn import_csv() {
+        let state = seeded_state().await;
+        let app = task(state.clone());
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let csv = "id,region_id,gift_name,quantity,created_at\n\
+            1,2,Toy Train,5,2024-01-01T10:00:00Z\n\
+            2,2,Doll,0,\n\
+            3,two,Doll,1,\n\
+            4,1,Board Game,10,\n";
+        let response = server
+            .post("/orders/import")
+            .bytes(csv.into())
+            .content_type("text/csv")
+            .await;
+        response.assert_status(StatusCode::OK);
+        let summary = response.json::<ImportSummary>();
+        assert_eq!((summary.inserted, summary.rejected), (2, 2));
+        let rows = summary
+            .errors
+            .iter()
+            .map(|error| error.row)
+            .collect::<Vec<_>>();
+        assert_eq!(rows, vec![2, 3]);
+
+        assert_eq!(state.store.total_quantity().await.unwrap(), 15);
+    }
+
+    #[tokio::test]
+    async fn import_ndjson() {
+        let state = seeded_state().await;
+        let app = task(state.clone());
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let ndjson = [
+            json!({"id":1,"region_id":2,"gift_name":"Toy Train","quantity":5}).to_string(),
+            String::new(),
+            "{not json".to_string(),
+            json!({"id":2,"region_id":9,"gift_name":"Doll","quantity":3}).to_string(),
+        ]
+        .join("\n");
+        let response = server
+            .post("/orders/import")
+            .add_query_param("on_conflict", "skip")
+            .bytes(ndjson.into())
+            .content_type("application/x-ndjson")
+            .await;
+        response.assert_status(StatusCode::OK);
+        let summary = response.json::<ImportSummary>();
+        // the unknown region is only rejected by the store
+        assert_eq!((summary.inserted, summary.rejected), (1, 2));
+        assert_eq!(summary.errors[0].row, 3);
+
+        let response = server
+            .post("/orders/import")
+            .bytes("[]".into())
+            .content_type("application/json")
+            .await;
+        assert_problem(
+            &response,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_media_type",
+        );
+    }
+
+    #[tokio::test]
+    async fn import_rejects_rows_not_batches() {
+        let state = seeded_state().await;
+        let app = task(state.clone());
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let csv = "id,region_id,gift_name,quantity\n\
+            1,2,Toy Train,5\n\
+            1,1,Doll,3\n\
+            2,1,Board Game,10\n";
+        let response = server
+            .post("/orders/import")
+            .bytes(csv.into())
+            .content_type("text/csv")
+            .await;
+        response.assert_status(StatusCode::OK);
+        let summary = response.json::<ImportSummary>();
+        // only the duplicate id is refused, the rest of its batch is kept
+        assert_eq!((summary.inserted, summary.rejected), (2, 1));
+        assert_eq!(summary.errors.len(), 1);
+        assert_eq!(summary.errors[0].row, 2);
+
+        assert_eq!(state.store.total_quantity().await.unwrap(), 15);
+    }
+
+    #[tokio::test]
+    async fn export_tables() {
+        let state = seeded_state().await;
+        let orders = vec![
+            Order {
+                id: 1,
+                region_id: 2,
+                gift_name: "Toy Train".to_string(),
+                quantity: 5,
+                created_at: "2024-01-01T10:00:00Z".parse().ok(),
+            },
+            Order {
+                id: 2,
+                region_id: 1,
+                gift_name: "Doll, large".to_string(),
+                quantity: 8,
+                created_at: "2024-01-02T12:00:00Z".parse().ok(),
+            },
+        ];
+        state
+            .store
+            .insert_orders(orders, OnConflict::Fail)
+            .await
+            .unwrap();
+        let app = task(state);
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server.get("/orders/export").await;
+        response.assert_status(StatusCode::OK);
+        assert_eq!(
+            response.text(),
+            "id,region_id,gift_name,quantity,created_at\n\
+            1,2,Toy Train,5,2024-01-01T10:00:00Z\n\
+            2,1,\"Doll, large\",8,2024-01-02T12:00:00Z\n"
+        );
+
+        let response = server
+            .get("/regions/export")
+            .add_header(header::ACCEPT, "application/x-ndjson".parse().unwrap())
+            .await;
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/x-ndjson"
+        );
+        assert_eq!(
+            response.text(),
+            "{\"id\":1,\"name\":\"North Pole\"}\n{\"id\":2,\"name\":\"Europe\"}\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn export_empty_table() {
+        let app = task(MyState::memory());
+
+        // Run the application for testing.
+        let server = TestServer::new(app).unwrap();
+
+        // Send the request.
+        let response = server.get("/orders/export").await;
+        response.assert_status(StatusCode::OK);
+        response.assert_text("id,region_id,gift_name,quantity,created_at\n");
+
+        let response = server.get("/regions/export").await;
+        response.assert_text("id,name\n");
+    }
+}
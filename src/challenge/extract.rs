@@ -0,0 +1,128 @@
+//! The extractors of axum, rejecting requests with an [`AppError`] so that
+//! malformed bodies, query strings and paths get a problem+json body too.
+
+use std::ops::{Deref, DerefMut};
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, FromRequestParts, Request},
+    http::request::Parts,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use super::error::AppError;
+
+#[derive(FromRequest, Debug, Clone, Copy, Default)]
+#[from_request(via(axum::Json), rejection(AppError))]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+#[derive(FromRequestParts, Debug, Clone, Copy, Default)]
+#[from_request(via(axum::extract::Query), rejection(AppError))]
+pub struct Query<T>(pub T);
+
+#[derive(FromRequestParts, Debug, Clone, Copy)]
+#[from_request(via(axum::extract::Path), rejection(AppError))]
+pub struct Path<T>(pub T);
+
+macro_rules! deref {
+    ($($wrapper:ident),*) => {$(
+        impl<T> Deref for $wrapper<T> {
+            type Target = T;
+
+            fn deref(&self) -> &T {
+                &self.0
+            }
+        }
+
+        impl<T> DerefMut for $wrapper<T> {
+            fn deref_mut(&mut self) -> &mut T {
+                &mut self.0
+            }
+        }
+    )*};
+}
+
+deref!(Json, Query, Path);
+
+pub struct Multipart(pub axum::extract::Multipart);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequest<S> for Multipart {
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(
+            axum::extract::Multipart::from_request(req, state).await?,
+        ))
+    }
+}
+
+pub struct WebSocketUpgrade(pub axum::extract::WebSocketUpgrade);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for WebSocketUpgrade {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(
+            axum::extract::WebSocketUpgrade::from_request_parts(parts, state).await?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        http::StatusCode,
+        routing::{get, post},
+        Router,
+    };
+    use axum_test::TestServer;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::challenge::error::assert_problem;
+
+    #[derive(Deserialize)]
+    struct Page {
+        #[allow(dead_code)]
+        limit: usize,
+    }
+
+    #[tokio::test]
+    async fn rejections() {
+        let app = Router::new()
+            .route(
+                "/json",
+                post(|Json(n): Json<i32>| async move { n.to_string() }),
+            )
+            .route("/query", get(|_: Query<Page>| async {}))
+            .route(
+                "/path/:id",
+                get(|Path(id): Path<i32>| async move { id.to_string() }),
+            );
+        let server = TestServer::new(app).unwrap();
+
+        let response = server.post("/json").text("1").await;
+        assert_problem(
+            &response,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_media_type",
+        );
+        let response = server.post("/json").json(&"one").await;
+        assert_problem(&response, StatusCode::UNPROCESSABLE_ENTITY, "invalid_json");
+        let response = server.get("/query").add_query_param("limit", "all").await;
+        assert_problem(&response, StatusCode::BAD_REQUEST, "invalid_query");
+        let response = server.get("/path/one").await;
+        assert_problem(&response, StatusCode::BAD_REQUEST, "invalid_path");
+
+        server.get("/path/1").await.assert_text("1");
+    }
+}
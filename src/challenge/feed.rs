@@ -0,0 +1,193 @@
+use std::{pin::pin, sync::Arc};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        State,
+    },
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    routing::get,
+    Router,
+};
+use futures::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    db::{GiftStore, MyState, RegionTotal},
+    error::AppError,
+    extract::WebSocketUpgrade,
+    ws::{Heartbeat, Sockets},
+};
+
+#[derive(Clone)]
+struct FeedState {
+    store: Arc<dyn GiftStore>,
+    sockets: Sockets,
+}
+
+/// Live aggregates of the orders, pushed whenever they change.
+pub fn task(state: MyState, sockets: Sockets) -> Router {
+    Router::new()
+        .route("/feed", get(sse_feed))
+        .route("/feed/ws", get(ws_feed))
+        .with_state(FeedState {
+            store: state.store,
+            sockets,
+        })
+}
+
+/// The aggregates of days 13 and 18 at one point in time.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Snapshot {
+    pub total: i64,
+    pub popular: Option<String>,
+    pub regions: Vec<RegionTotal>,
+}
+
+impl Snapshot {
+    async fn load(store: &dyn GiftStore) -> Result<Self, AppError> {
+        Ok(Self {
+            total: store.total_quantity().await?,
+            popular: store.popular_gift().await?,
+            regions: store.region_totals().await?,
+        })
+    }
+}
+
+/// The current snapshot, then a new one after every change. Changes made
+/// while a snapshot is loaded are folded into the next one.
+fn snapshots(store: Arc<dyn GiftStore>) -> impl Stream<Item = Result<Snapshot, AppError>> {
+    let changes = store.subscribe();
+    stream::unfold(
+        (store, changes, true),
+        |(store, mut changes, first)| async move {
+            if !first && changes.changed().await.is_err() {
+                return None;
+            }
+            let snapshot = Snapshot::load(store.as_ref()).await;
+            Some((snapshot, (store, changes, false)))
+        },
+    )
+}
+
+async fn sse_feed(
+    State(state): State<FeedState>,
+) -> Sse<impl Stream<Item = Result<Event, AppError>>> {
+    let events = snapshots(state.store).map(|snapshot| {
+        Event::default()
+            .event("snapshot")
+            .json_data(snapshot?)
+            .map_err(|e| AppError::Internal(e.to_string()))
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+async fn ws_feed(
+    WebSocketUpgrade(ws): WebSocketUpgrade,
+    State(FeedState { store, sockets }): State<FeedState>,
+) -> impl IntoResponse {
+    sockets
+        .limit(ws)
+        .on_upgrade(move |socket| push_snapshots(socket, store, sockets.open()))
+}
+
+async fn push_snapshots(socket: WebSocket, store: Arc<dyn GiftStore>, mut heartbeat: Heartbeat) {
+    let (mut sender, mut receiver) = socket.split();
+    let mut snapshots = pin!(snapshots(store));
+
+    loop {
+        tokio::select! {
+            message = heartbeat.beat() => {
+                let close = matches!(message, Message::Close(_));
+                if heartbeat.send(&mut sender, message).await.is_err() || close {
+                    break;
+                }
+            }
+            snapshot = snapshots.next() => {
+                let snapshot = match snapshot {
+                    Some(Ok(snapshot)) => snapshot,
+                    Some(Err(e)) => {
+                        tracing::error!("failed to load feed snapshot: {e}");
+                        break;
+                    }
+                    None => break,
+                };
+                let text = serde_json::to_string(&snapshot).unwrap();
+                if heartbeat.send(&mut sender, Message::Text(text)).await.is_err() {
+                    break;
+                }
+            }
+            message = receiver.next() => match message {
+                // the feed only goes one way, anything else from the client is ignored
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => heartbeat.seen().touch(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenge::db::{OnConflict, Order, Region};
+
+    #[tokio::test]
+    async fn snapshot_after_change() {
+        let state = MyState::memory();
+        let mut snapshots = pin!(snapshots(state.store.clone()));
+
+        let snapshot = snapshots.next().await.unwrap().unwrap();
+        assert_eq!(
+            snapshot,
+            Snapshot {
+                total: 0,
+                popular: None,
+                regions: vec![],
+            }
+        );
+
+        // both writes land before the stream is polled again
+        let region = Region {
+            id: 1,
+            name: "Europe".to_string(),
+        };
+        let order = Order {
+            id: 1,
+            region_id: 1,
+            gift_name: "Doll".to_string(),
+            quantity: 8,
+            created_at: None,
+        };
+        state
+            .store
+            .insert_regions(vec![region], OnConflict::Fail)
+            .await
+            .unwrap();
+        state
+            .store
+            .insert_orders(vec![order], OnConflict::Fail)
+            .await
+            .unwrap();
+
+        let snapshot = snapshots.next().await.unwrap().unwrap();
+        assert_eq!(
+            snapshot,
+            Snapshot {
+                total: 8,
+                popular: Some("Doll".to_string()),
+                regions: vec![RegionTotal {
+                    region: "Europe".to_string(),
+                    total: 8,
+                }],
+            }
+        );
+
+        state.store.reset().await.unwrap();
+        let snapshot = snapshots.next().await.unwrap().unwrap();
+        assert_eq!(snapshot.total, 0);
+    }
+}
@@ -1,3 +1,4 @@
+pub mod api;
 pub mod day1;
 pub mod day11;
 pub mod day12;
@@ -16,3 +17,8 @@ pub mod day7;
 pub mod day8;
 pub mod day_1;
 pub mod db;
+pub mod error;
+pub mod exchange;
+pub mod extract;
+pub mod feed;
+pub mod ws;
@@ -0,0 +1,232 @@
+//! Heartbeat, limits and shutdown shared by the WebSocket handlers.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::extract::{
+    ws::{CloseFrame, Message},
+    WebSocketUpgrade,
+};
+use futures::{Sink, SinkExt};
+use tokio::{
+    sync::RwLock,
+    time::{interval_at, timeout, Instant, Interval, MissedTickBehavior},
+};
+use tokio_util::{
+    sync::CancellationToken,
+    task::{task_tracker::TaskTrackerToken, TaskTracker},
+};
+
+use crate::config::WebSocketConfig;
+
+/// How long stopping the server waits for the sockets to close.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// The limits of every socket and the signal closing them all.
+#[derive(Clone, Debug)]
+pub struct Sockets {
+    ping_interval: Duration,
+    idle_timeout: Duration,
+    send_timeout: Duration,
+    max_frame_size: usize,
+    max_message_size: usize,
+    shutdown: CancellationToken,
+    open: TaskTracker,
+}
+
+impl Default for Sockets {
+    fn default() -> Self {
+        Self::new(&WebSocketConfig::default())
+    }
+}
+
+impl Sockets {
+    pub fn new(config: &WebSocketConfig) -> Self {
+        Self {
+            ping_interval: Duration::from_secs(config.ping_interval_secs),
+            idle_timeout: Duration::from_secs(config.idle_timeout_secs),
+            send_timeout: Duration::from_secs(config.send_timeout_secs),
+            max_frame_size: config.max_frame_size,
+            max_message_size: config.max_message_size,
+            shutdown: CancellationToken::new(),
+            open: TaskTracker::new(),
+        }
+    }
+
+    /// Apply the size limits to an upgrade.
+    pub fn limit(&self, ws: WebSocketUpgrade) -> WebSocketUpgrade {
+        ws.max_frame_size(self.max_frame_size)
+            .max_message_size(self.max_message_size)
+    }
+
+    /// Start the heartbeat of a socket that was just upgraded. The socket
+    /// counts as open until the heartbeat is dropped.
+    pub fn open(&self) -> Heartbeat {
+        let mut ping = interval_at(Instant::now() + self.ping_interval, self.ping_interval);
+        ping.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Heartbeat {
+            ping,
+            idle_timeout: self.idle_timeout,
+            send_timeout: self.send_timeout,
+            seen: Seen(Arc::new(Mutex::new(Instant::now()))),
+            shutdown: self.shutdown.clone(),
+            _open: self.open.token(),
+        }
+    }
+
+    /// Have every socket send a close frame, without waiting for them.
+    pub fn close(&self) {
+        self.shutdown.cancel();
+        self.open.close();
+    }
+
+    /// Have every socket send a close frame, then wait a little for them
+    /// to be closed.
+    pub async fn shutdown(&self) {
+        self.close();
+        if timeout(SHUTDOWN_GRACE, self.open.wait()).await.is_err() {
+            tracing::warn!("{} sockets still open after shutdown", self.open.len());
+        }
+    }
+}
+
+/// When a client last sent a frame.
+#[derive(Clone, Debug)]
+pub struct Seen(Arc<Mutex<Instant>>);
+
+impl Seen {
+    /// Record a frame of the client.
+    pub fn touch(&self) {
+        *self.0.lock().unwrap() = Instant::now();
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.0.lock().unwrap().elapsed()
+    }
+}
+
+/// Keeps a socket alive: pings the client, and closes the socket once the
+/// client went quiet or the server stops.
+#[derive(Debug)]
+pub struct Heartbeat {
+    ping: Interval,
+    idle_timeout: Duration,
+    send_timeout: Duration,
+    seen: Seen,
+    shutdown: CancellationToken,
+    _open: TaskTrackerToken,
+}
+
+impl Heartbeat {
+    /// A handle for the half of the socket that receives frames.
+    pub fn seen(&self) -> Seen {
+        self.seen.clone()
+    }
+
+    /// Wait for the next frame to send: a ping, or the close frame ending
+    /// the socket. The client is checked for idleness at every ping.
+    pub async fn beat(&mut self) -> Message {
+        let close = |code, reason: &'static str| {
+            Message::Close(Some(CloseFrame {
+                code,
+                reason: reason.into(),
+            }))
+        };
+        tokio::select! {
+            _ = self.shutdown.cancelled() => close(1001, "server is shutting down"),
+            _ = self.ping.tick() => {
+                if self.seen.elapsed() >= self.idle_timeout {
+                    close(1000, "idle timeout")
+                } else {
+                    Message::Ping(Vec::new())
+                }
+            }
+        }
+    }
+
+    /// Send `message`, failing when the client takes too long to accept it.
+    pub async fn send<S>(&self, sender: &mut S, message: Message) -> Result<(), axum::Error>
+    where
+        S: Sink<Message, Error = axum::Error> + Unpin,
+    {
+        timeout(self.send_timeout, sender.send(message))
+            .await
+            .map_err(|_| axum::Error::new("client too slow to accept frames"))?
+    }
+
+    /// Ping the client until the socket is to be closed, then close it. For
+    /// handlers whose sending half is shared.
+    pub async fn keep_alive<S>(mut self, sender: Arc<RwLock<S>>)
+    where
+        S: Sink<Message, Error = axum::Error> + Unpin,
+    {
+        loop {
+            let message = self.beat().await;
+            let close = matches!(message, Message::Close(_));
+            let sent = self.send(&mut *sender.write().await, message).await;
+            if sent.is_err() || close {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sockets() -> Sockets {
+        Sockets {
+            ping_interval: Duration::from_millis(20),
+            idle_timeout: Duration::from_millis(50),
+            ..Sockets::default()
+        }
+    }
+
+    fn close_code(message: Message) -> Option<u16> {
+        match message {
+            Message::Close(Some(frame)) => Some(frame.code),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn idle_client() {
+        let mut heartbeat = sockets().open();
+        let seen = heartbeat.seen();
+
+        // a client answering pings stays
+        for _ in 0..5 {
+            assert!(matches!(heartbeat.beat().await, Message::Ping(_)));
+            seen.touch();
+        }
+
+        let mut pings = 0;
+        let close = loop {
+            match heartbeat.beat().await {
+                Message::Ping(_) => pings += 1,
+                message => break message,
+            }
+        };
+        assert!(pings <= 2);
+        assert_eq!(close_code(close), Some(1000));
+    }
+
+    #[tokio::test]
+    async fn shutdown() {
+        let sockets = Sockets {
+            ping_interval: Duration::from_secs(60),
+            ..Sockets::default()
+        };
+        let mut heartbeat = sockets.open();
+        let socket = tokio::spawn(async move { close_code(heartbeat.beat().await) });
+
+        // returns once the socket is closed
+        timeout(Duration::from_secs(1), sockets.shutdown())
+            .await
+            .unwrap();
+        assert_eq!(socket.await.unwrap(), Some(1001));
+    }
+}
@@ -0,0 +1,385 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde::Deserialize;
+
+/// Settings of the standalone server, read from a TOML file and overridden by
+/// `CCH23_*` environment variables.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: SocketAddr,
+    pub store: StoreBackend,
+    pub database_url: Option<String>,
+    pub assets_dir: PathBuf,
+    /// Enable flag per day, keyed by the day's path segment (e.g. `13`, `-1`,
+    /// or `api` for the REST API).
+    /// Days that are not listed are enabled.
+    pub days: HashMap<String, bool>,
+    pub chat: ChatConfig,
+    pub websocket: WebSocketConfig,
+    pub archive: ArchiveConfig,
+}
+
+/// Limits of the day 19 chat rooms.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChatConfig {
+    /// Longest message in bytes.
+    pub max_message_len: usize,
+    /// Messages a user may send to a room per `rate_window_secs`, unlimited
+    /// when unset.
+    pub rate_limit: Option<usize>,
+    pub rate_window_secs: u64,
+    /// Regexes, with the syntax of day 15, a message must not match.
+    pub blocked_patterns: Vec<String>,
+    /// Key signing the tokens of `/19/login`; instances sharing rooms need
+    /// the same one. Random when unset.
+    pub secret: Option<String>,
+    pub token_ttl_secs: u64,
+    /// Refuse connections without a token, unless unset to trust the user
+    /// of the path.
+    pub require_token: bool,
+    /// Messages kept in the history of a room, older ones are deleted.
+    pub history_limit: usize,
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            max_message_len: 128,
+            rate_limit: None,
+            rate_window_secs: 10,
+            blocked_patterns: Vec::new(),
+            secret: None,
+            token_ttl_secs: 24 * 60 * 60,
+            require_token: true,
+            history_limit: 10_000,
+        }
+    }
+}
+
+/// Heartbeat and limits of every WebSocket.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebSocketConfig {
+    /// Seconds between the pings sent to every client.
+    pub ping_interval_secs: u64,
+    /// Seconds without any frame from a client, pongs included, before it is
+    /// disconnected.
+    pub idle_timeout_secs: u64,
+    /// Seconds a client may take to accept a frame before it is disconnected
+    /// as too slow.
+    pub send_timeout_secs: u64,
+    /// Largest frame a client may send, in bytes.
+    pub max_frame_size: usize,
+    /// Largest message a client may send, in bytes, once its frames are
+    /// joined.
+    pub max_message_size: usize,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval_secs: 30,
+            idle_timeout_secs: 75,
+            send_timeout_secs: 10,
+            max_frame_size: 64 << 10,
+            max_message_size: 64 << 10,
+        }
+    }
+}
+
+/// Limits of the day 20 archive endpoints.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArchiveConfig {
+    /// Largest archive in bytes. Archives are streamed, so this bounds the
+    /// upload rather than the memory used.
+    pub max_body_size: u64,
+    /// Most entries an archive may have.
+    pub max_entries: usize,
+    /// Largest total size in bytes of the entries once unpacked.
+    pub max_unpacked_size: u64,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            max_body_size: 1 << 30,
+            max_entries: 10_000,
+            max_unpacked_size: 4 << 30,
+        }
+    }
+}
+
+/// Where the gift orders and regions are stored.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreBackend {
+    #[default]
+    Postgres,
+    Memory,
+}
+
+impl FromStr for StoreBackend {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "postgres" => Ok(Self::Postgres),
+            "memory" => Ok(Self::Memory),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to read {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to parse {}: {source}", path.display())]
+    Toml {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("invalid value for {name}: {value}")]
+    Env { name: &'static str, value: String },
+    #[error("invalid {name}: {reason}")]
+    Setting {
+        name: &'static str,
+        reason: &'static str,
+    },
+    #[error("invalid chat pattern `{pattern}`: {source}")]
+    Pattern {
+        pattern: String,
+        source: Box<fancy_regex::Error>,
+    },
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: SocketAddr::from(([0, 0, 0, 0], 8000)),
+            store: StoreBackend::default(),
+            database_url: None,
+            assets_dir: PathBuf::from("assets"),
+            days: HashMap::new(),
+            chat: ChatConfig::default(),
+            websocket: WebSocketConfig::default(),
+            archive: ArchiveConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Load the configuration file (if any) and apply environment overrides.
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let mut config = match path {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        config.apply_env(|name| std::env::var(name).ok())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the settings that can't be checked while parsing.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.websocket.ping_interval_secs == 0 {
+            return Err(ConfigError::Setting {
+                name: "websocket.ping_interval_secs",
+                reason: "must be at least 1",
+            });
+        }
+        if self.chat.history_limit == 0 {
+            return Err(ConfigError::Setting {
+                name: "chat.history_limit",
+                reason: "must be at least 1",
+            });
+        }
+        for pattern in &self.chat.blocked_patterns {
+            fancy_regex::Regex::new(pattern).map_err(|source| ConfigError::Pattern {
+                pattern: pattern.clone(),
+                source: Box::new(source),
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let text = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        toml::from_str(&text).map_err(|source| ConfigError::Toml {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Override settings with `CCH23_LISTEN`, `CCH23_STORE`, `DATABASE_URL`,
+    /// `CCH23_ASSETS_DIR`, `CCH23_DISABLED_DAYS` (a comma separated list of days)
+    /// and `CCH23_CHAT_SECRET`.
+    pub fn apply_env(
+        &mut self,
+        var: impl Fn(&'static str) -> Option<String>,
+    ) -> Result<(), ConfigError> {
+        if let Some(listen) = var("CCH23_LISTEN") {
+            self.listen = listen.parse().map_err(|_| ConfigError::Env {
+                name: "CCH23_LISTEN",
+                value: listen,
+            })?;
+        }
+        if let Some(store) = var("CCH23_STORE") {
+            self.store = store.parse().map_err(|_| ConfigError::Env {
+                name: "CCH23_STORE",
+                value: store,
+            })?;
+        }
+        if let Some(database_url) = var("DATABASE_URL") {
+            self.database_url = Some(database_url);
+        }
+        if let Some(assets_dir) = var("CCH23_ASSETS_DIR") {
+            self.assets_dir = PathBuf::from(assets_dir);
+        }
+        if let Some(days) = var("CCH23_DISABLED_DAYS") {
+            for day in days.split(',').map(str::trim).filter(|day| !day.is_empty()) {
+                self.days.insert(day.to_string(), false);
+            }
+        }
+        if let Some(secret) = var("CCH23_CHAT_SECRET") {
+            self.chat.secret = Some(secret);
+        }
+        Ok(())
+    }
+
+    pub fn day_enabled(&self, day: &str) -> bool {
+        self.days.get(day).copied().unwrap_or(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_file_with_env_overrides() {
+        let mut config: Config = toml::from_str(
+            r#"
+            listen = "127.0.0.1:3000"
+            store = "memory"
+            database_url = "postgres://localhost/cch23"
+
+            [days]
+            13 = false
+            "-1" = true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.listen, SocketAddr::from(([127, 0, 0, 1], 3000)));
+        assert_eq!(config.store, StoreBackend::Memory);
+        assert_eq!(config.assets_dir, PathBuf::from("assets"));
+        assert!(!config.day_enabled("13"));
+        assert!(config.day_enabled("-1"));
+        assert!(config.day_enabled("18"));
+
+        config
+            .apply_env(|name| match name {
+                "CCH23_LISTEN" => Some("0.0.0.0:9000".to_string()),
+                "CCH23_STORE" => Some("postgres".to_string()),
+                "CCH23_DISABLED_DAYS" => Some("18, 19".to_string()),
+                _ => None,
+            })
+            .unwrap();
+
+        assert_eq!(config.listen, SocketAddr::from(([0, 0, 0, 0], 9000)));
+        assert_eq!(config.store, StoreBackend::Postgres);
+        assert_eq!(
+            config.database_url.as_deref(),
+            Some("postgres://localhost/cch23")
+        );
+        assert!(!config.day_enabled("18"));
+        assert!(!config.day_enabled("19"));
+    }
+
+    #[test]
+    fn invalid_chat_pattern() {
+        let config: Config = toml::from_str(
+            r#"
+            [chat]
+            rate_limit = 5
+            blocked_patterns = ["(unclosed"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.chat.rate_limit, Some(5));
+        assert_eq!(config.chat.max_message_len, 128);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Pattern { .. })
+        ));
+    }
+
+    #[test]
+    fn websocket_settings() {
+        let config: Config = toml::from_str(
+            r#"
+            [websocket]
+            idle_timeout_secs = 120
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.websocket.idle_timeout_secs, 120);
+        assert_eq!(config.websocket.ping_interval_secs, 30);
+        assert!(config.validate().is_ok());
+
+        let config: Config = toml::from_str("websocket = { ping_interval_secs = 0 }").unwrap();
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::Setting {
+                name: "websocket.ping_interval_secs",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn unknown_keys() {
+        assert!(toml::from_str::<Config>("listn = \"127.0.0.1:3000\"").is_err());
+        assert!(toml::from_str::<Config>("[chat]\nhistory_limt = 10").is_err());
+    }
+
+    #[test]
+    fn example_config() {
+        let config: Config = toml::from_str(include_str!("../cch23.example.toml")).unwrap();
+        assert!(config.days.values().all(|enabled| *enabled));
+        assert!(config.chat.require_token);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_listen_address() {
+        let error = Config::default()
+            .apply_env(|name| (name == "CCH23_LISTEN").then(|| "nowhere".to_string()))
+            .unwrap_err();
+
+        assert!(matches!(
+            error,
+            ConfigError::Env {
+                name: "CCH23_LISTEN",
+                ..
+            }
+        ));
+    }
+}
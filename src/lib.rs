@@ -0,0 +1,92 @@
+use axum::Router;
+
+pub mod challenge;
+pub mod config;
+
+use challenge::{db::MyState, ws::Sockets};
+use config::Config;
+
+/// Compose the routers of every enabled day and of the REST API. `sockets`
+/// closes their WebSockets when the server stops.
+pub fn router(state: MyState, sockets: Sockets, config: &Config) -> Router {
+    let days = Days {
+        router: Router::new(),
+        config,
+    };
+
+    days.nest("1", challenge::day1::task)
+        .nest("4", challenge::day4::task)
+        .nest("5", challenge::day5::task)
+        .nest("6", challenge::day6::task)
+        .nest("7", challenge::day7::task)
+        .nest("8", challenge::day8::task)
+        .nest("11", || challenge::day11::task(&config.assets_dir))
+        .nest("12", challenge::day12::task)
+        .nest("13", || challenge::day13::task(state.clone()))
+        .nest("14", challenge::day14::task)
+        .nest("15", challenge::day15::task)
+        .nest("18", || challenge::day18::task(state.clone()))
+        .nest("19", || {
+            challenge::day19::task(state.clone(), sockets.clone(), &config.chat)
+        })
+        .nest("20", || challenge::day20::task(&config.archive))
+        .nest("21", challenge::day21::task)
+        .nest("22", challenge::day22::task)
+        .nest("-1", challenge::day_1::task)
+        .nest("api", || {
+            challenge::api::task(state.clone(), sockets.clone())
+        })
+        .router
+}
+
+/// Resolve on SIGTERM or Ctrl+C.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+
+    tracing::info!("shutting down");
+}
+
+struct Days<'a> {
+    router: Router,
+    config: &'a Config,
+}
+
+impl Days<'_> {
+    fn nest(self, day: &str, task: impl FnOnce() -> Router) -> Self {
+        if !self.config.day_enabled(day) {
+            tracing::info!("/{day} is disabled");
+            return self;
+        }
+
+        // day -1 lives at the root
+        let path = if day == "-1" {
+            "/".to_string()
+        } else {
+            format!("/{day}")
+        };
+        Self {
+            router: self.router.nest(&path, task()),
+            config: self.config,
+        }
+    }
+}
@@ -1,36 +1,64 @@
+use std::net::SocketAddr;
+
 use axum::Router;
+use cch23_wolfboyyang::{
+    challenge::{self, ws::Sockets},
+    config::Config,
+    router, shutdown_signal,
+};
 use shuttle_runtime::CustomError;
 use sqlx::PgPool;
-
-mod challenge;
+use tokio::net::TcpListener;
 
 #[shuttle_runtime::main]
-async fn main(#[shuttle_shared_db::Postgres] pool: PgPool) -> shuttle_axum::ShuttleAxum {
+async fn main(
+    #[shuttle_shared_db::Postgres] pool: PgPool,
+) -> Result<Service, shuttle_runtime::Error> {
     sqlx::migrate!()
         .run(&pool)
         .await
         .map_err(CustomError::new)?;
 
-    let state = challenge::db::MyState { pool };
-
-    let router = Router::new()
-        .nest("/1", challenge::day1::task())
-        .nest("/4", challenge::day4::task())
-        .nest("/5", challenge::day5::task())
-        .nest("/6", challenge::day6::task())
-        .nest("/7", challenge::day7::task())
-        .nest("/8", challenge::day8::task())
-        .nest("/11", challenge::day11::task())
-        .nest("/12", challenge::day12::task())
-        .nest("/13", challenge::day13::task(state.clone()))
-        .nest("/14", challenge::day14::task())
-        .nest("/15", challenge::day15::task())
-        .nest("/18", challenge::day18::task(state))
-        .nest("/19", challenge::day19::task())
-        .nest("/20", challenge::day20::task())
-        .nest("/21", challenge::day21::task())
-        .nest("/22", challenge::day22::task())
-        .nest("/", challenge::day_1::task());
-
-    Ok(router.into())
+    let state = challenge::db::MyState::postgres(pool);
+    let config = Config::default();
+    let sockets = Sockets::new(&config.websocket);
+
+    let router = router(state, sockets.clone(), &config);
+
+    Ok(Service { router, sockets })
+}
+
+/// The router, closing its WebSockets when the service stops.
+struct Service {
+    router: Router,
+    sockets: Sockets,
+}
+
+#[shuttle_runtime::async_trait]
+impl shuttle_runtime::Service for Service {
+    async fn bind(self, addr: SocketAddr) -> Result<(), shuttle_runtime::Error> {
+        // Shuttle stops a deployment by aborting this task, which drops the guard
+        let _close = CloseOnDrop(self.sockets.clone());
+        let listener = TcpListener::bind(addr).await.map_err(CustomError::new)?;
+        let sockets = self.sockets;
+        axum::serve(listener, self.router)
+            .with_graceful_shutdown(async move {
+                shutdown_signal().await;
+                // upgraded connections are not waited for, so close them first
+                sockets.shutdown().await;
+            })
+            .await
+            .map_err(CustomError::new)?;
+
+        Ok(())
+    }
+}
+
+/// Has every socket send a close frame once dropped.
+struct CloseOnDrop(Sockets);
+
+impl Drop for CloseOnDrop {
+    fn drop(&mut self) {
+        self.0.close();
+    }
 }